@@ -0,0 +1,48 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod merkle_mountain_ranges;
+#[cfg(feature = "std")]
+pub mod merkle_tree;
+#[cfg(feature = "std")]
+pub mod sparse_merkle_tree;
+
+/// Without the `std` feature only proof verification is built: the hashers,
+/// `SmtProof` with its encodings and bundles, and `SmtVerifier`.
+#[cfg(not(feature = "std"))]
+pub mod sparse_merkle_tree {
+    mod bundle;
+    mod evm;
+    mod proof;
+    mod verifier;
+
+    pub use bundle::SmtProofBundle;
+    pub use evm::{verify_evm_encoding, EvmProof};
+    pub use proof::{
+        BitOrder, Keccak256Hasher, ProofHexError, ProofStats, Sha256Hasher, SmtError, SmtFlavor,
+        SmtHasher, SmtProof,
+    };
+    pub use verifier::SmtVerifier;
+}
+
+#[cfg(feature = "std")]
+pub use merkle_tree::{ConsistencyProof, MerkleError, MerkleProof, MerkleTree, PaddingPolicy};
+#[cfg(feature = "async")]
+pub use sparse_merkle_tree::AsyncNodeStore;
+#[cfg(feature = "poseidon")]
+pub use sparse_merkle_tree::PoseidonHasher;
+#[cfg(feature = "std")]
+pub use sparse_merkle_tree::{
+    key_to_path, path_to_key, CheckpointId, CompressedProof, ConflictPolicy, FileStore,
+    IntegrityError, InternedStore, MemoryStore, MergeConflict, NodeStore, NonInclusionWitness,
+    PartialSmt, ShardedSmt, ShardedSparseMerkleTree, SharedSmt, SharedSparseMerkleTree, Smt,
+    SmtDiff, SmtMetrics, SmtMultiProof, SmtNonInclusionProof, SmtOp, SmtOperation,
+    SmtTransitionProof, SmtWitness, SparseMerkleTree, SparseMerkleTree256, TreeStats, TrieStore,
+    TypedSmt, TypedSparseMerkleTree, VersionedSmt, VersionedSparseMerkleTree,
+};
+pub use sparse_merkle_tree::{
+    BitOrder, EvmProof, Keccak256Hasher, ProofHexError, ProofStats, Sha256Hasher, SmtError,
+    SmtFlavor, SmtHasher, SmtProof, SmtProofBundle, SmtVerifier,
+};
@@ -1,4 +1 @@
-pub mod merkle_mountain_ranges;
-pub mod merkle_tree;
-pub mod sparse_merkle_tree;
 fn main() {}
@@ -1,73 +1,549 @@
+use crate::sparse_merkle_tree::{decode_hex, ProofHexError};
 use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::fmt;
+use std::marker::PhantomData;
 
-struct MerkleTree {
-    root: Vec<u8>,
-    leaves: Vec<Vec<u8>>,
+/// How a `MerkleTree` hashes its leaves and the pairs of nodes above them.
+/// `Prefixed` relies on `hash_leaf` being the plain digest of its input.
+pub trait Hasher {
+    fn hash_leaf(leaf: &[u8]) -> [u8; 32];
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32];
+}
+
+/// `sha256(leaf)` and `sha256(left || right)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MerkleSha256Hasher;
+
+impl Hasher for MerkleSha256Hasher {
+    fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+        Sha256::digest(leaf).into()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        Sha256::new_with_prefix(left)
+            .chain_update(right)
+            .finalize()
+            .into()
+    }
+}
+
+/// `keccak256(leaf)` and `keccak256(left || right)`, as Solidity's
+/// `keccak256(abi.encodePacked(..))` computes them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MerkleKeccak256Hasher;
+
+impl Hasher for MerkleKeccak256Hasher {
+    fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+        Keccak256::digest(leaf).into()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        Keccak256::new_with_prefix(left)
+            .chain_update(right)
+            .finalize()
+            .into()
+    }
+}
+
+/// `H` with domain separation: leaves hashed as `H(0x00 || leaf)` and pairs as
+/// `H(0x01 || left || right)`, with `H::hash_leaf` as `H`. Without it, a 64-byte
+/// leaf made of two child hashes hashes to their parent, so the proof of that
+/// parent passes for the proof of a leaf. `Prefixed<MerkleSha256Hasher>` is the RFC
+/// 6962 (Certificate Transparency) hashing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Prefixed<H = MerkleSha256Hasher>(PhantomData<H>);
+
+impl<H: Hasher> Hasher for Prefixed<H> {
+    fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+        H::hash_leaf(&[&[0x00], leaf].concat())
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut node = [0x01; 65];
+        node[1..33].copy_from_slice(left);
+        node[33..].copy_from_slice(right);
+        H::hash_leaf(&node)
+    }
+}
+
+/// `sha256(sha256(leaf))` and `sha256(sha256(left || right))`, Bitcoin's
+/// double SHA-256: a serialized transaction hashes to its txid.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitcoinHasher;
+
+impl Hasher for BitcoinHasher {
+    fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+        Sha256::digest(Sha256::digest(leaf)).into()
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        Sha256::digest(Sha256::new_with_prefix(left).chain_update(right).finalize()).into()
+    }
+}
+
+/// The hashing of RFC 6962 (Certificate Transparency), see `MerkleTree::rfc6962`.
+pub type Rfc6962Hasher = Prefixed<MerkleSha256Hasher>;
+
+/// What happens to the last node of a level with an odd number of nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// It moves up a level unchanged, as in Certificate Transparency style trees.
+    #[default]
+    Promote,
+    /// It's paired with itself, as in Bitcoin.
+    DuplicateLast,
+    /// It's paired with 32 zero bytes.
+    PadWithZero,
+}
+
+impl PaddingPolicy {
+    /// The sibling `node` gets when it's last on an odd level, if any.
+    fn odd_sibling(self, node: &[u8; 32]) -> Option<[u8; 32]> {
+        match self {
+            Self::Promote => None,
+            Self::DuplicateLast => Some(*node),
+            Self::PadWithZero => Some([0; 32]),
+        }
+    }
+}
+
+/// Why an operation on a `MerkleTree` failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MerkleError {
+    /// A leaf index past the last of `len` leaves.
+    IndexOutOfRange { index: usize, len: usize },
+    /// A string that isn't hex, see `MerkleProof::from_hex`.
+    InvalidHex(ProofHexError),
+    /// Bytes that don't encode a `MerkleProof`.
+    InvalidProofEncoding,
+    /// A hash of this many bytes rather than 32.
+    InvalidHashLength(usize),
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IndexOutOfRange { index, len } => {
+                write!(f, "leaf index {index} is out of range for {len} leaves")
+            }
+            Self::InvalidHex(err) => write!(f, "invalid hex: {err}"),
+            Self::InvalidProofEncoding => write!(f, "bytes don't encode a proof"),
+            Self::InvalidHashLength(len) => write!(f, "a hash of {len} bytes, not 32"),
+        }
+    }
+}
+
+impl std::error::Error for MerkleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidHex(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Proof that one tree's leaves start with all of another's, see
+/// `MerkleTree::generate_consistency_proof`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConsistencyProof {
+    pub hashes: Vec<[u8; 32]>,
+}
+
+/// A leaf's proof: its siblings from the leaf up, whether the path node is on the
+/// left of each, and the leaf's index. See `MerkleTree::generate_proof`. Typed by
+/// the hasher of the tree it's for, so `MerkleTree::<H>::verify_proof` only takes
+/// proofs made with `H`; `from_hex` and serde give a proof the hasher the caller
+/// names, as neither encoding records it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+pub struct MerkleProof<H = MerkleSha256Hasher> {
+    pub siblings: Vec<[u8; 32]>,
+    /// One per sibling, `true` where the path node is the left child.
+    pub directions: Vec<bool>,
+    pub leaf_index: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+// By hand, as derives would require `H` itself to implement them.
+impl<H> fmt::Debug for MerkleProof<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MerkleProof")
+            .field("siblings", &self.siblings)
+            .field("directions", &self.directions)
+            .field("leaf_index", &self.leaf_index)
+            .finish()
+    }
+}
+
+impl<H> Clone for MerkleProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            siblings: self.siblings.clone(),
+            directions: self.directions.clone(),
+            leaf_index: self.leaf_index,
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H> PartialEq for MerkleProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.siblings == other.siblings
+            && self.directions == other.directions
+            && self.leaf_index == other.leaf_index
+    }
+}
+
+impl<H> Eq for MerkleProof<H> {}
+
+impl<H> MerkleProof<H> {
+    /// A proof from the `(sibling, path node is on the left)` pairs proofs used to
+    /// be, for leaf `leaf_index`.
+    pub fn from_pairs(pairs: &[([u8; 32], bool)], leaf_index: usize) -> Self {
+        Self {
+            siblings: pairs.iter().map(|&(sibling, _)| sibling).collect(),
+            directions: pairs.iter().map(|&(_, is_left)| is_left).collect(),
+            leaf_index,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Whether the directions fit `leaf_index`, whose bits from the lowest are 0
+    /// where the path node is on the left and 1 where it's on the right. A node
+    /// promoted past an odd level gets no sibling there and is the last of its
+    /// level from then on, so such levels are 0 bits just before a turn to the
+    /// right or past the last sibling. The index is thus checked only as far as
+    /// the directions pin it down.
+    fn directions_match_index(&self) -> bool {
+        let mut index = self.leaf_index;
+        for &is_left in &self.directions {
+            if !is_left {
+                index >>= index.trailing_zeros().min(usize::BITS - 1);
+            }
+            if (index & 1 == 0) != is_left {
+                return false;
+            }
+            index >>= 1;
+        }
+        index == 0
+    }
+
+    /// The directions of the `siblings` siblings of leaf `leaf_index`, as
+    /// `MerkleTree::generate_proof` gives them. Bit `k` of the index is 1 where the
+    /// path node is on the right at height `k`. Below some height `m` every level
+    /// has a sibling; from there the node is the last of each level, promoted past
+    /// it on a 0 bit, with no sibling, and a right child on a 1 bit. `m` is the one
+    /// height with a 0 bit where `m` plus the 1 bits from `m` on makes `siblings`.
+    /// `None` if there is no such height.
+    pub fn directions_for(leaf_index: usize, siblings: usize) -> Option<Vec<bool>> {
+        let m = (0..=usize::BITS as usize).find(|&m| {
+            let above = leaf_index.checked_shr(m as u32).unwrap_or(0);
+            above & 1 == 0 && m + above.count_ones() as usize == siblings
+        })?;
+        let mut directions: Vec<bool> = (0..m).map(|k| leaf_index >> k & 1 == 0).collect();
+        directions.resize(siblings, false);
+        Some(directions)
+    }
+
+    /// `0x`, the leaf index as 8 big-endian bytes, then each direction as a byte,
+    /// 1 for left, followed by its sibling.
+    pub fn to_hex(&self) -> String {
+        let mut hex = format!("0x{:016x}", self.leaf_index);
+        for (sibling, is_left) in self.siblings.iter().zip(&self.directions) {
+            hex.push_str(if *is_left { "01" } else { "00" });
+            hex.extend(sibling.iter().map(|b| format!("{b:02x}")));
+        }
+        hex
+    }
+
+    /// Reads the `to_hex` form back, the `0x` prefix optional. Fails with
+    /// `InvalidHex` for bad hex and `InvalidProofEncoding` for hex of the wrong
+    /// length or with a direction byte other than 0 or 1.
+    pub fn from_hex(hex: &str) -> Result<Self, MerkleError> {
+        let bytes = decode_hex(hex).map_err(MerkleError::InvalidHex)?;
+        if bytes.len() < 8 || (bytes.len() - 8) % 33 != 0 {
+            return Err(MerkleError::InvalidProofEncoding);
+        }
+        let leaf_index = u64::from_be_bytes(bytes[..8].try_into().unwrap())
+            .try_into()
+            .map_err(|_| MerkleError::InvalidProofEncoding)?;
+        let mut pairs = Vec::new();
+        for entry in bytes[8..].chunks(33) {
+            let is_left = match entry[0] {
+                0 => false,
+                1 => true,
+                _ => return Err(MerkleError::InvalidProofEncoding),
+            };
+            pairs.push((entry[1..].try_into().unwrap(), is_left));
+        }
+        Ok(Self::from_pairs(&pairs, leaf_index))
+    }
+}
+
+/// A binary Merkle tree over a list of leaves, hashed with `H`.
+#[derive(Debug, Clone)]
+pub struct MerkleTree<H = MerkleSha256Hasher> {
+    /// Every level of nodes, from the leaves up to the root alone.
+    levels: Vec<Vec<[u8; 32]>>,
+    padding: PaddingPolicy,
+    /// Whether pairs are hashed smallest first, see `with_options`.
+    sorted_pairs: bool,
+    hasher: PhantomData<H>,
 }
 
 impl MerkleTree {
-    fn new(data: &[&str]) -> Self {
-        let leaves: Vec<Vec<u8>> = data.iter().map(|&s| Self::hash_leaf(s)).collect();
+    /// Builds the tree over `data`, any byte strings: `&str`s, byte slices or
+    /// vectors alike, UTF-8 or not. Odd nodes are promoted.
+    pub fn new<T: AsRef<[u8]>>(data: &[T]) -> Self {
+        Self::with_hasher(data)
+    }
+}
+
+impl MerkleTree<Rfc6962Hasher> {
+    /// Builds the tree RFC 6962 defines as `MTH(D[n])`: leaves hashed as
+    /// `SHA-256(0x00 || leaf)`, pairs as `SHA-256(0x01 || left || right)`, and `n`
+    /// leaves split after the largest power of two below `n`. Pairing each level
+    /// and promoting its odd node gives that split, so the roots match those of
+    /// Certificate Transparency logs, and `generate_proof` siblings are the audit
+    /// paths `verify_audit_path` checks.
+    pub fn rfc6962<T: AsRef<[u8]>>(data: &[T]) -> Self {
+        Self::with_hasher(data)
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// Like `new`, hashed with `H`.
+    pub fn with_hasher<T: AsRef<[u8]>>(data: &[T]) -> Self {
+        Self::with_padding(data, PaddingPolicy::Promote)
+    }
+
+    /// Like `with_hasher`, handling odd nodes as `padding` says. Call it as
+    /// `<MerkleTree>::with_padding(..)` to use the default hasher.
+    pub fn with_padding<T: AsRef<[u8]>>(data: &[T], padding: PaddingPolicy) -> Self {
+        Self::with_options(data, padding, false)
+    }
+
+    /// Like `with_padding`. With `sorted_pairs`, the two nodes of a pair are hashed
+    /// smallest first whatever their position, as OpenZeppelin's `MerkleProof` and
+    /// merkletreejs' `sortPairs` do, so proofs need no directions: see
+    /// `generate_sorted_proof`. Panics for no data.
+    pub fn with_options<T: AsRef<[u8]>>(
+        data: &[T],
+        padding: PaddingPolicy,
+        sorted_pairs: bool,
+    ) -> Self {
+        let leaves = data.iter().map(|d| Self::hash_leaf(d.as_ref())).collect();
+        Self::from_leaves(leaves, padding, sorted_pairs)
+    }
+
+    /// Like `with_options`, over leaves already hashed.
+    fn from_leaves(leaves: Vec<[u8; 32]>, padding: PaddingPolicy, sorted_pairs: bool) -> Self {
+        assert!(!leaves.is_empty(), "a tree has at least one leaf");
+        let mut tree = Self {
+            levels: vec![leaves],
+            padding,
+            sorted_pairs,
+            hasher: PhantomData,
+        };
+        while tree.levels[tree.levels.len() - 1].len() > 1 {
+            let above = tree.hash_level(&tree.levels[tree.levels.len() - 1]);
+            tree.levels.push(above);
+        }
+        tree
+    }
+
+    /// Number of leaves.
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Always false, as a tree has at least one leaf.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Appends a leaf for `data` and returns its index. Only the right edge of the
+    /// tree is rehashed, O(log n) hashes, and the root is as `with_options` would
+    /// give for all the leaves.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        self.levels[0].push(Self::hash_leaf(data));
+        let index = self.len() - 1;
+        self.rehash_path(index);
+        index
+    }
+
+    /// Sets leaf `index` to the hash of `data`, rehashing only its path to the
+    /// root. Fails with `IndexOutOfRange` past the last leaf.
+    pub fn update_leaf(&mut self, index: usize, data: &[u8]) -> Result<(), MerkleError> {
+        let len = self.len();
+        let leaf = self.levels[0]
+            .get_mut(index)
+            .ok_or(MerkleError::IndexOutOfRange { index, len })?;
+        *leaf = Self::hash_leaf(data);
+        self.rehash_path(index);
+        Ok(())
+    }
+
+    /// Recomputes the ancestors of leaf `index`, adding a level on top if the
+    /// root's level has grown past one node.
+    fn rehash_path(&mut self, mut index: usize) {
+        let mut height = 0;
+        while self.levels[height].len() > 1 {
+            let level = &self.levels[height];
+            let left = index & !1;
+            let parent = match level.get(left + 1) {
+                Some(right) => self.hash_children(&level[left], right),
+                None => self.hash_odd(&level[left]),
+            };
+            index /= 2;
+            if height + 1 == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let above = &mut self.levels[height + 1];
+            if index == above.len() {
+                above.push(parent);
+            } else {
+                above[index] = parent;
+            }
+            height += 1;
+        }
+    }
 
-        let root = Self::find_root(&leaves);
+    pub fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+        H::hash_leaf(leaf)
+    }
 
-        Self { root, leaves }
+    pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        H::hash_pair(left, right)
     }
 
-    fn find_root(leaves: &[Vec<u8>]) -> Vec<u8> {
-        let mut current_level = leaves.to_vec();
-        while current_level.len() > 1 {
-            current_level = Self::hash_level(&current_level);
+    /// `hash_pair` of the two nodes, smallest first.
+    pub fn hash_sorted_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        if a <= b {
+            Self::hash_pair(a, b)
+        } else {
+            Self::hash_pair(b, a)
         }
-        current_level.into_iter().next().unwrap()
     }
 
-    pub fn hash_leaf(leaf: &str) -> Vec<u8> {
-        return Sha256::digest(leaf.as_bytes()).to_vec();
+    /// The parent of `left` and `right` in this tree.
+    fn hash_children(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        if self.sorted_pairs {
+            Self::hash_sorted_pair(left, right)
+        } else {
+            Self::hash_pair(left, right)
+        }
     }
 
-    fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(left);
-        hasher.update(right);
-        hasher.finalize().to_vec()
+    /// The parent of the last node of an odd level, padded as the tree says.
+    fn hash_odd(&self, node: &[u8; 32]) -> [u8; 32] {
+        match self.padding.odd_sibling(node) {
+            Some(right) => self.hash_children(node, &right),
+            None => *node,
+        }
     }
 
-    fn hash_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    fn hash_level(&self, level: &[[u8; 32]]) -> Vec<[u8; 32]> {
         level
             .chunks(2)
             .map(|chunk| match chunk {
-                [left, right] => Self::hash_pair(left, right),
-                [single] => single.clone(),
+                [left, right] => self.hash_children(left, right),
+                [single] => self.hash_odd(single),
                 _ => unreachable!(),
             })
             .collect()
     }
 
-    fn root(&self) -> &Vec<u8> {
-        &self.root
+    /// The root hash.
+    pub fn root(&self) -> [u8; 32] {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    /// `root` as `0x`-prefixed hex.
+    pub fn root_hex(&self) -> String {
+        let hex: String = self.root().iter().map(|b| format!("{b:02x}")).collect();
+        format!("0x{hex}")
     }
 
-    fn generate_proof(&self, leaf_index: usize) -> Vec<(Vec<u8>, bool)> {
+    /// The proof of leaf `leaf_index`. With `PaddingPolicy::Promote`, a node promoted past an odd level keeps its
+    /// hash there, so that level adds no sibling and proofs of such leaves are
+    /// shorter. The other policies give the node's padding as its sibling. For a
+    /// tree with sorted pairs, use `generate_sorted_proof`. Fails with
+    /// `IndexOutOfRange` past the last leaf.
+    pub fn generate_proof(&self, leaf_index: usize) -> Result<MerkleProof<H>, MerkleError> {
+        let len = self.len();
+        if leaf_index >= len {
+            return Err(MerkleError::IndexOutOfRange {
+                index: leaf_index,
+                len,
+            });
+        }
         let mut proof = Vec::new();
-        let mut current_level = self.leaves.clone();
         let mut current_index = leaf_index;
 
-        while current_level.len() > 1 {
-            let sibling = current_index ^ 1;
-            if sibling < current_level.len() {
-                proof.push((current_level[sibling].clone(), current_index % 2 == 0));
+        // The siblings are read from the stored levels, so nothing is hashed.
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(current_index ^ 1) {
+                proof.push((*sibling, current_index.is_multiple_of(2)));
+            } else if let Some(padding) = self.padding.odd_sibling(&level[current_index]) {
+                proof.push((padding, true));
             }
             current_index /= 2;
-            current_level = Self::hash_level(&current_level);
         }
-        proof
+        Ok(MerkleProof::from_pairs(&proof, leaf_index))
+    }
+
+    /// The siblings of `generate_proof` without their directions, for a tree built
+    /// with sorted pairs, where they don't matter. Panics for any other tree, and
+    /// fails like `generate_proof`.
+    pub fn generate_sorted_proof(&self, leaf_index: usize) -> Result<Vec<[u8; 32]>, MerkleError> {
+        assert!(self.sorted_pairs, "the tree doesn't sort its pairs");
+        Ok(self.generate_proof(leaf_index)?.siblings)
+    }
+
+    /// Whether `proof` from `generate_sorted_proof` leads from `leaf` to `root`,
+    /// hashing each pair smallest first like OpenZeppelin's `MerkleProof.verify`.
+    pub fn verify_sorted_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &[[u8; 32]]) -> bool {
+        let current_hash = proof.iter().fold(*leaf, |node, sibling| {
+            Self::hash_sorted_pair(&node, sibling)
+        });
+        current_hash == *root
     }
 
-    fn verify_proof(root: &[u8], leaf: &[u8], proof: &[(Vec<u8>, bool)]) -> bool {
-        let mut current_hash = leaf.to_vec();
+    /// Whether `siblings`, those of a `MerkleProof`, lead from `leaf_hash` to `root`
+    /// for leaf `leaf_index`, with the directions read from the index's bits as
+    /// `MerkleProof::directions_for` does rather than sent along.
+    pub fn verify_indexed(
+        root: &[u8; 32],
+        leaf_hash: &[u8; 32],
+        leaf_index: usize,
+        siblings: &[[u8; 32]],
+    ) -> bool {
+        let Some(directions) = MerkleProof::<H>::directions_for(leaf_index, siblings.len()) else {
+            return false;
+        };
+        let proof = MerkleProof {
+            siblings: siblings.to_vec(),
+            directions,
+            leaf_index,
+            hasher: PhantomData,
+        };
+        Self::verify_proof(root, leaf_hash, &proof)
+    }
 
-        for (sibling, is_left) in proof {
+    /// Whether `proof` leads from `leaf` to `root` when hashed with `H`, so a proof
+    /// only verifies with the hasher of the tree that generated it. A proof whose
+    /// directions don't fit its index, or don't come one per sibling, fails.
+    pub fn verify_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &MerkleProof<H>) -> bool {
+        if proof.directions.len() != proof.siblings.len() || !proof.directions_match_index() {
+            return false;
+        }
+        let mut current_hash = *leaf;
+
+        for (sibling, is_left) in proof.siblings.iter().zip(&proof.directions) {
             current_hash = if *is_left {
                 Self::hash_pair(&current_hash, sibling)
             } else {
@@ -75,31 +551,238 @@ impl MerkleTree {
             }
         }
 
-        current_hash == root
+        current_hash == *root
+    }
+
+    /// Whether `path`, an RFC 6962 audit path such as the siblings of a
+    /// `generate_proof`, leads from `leaf_hash` at `leaf_index` to `root` of a tree
+    /// of `tree_size` leaves, following the verification in RFC 9162, section
+    /// 2.1.3.2. Unlike `verify_indexed`, the levels where the node is promoted are
+    /// read from the tree size, as CT logs send it with the path.
+    pub fn verify_audit_path(
+        root: &[u8; 32],
+        leaf_hash: &[u8; 32],
+        leaf_index: usize,
+        tree_size: usize,
+        path: &[[u8; 32]],
+    ) -> bool {
+        if leaf_index >= tree_size {
+            return false;
+        }
+        let (mut node, mut last) = (leaf_index, tree_size - 1);
+        let mut hash = *leaf_hash;
+        for sibling in path {
+            if last == 0 {
+                return false;
+            }
+            if node & 1 == 1 || node == last {
+                hash = Self::hash_pair(sibling, &hash);
+                // Skip the levels where the node is promoted.
+                while node & 1 == 0 && node != 0 {
+                    node >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = Self::hash_pair(&hash, sibling);
+            }
+            node >>= 1;
+            last >>= 1;
+        }
+        last == 0 && hash == *root
+    }
+
+    /// Proof that the tree over the first `old_size` leaves is a prefix of the tree
+    /// over the first `new_size`, as RFC 6962's `PROOF(m, D[n])`. Empty when
+    /// `old_size` is 0 or `new_size`. Panics unless `old_size <= new_size <= len()`,
+    /// and for a tree without `PaddingPolicy::Promote` or with sorted pairs, as
+    /// only those trees split their leaves like RFC 6962.
+    pub fn generate_consistency_proof(&self, old_size: usize, new_size: usize) -> ConsistencyProof {
+        assert!(
+            self.padding == PaddingPolicy::Promote && !self.sorted_pairs,
+            "consistency proofs need promoted odd nodes and ordered pairs"
+        );
+        assert!(
+            old_size <= new_size && new_size <= self.len(),
+            "sizes {old_size} and {new_size} don't fit a tree of {} leaves",
+            self.len()
+        );
+        let mut hashes = Vec::new();
+        if 0 < old_size && old_size < new_size {
+            self.subproof(old_size, 0, new_size, true, &mut hashes);
+        }
+        ConsistencyProof { hashes }
+    }
+
+    /// RFC 6962's `SUBPROOF(m, D[start:end], complete)`, onto `hashes`.
+    fn subproof(
+        &self,
+        m: usize,
+        start: usize,
+        end: usize,
+        complete: bool,
+        hashes: &mut Vec<[u8; 32]>,
+    ) {
+        let n = end - start;
+        if m == n {
+            if !complete {
+                hashes.push(self.subtree_root(start, end));
+            }
+            return;
+        }
+        let k = split_point(n);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, hashes);
+            hashes.push(self.subtree_root(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, hashes);
+            hashes.push(self.subtree_root(start, start + k));
+        }
+    }
+
+    /// Root of the tree over leaves `start..end`: a stored node for a full,
+    /// aligned subtree, and otherwise hashed from the two halves RFC 6962 splits
+    /// it into.
+    fn subtree_root(&self, start: usize, end: usize) -> [u8; 32] {
+        let size = end - start;
+        if size.is_power_of_two() && start.is_multiple_of(size) {
+            return self.levels[size.trailing_zeros() as usize][start / size];
+        }
+        let k = split_point(size);
+        Self::hash_pair(
+            &self.subtree_root(start, start + k),
+            &self.subtree_root(start + k, end),
+        )
+    }
+
+    /// Whether `proof` shows the tree of `old_size` leaves with root `old_root` to
+    /// be a prefix of the one of `new_size` leaves with root `new_root`, following
+    /// the verification in RFC 9162, section 2.1.4.2. Any tree extends the empty
+    /// one, and a tree extends itself only with an empty proof.
+    pub fn verify_consistency(
+        old_root: &[u8; 32],
+        old_size: usize,
+        new_root: &[u8; 32],
+        new_size: usize,
+        proof: &ConsistencyProof,
+    ) -> bool {
+        let path = proof.hashes.as_slice();
+        if old_size > new_size {
+            return false;
+        }
+        if old_size == 0 || old_size == new_size {
+            return path.is_empty() && (old_size == 0 || old_root == new_root);
+        }
+        // For a power of two, the old root is a node of the new tree and is left out.
+        let (first, rest) = if old_size.is_power_of_two() {
+            (old_root, path)
+        } else {
+            match path.split_first() {
+                Some(split) => split,
+                None => return false,
+            }
+        };
+        let (mut old_node, mut new_node) = (old_size - 1, new_size - 1);
+        while old_node & 1 == 1 {
+            old_node >>= 1;
+            new_node >>= 1;
+        }
+        let (mut old_hash, mut new_hash) = (*first, *first);
+        for hash in rest {
+            if new_node == 0 {
+                return false;
+            }
+            if old_node & 1 == 1 || old_node == new_node {
+                old_hash = Self::hash_pair(hash, &old_hash);
+                new_hash = Self::hash_pair(hash, &new_hash);
+                while old_node & 1 == 0 && old_node != 0 {
+                    old_node >>= 1;
+                    new_node >>= 1;
+                }
+            } else {
+                new_hash = Self::hash_pair(&new_hash, hash);
+            }
+            old_node >>= 1;
+            new_node >>= 1;
+        }
+        old_hash == *old_root && new_hash == *new_root && new_node == 0
+    }
+}
+
+impl MerkleTree<BitcoinHasher> {
+    /// Builds the merkle tree of a Bitcoin block over its `txids`, coinbase first,
+    /// in the byte order they're hashed in: the reverse of the hex block explorers
+    /// and RPCs show, see `bitcoin_hash_from_hex`. The last node of an odd level is
+    /// paired with itself, so proofs are SPV merkle branches: the siblings of
+    /// `generate_proof` from the txid up, which `verify_indexed` checks from the
+    /// transaction's position in the block.
+    ///
+    /// That duplication makes the txids `[a, b, c]` and `[a, b, c, c]` share a
+    /// root (CVE-2012-2459), so a proof also passes for the index one past an odd
+    /// level's last node: check indexes against the block's transaction count,
+    /// and blocks against `is_mutated`. Panics for no txids.
+    pub fn bitcoin(txids: &[[u8; 32]]) -> Self {
+        Self::from_leaves(txids.to_vec(), PaddingPolicy::DuplicateLast, false)
+    }
+
+    /// Whether some level pairs two equal nodes, as Bitcoin Core's
+    /// `ComputeMerkleRoot` checks: a block with repeated txids hashing to the root
+    /// of one without them, which nodes reject as invalid.
+    pub fn is_mutated(&self) -> bool {
+        self.levels
+            .iter()
+            .any(|level| level.chunks_exact(2).any(|pair| pair[0] == pair[1]))
     }
 }
 
+/// The merkle root of a Bitcoin block with these `txids`, in the byte order of
+/// `MerkleTree::bitcoin`. Panics for no txids, as a block has a coinbase.
+pub fn bitcoin_merkle_root(txids: &[[u8; 32]]) -> [u8; 32] {
+    MerkleTree::bitcoin(txids).root()
+}
+
+/// A txid, block hash or merkle root from the hex Bitcoin shows it as, with its
+/// bytes reversed into the order it's hashed in.
+pub fn bitcoin_hash_from_hex(hex: &str) -> Result<[u8; 32], MerkleError> {
+    let mut bytes = decode_hex(hex).map_err(MerkleError::InvalidHex)?;
+    bytes.reverse();
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| MerkleError::InvalidHashLength(len))
+}
+
+/// The hex Bitcoin shows `hash` as, the reverse of `bitcoin_hash_from_hex`.
+pub fn bitcoin_hash_to_hex(hash: &[u8; 32]) -> String {
+    hash.iter().rev().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The largest power of two below `n`, where RFC 6962 splits `n` leaves.
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::Cell;
 
     #[test]
     fn test_merkle_tree() {
         let data = &["a", "b", "c", "d"];
         let tree = MerkleTree::new(data);
         println!("Root: {:?}", tree.root());
-        let leaf_a = MerkleTree::hash_leaf("a");
-        let leaf_b = MerkleTree::hash_leaf("b");
-        let leaf_c = MerkleTree::hash_leaf("c");
-        let leaf_d = MerkleTree::hash_leaf("d");
+        let leaf_a = <MerkleTree>::hash_leaf(b"a");
+        let leaf_b = <MerkleTree>::hash_leaf(b"b");
+        let leaf_c = <MerkleTree>::hash_leaf(b"c");
+        let leaf_d = <MerkleTree>::hash_leaf(b"d");
 
-        let hash_ab = MerkleTree::hash_pair(&leaf_a, &leaf_b);
-        let hash_cd = MerkleTree::hash_pair(&leaf_c, &leaf_d);
+        let hash_ab = <MerkleTree>::hash_pair(&leaf_a, &leaf_b);
+        let hash_cd = <MerkleTree>::hash_pair(&leaf_c, &leaf_d);
 
-        let expected_root = MerkleTree::hash_pair(&hash_ab, &hash_cd);
+        let expected_root = <MerkleTree>::hash_pair(&hash_ab, &hash_cd);
         println!("Expected Root: {:?}", tree.root());
 
-        assert_eq!(tree.root(), &expected_root);
+        assert_eq!(tree.root(), expected_root);
     }
 
     #[test]
@@ -108,17 +791,752 @@ mod tests {
         let tree = MerkleTree::new(data);
 
         // Generate and verify proof for leaf "b" (index 1)
-        let leaf_b = MerkleTree::hash_leaf("b");
-        let proof = tree.generate_proof(1);
-        assert!(MerkleTree::verify_proof(tree.root(), &leaf_b, &proof) == true);
+        let leaf_b = <MerkleTree>::hash_leaf(b"b");
+        let proof = tree.generate_proof(1).unwrap();
+        assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf_b, &proof));
 
         // Verify that the proof fails for a different leaf
-        let leaf_c = MerkleTree::hash_leaf("c");
-        assert!(MerkleTree::verify_proof(tree.root(), &leaf_c, &proof) == false);
+        let leaf_c = <MerkleTree>::hash_leaf(b"c");
+        assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf_c, &proof));
 
         // Tamper with the proof and verify it fails
         let mut tampered_proof = proof.clone();
-        tampered_proof[0].0[0] ^= 1; // Flip a bit in the first hash
-        assert!(MerkleTree::verify_proof(tree.root(), &leaf_b, &tampered_proof) == false);
+        tampered_proof.siblings[0][0] ^= 1; // Flip a bit in the first hash
+        assert!(!<MerkleTree>::verify_proof(
+            &tree.root(),
+            &leaf_b,
+            &tampered_proof
+        ));
+    }
+
+    #[test]
+    fn test_merkle_tree_over_bytes() {
+        // Not UTF-8, so these couldn't be passed as `&str`.
+        let data: [&[u8]; 3] = [&[0xff, 0xfe], &[0x80], &[0xc3, 0x28, 0x00]];
+        let tree = MerkleTree::new(&data);
+        let leaves: Vec<_> = data.iter().map(|d| <MerkleTree>::hash_leaf(d)).collect();
+        let expected_root =
+            <MerkleTree>::hash_pair(&<MerkleTree>::hash_pair(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(tree.root(), expected_root);
+
+        let proof = tree.generate_proof(0).unwrap();
+        assert!(<MerkleTree>::verify_proof(&tree.root(), &leaves[0], &proof));
+
+        // A `&str` and its bytes make the same tree.
+        let owned = vec![b"a".to_vec(), b"b".to_vec()];
+        assert_eq!(
+            MerkleTree::new(&owned).root(),
+            MerkleTree::new(&["a", "b"]).root()
+        );
+    }
+
+    #[test]
+    fn test_every_leaf_proves_for_any_size() {
+        for size in 1..=16 {
+            let data: Vec<Vec<u8>> = (0..size).map(|i: u8| vec![i]).collect();
+            let tree = MerkleTree::new(&data);
+            for (index, leaf) in data.iter().enumerate() {
+                let leaf = <MerkleTree>::hash_leaf(leaf);
+                let proof = tree.generate_proof(index).unwrap();
+                assert!(
+                    <MerkleTree>::verify_proof(&tree.root(), &leaf, &proof),
+                    "leaf {index} of {size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_padding_policies() {
+        let data = &["a", "b", "c"];
+        let policies = [
+            (
+                PaddingPolicy::Promote,
+                "7075152d03a5cd92104887b476862778ec0c87be5c2fa1c0a90f87c49fad6eff",
+            ),
+            (
+                PaddingPolicy::DuplicateLast,
+                "d31a37ef6ac14a2db1470c4316beb5592e6afd4465022339adafda76a18ffabe",
+            ),
+            (
+                PaddingPolicy::PadWithZero,
+                "d0a664079d491a97357efa1ce1eab5aeb566adef78a2b910e8d13e901e192832",
+            ),
+        ];
+        let trees: Vec<_> = policies
+            .iter()
+            .map(|&(padding, root)| {
+                let tree = <MerkleTree>::with_padding(data, padding);
+                let hex: String = tree.root().iter().map(|b| format!("{b:02x}")).collect();
+                assert_eq!(hex, root, "{padding:?}");
+                tree
+            })
+            .collect();
+
+        for size in 1..=9u8 {
+            let data: Vec<Vec<u8>> = (0..size).map(|i| vec![i]).collect();
+            for &(padding, _) in &policies {
+                let tree = <MerkleTree>::with_padding(&data, padding);
+                for (index, leaf) in data.iter().enumerate() {
+                    let proof = tree.generate_proof(index).unwrap();
+                    let leaf = <MerkleTree>::hash_leaf(leaf);
+                    assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &proof));
+                }
+            }
+        }
+
+        // Every leaf's proof under one policy fails against another policy's root.
+        for (i, tree) in trees.iter().enumerate() {
+            for (index, leaf) in data.iter().enumerate() {
+                let proof = tree.generate_proof(index).unwrap();
+                let leaf = <MerkleTree>::hash_leaf(leaf.as_bytes());
+                for (j, other) in trees.iter().enumerate() {
+                    assert_eq!(
+                        <MerkleTree>::verify_proof(&other.root(), &leaf, &proof),
+                        i == j
+                    );
+                }
+            }
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn test_hashers() {
+        let data = &["a", "b"];
+        let sha256 = MerkleTree::new(data);
+        let keccak = MerkleTree::<MerkleKeccak256Hasher>::with_hasher(data);
+        assert_eq!(
+            hex(&MerkleTree::<MerkleKeccak256Hasher>::hash_leaf(b"a")),
+            "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb"
+        );
+        assert_eq!(
+            hex(&sha256.root()),
+            "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a"
+        );
+        assert_eq!(
+            hex(&keccak.root()),
+            "805b21d846b189efaeb0377d6bb0d201b3872a363e607c25088f025b0c6ae1f8"
+        );
+
+        // Each tree's proofs verify with its own hasher only: the types keep a
+        // proof from another verifier unless it is decoded again as that one's.
+        let proof = keccak.generate_proof(0).unwrap();
+        let leaf = MerkleTree::<MerkleKeccak256Hasher>::hash_leaf(b"a");
+        assert!(MerkleTree::<MerkleKeccak256Hasher>::verify_proof(
+            &keccak.root(),
+            &leaf,
+            &proof
+        ));
+        let relabelled: MerkleProof = MerkleProof::from_hex(&proof.to_hex()).unwrap();
+        assert!(!<MerkleTree>::verify_proof(
+            &keccak.root(),
+            &leaf,
+            &relabelled
+        ));
+    }
+
+    #[test]
+    fn test_sorted_pairs() {
+        type KeccakTree = MerkleTree<MerkleKeccak256Hasher>;
+
+        // keccak256("a") < keccak256("b"), so sorting leaves this pair as it is.
+        let pair = KeccakTree::with_options(&["a", "b"], PaddingPolicy::Promote, true);
+        assert_eq!(pair.root(), KeccakTree::with_hasher(&["a", "b"]).root());
+        let reversed = KeccakTree::with_options(&["b", "a"], PaddingPolicy::Promote, true);
+        assert_eq!(reversed.root(), pair.root());
+
+        // The tree merkletreejs builds with `hashLeaves` and `sortPairs` over keccak256,
+        // which also promotes the odd node.
+        let data = ["a", "b", "c", "d", "e"];
+        let tree = KeccakTree::with_options(&data, PaddingPolicy::Promote, true);
+        assert_eq!(
+            hex(&tree.root()),
+            "1dd0d2a6ae466d665cb26e1a31f07c57ae5df7d2bc559cd5826d417be9141a5d"
+        );
+        for (index, leaf) in data.iter().enumerate() {
+            let leaf = KeccakTree::hash_leaf(leaf.as_bytes());
+            let proof = tree.generate_sorted_proof(index).unwrap();
+            assert!(KeccakTree::verify_sorted_proof(&tree.root(), &leaf, &proof));
+            let other = KeccakTree::hash_leaf(b"f");
+            assert!(!KeccakTree::verify_sorted_proof(
+                &tree.root(),
+                &other,
+                &proof
+            ));
+        }
+    }
+
+    #[test]
+    fn test_prefixes_stop_internal_node_forgery() {
+        /// Whether the parent of the first two leaves passes for a leaf, with
+        /// `hash_leaf(left || right)` as its hash and the parent's proof.
+        fn forge<H: Hasher>() -> bool {
+            let tree = MerkleTree::<H>::with_hasher(&["a", "b", "c", "d"]);
+            let forged_leaf = [tree.levels[0][0], tree.levels[0][1]].concat();
+            let mut proof = tree.generate_proof(0).unwrap();
+            proof.siblings.remove(0);
+            proof.directions.remove(0);
+            let leaf = MerkleTree::<H>::hash_leaf(&forged_leaf);
+            MerkleTree::<H>::verify_proof(&tree.root(), &leaf, &proof)
+        }
+
+        assert!(forge::<MerkleSha256Hasher>());
+        assert!(forge::<MerkleKeccak256Hasher>());
+        assert!(!forge::<Prefixed>());
+        assert!(!forge::<Prefixed<MerkleKeccak256Hasher>>());
+
+        // RFC 6962's hash of the empty leaf, sha256(0x00).
+        assert_eq!(
+            hex(&<Prefixed>::hash_leaf(b"")),
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"
+        );
+    }
+
+    #[test]
+    fn test_push_matches_rebuild() {
+        let data: Vec<Vec<u8>> = (0..300u16).map(|i| i.to_be_bytes().to_vec()).collect();
+        for padding in [
+            PaddingPolicy::Promote,
+            PaddingPolicy::DuplicateLast,
+            PaddingPolicy::PadWithZero,
+        ] {
+            for sorted_pairs in [false, true] {
+                let mut tree = <MerkleTree>::with_options(&data[..1], padding, sorted_pairs);
+                for (index, leaf) in data.iter().enumerate().skip(1) {
+                    assert_eq!(tree.push(leaf), index);
+                    let len = index + 1;
+                    if len < 20 || len % 37 == 0 || len.is_power_of_two() || len == data.len() {
+                        let rebuilt =
+                            <MerkleTree>::with_options(&data[..len], padding, sorted_pairs);
+                        assert_eq!(tree.root(), rebuilt.root(), "{padding:?} at {len}");
+                        assert_eq!(tree.levels, rebuilt.levels);
+                    }
+                }
+                let leaf = <MerkleTree>::hash_leaf(&data[123]);
+                assert!(if sorted_pairs {
+                    let proof = tree.generate_sorted_proof(123).unwrap();
+                    <MerkleTree>::verify_sorted_proof(&tree.root(), &leaf, &proof)
+                } else {
+                    <MerkleTree>::verify_proof(
+                        &tree.root(),
+                        &leaf,
+                        &tree.generate_proof(123).unwrap(),
+                    )
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_leaf() {
+        for padding in [PaddingPolicy::Promote, PaddingPolicy::DuplicateLast] {
+            let mut data: Vec<Vec<u8>> = (0..11u8).map(|i| vec![i]).collect();
+            let mut tree = <MerkleTree>::with_padding(&data, padding);
+            for index in [0, 5, 10] {
+                let untouched = (index + 1) % data.len();
+                let old_proof = tree.generate_proof(untouched).unwrap();
+                data[index] = b"updated".to_vec();
+                tree.update_leaf(index, &data[index]).unwrap();
+
+                let rebuilt = <MerkleTree>::with_padding(&data, padding);
+                assert_eq!(tree.root(), rebuilt.root());
+                assert_eq!(tree.levels, rebuilt.levels);
+                // Older proofs commit to the old leaf, so they no longer verify.
+                let leaf = <MerkleTree>::hash_leaf(&data[untouched]);
+                assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf, &old_proof));
+                let proof = tree.generate_proof(untouched).unwrap();
+                assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &proof));
+            }
+            assert_eq!(
+                tree.update_leaf(11, b"past the end"),
+                Err(MerkleError::IndexOutOfRange { index: 11, len: 11 })
+            );
+        }
+    }
+
+    thread_local! {
+        static HASHES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// `MerkleSha256Hasher`, counting its hashes on each thread.
+    struct CountingHasher;
+
+    impl Hasher for CountingHasher {
+        fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+            HASHES.with(|hashes| hashes.set(hashes.get() + 1));
+            MerkleSha256Hasher::hash_leaf(leaf)
+        }
+
+        fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+            HASHES.with(|hashes| hashes.set(hashes.get() + 1));
+            MerkleSha256Hasher::hash_pair(left, right)
+        }
+    }
+
+    #[test]
+    fn test_proofs_read_stored_levels() {
+        /// Proof generation as it was before levels were stored, rehashing every
+        /// level below the root.
+        fn rehashed_proof<H: Hasher>(tree: &MerkleTree<H>, leaf_index: usize) -> MerkleProof<H> {
+            let mut proof = Vec::new();
+            let mut current_level = tree.levels[0].clone();
+            let mut current_index = leaf_index;
+            while current_level.len() > 1 {
+                let sibling = current_index ^ 1;
+                if sibling < current_level.len() {
+                    proof.push((current_level[sibling], current_index.is_multiple_of(2)));
+                } else if let Some(padding) =
+                    tree.padding.odd_sibling(&current_level[current_index])
+                {
+                    proof.push((padding, true));
+                }
+                current_index /= 2;
+                current_level = tree.hash_level(&current_level);
+            }
+            MerkleProof::from_pairs(&proof, leaf_index)
+        }
+
+        for size in [1, 2, 3, 7, 8, 13, 64, 100] {
+            let data: Vec<Vec<u8>> = (0..size).map(|i: u8| vec![i]).collect();
+            for padding in [
+                PaddingPolicy::Promote,
+                PaddingPolicy::DuplicateLast,
+                PaddingPolicy::PadWithZero,
+            ] {
+                let tree = MerkleTree::<CountingHasher>::with_padding(&data, padding);
+                let expected: Vec<_> = (0..data.len()).map(|i| rehashed_proof(&tree, i)).collect();
+
+                let before = HASHES.with(Cell::get);
+                let proofs: Vec<_> = (0..data.len())
+                    .map(|i| tree.generate_proof(i).unwrap())
+                    .collect();
+                assert_eq!(HASHES.with(Cell::get), before);
+                assert_eq!(proofs, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_encodings_and_tampering() {
+        let data: Vec<Vec<u8>> = (0..13u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::new(&data);
+        for index in [0, 5, 12] {
+            let proof = tree.generate_proof(index).unwrap();
+            let leaf = <MerkleTree>::hash_leaf(&data[index]);
+            assert_eq!(proof.leaf_index, index);
+            assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &proof));
+            assert_eq!(MerkleProof::from_hex(&proof.to_hex()), Ok(proof.clone()));
+            assert_eq!(
+                <MerkleProof>::from_hex(&proof.to_hex()[2..].to_uppercase()),
+                Ok(proof.clone())
+            );
+
+            let mut tampered = proof.clone();
+            tampered.siblings[1][31] ^= 1;
+            assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf, &tampered));
+            let mut tampered = proof.clone();
+            tampered.directions[0] = !tampered.directions[0];
+            assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf, &tampered));
+            let mut tampered = proof.clone();
+            tampered.leaf_index ^= 1;
+            assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf, &tampered));
+            let mut tampered = proof;
+            tampered.directions.pop();
+            assert!(!<MerkleTree>::verify_proof(&tree.root(), &leaf, &tampered));
+        }
+
+        // Leaf 12 of 13 is promoted twice, so its proof skips those levels.
+        let proof = tree.generate_proof(12).unwrap();
+        assert_eq!(proof.directions, [false, false]);
+        let pairs: Vec<_> = proof.siblings.iter().map(|&s| (s, false)).collect();
+        assert_eq!(MerkleProof::from_pairs(&pairs, 12), proof);
+        assert!(!<MerkleProof>::from_pairs(&pairs, 13).directions_match_index());
+
+        let hex = tree.generate_proof(3).unwrap().to_hex();
+        assert_eq!(
+            <MerkleProof>::from_hex(&hex[..hex.len() - 2]),
+            Err(MerkleError::InvalidProofEncoding)
+        );
+        let mut bad_direction = hex.clone();
+        bad_direction.replace_range(18..20, "02");
+        assert_eq!(
+            <MerkleProof>::from_hex(&bad_direction),
+            Err(MerkleError::InvalidProofEncoding)
+        );
+        assert!(matches!(
+            <MerkleProof>::from_hex("0xzz"),
+            Err(MerkleError::InvalidHex(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_merkle_proof_serde_round_trip() {
+        let tree = MerkleTree::new(&["a", "b", "c"]);
+        let proof = tree.generate_proof(2).unwrap();
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<MerkleProof>(&json).unwrap(), proof);
+    }
+
+    #[test]
+    fn test_verify_indexed_agrees_with_directions() {
+        for size in 1..=40 {
+            let data: Vec<Vec<u8>> = (0..size).map(|i: u8| vec![i]).collect();
+            for padding in [PaddingPolicy::Promote, PaddingPolicy::DuplicateLast] {
+                let tree = <MerkleTree>::with_padding(&data, padding);
+                for (index, leaf) in data.iter().enumerate() {
+                    let leaf = <MerkleTree>::hash_leaf(leaf);
+                    let proof = tree.generate_proof(index).unwrap();
+                    assert_eq!(
+                        <MerkleProof>::directions_for(index, proof.siblings.len()).as_ref(),
+                        Some(&proof.directions),
+                        "leaf {index} of {size}"
+                    );
+                    assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &proof));
+                    assert!(<MerkleTree>::verify_indexed(
+                        &tree.root(),
+                        &leaf,
+                        index,
+                        &proof.siblings
+                    ));
+                    let other = (index + 1) % data.len();
+                    assert_eq!(
+                        <MerkleTree>::verify_indexed(&tree.root(), &leaf, other, &proof.siblings),
+                        other == index
+                    );
+                }
+            }
+        }
+        assert!(!<MerkleTree>::verify_indexed(
+            &[0; 32],
+            &[0; 32],
+            0b111,
+            &[[0; 32]]
+        ));
+    }
+
+    /// The leaves of the Certificate Transparency test suite.
+    const CT_LEAVES: [&[u8]; 8] = [
+        b"",
+        b"\x00",
+        b"\x10",
+        b"\x20\x21",
+        b"\x30\x31",
+        b"\x40\x41\x42\x43",
+        b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+    ];
+
+    #[test]
+    fn test_rfc6962_roots_and_audit_paths() {
+        type CtTree = MerkleTree<Rfc6962Hasher>;
+        for (size, root) in [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, root)| (i + 1, root))
+        {
+            assert_eq!(hex(&MerkleTree::rfc6962(&CT_LEAVES[..size]).root()), root);
+        }
+
+        for (leaf_index, size, path) in [
+            (
+                0,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ][..],
+            ),
+            (
+                5,
+                8,
+                &[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ][..],
+            ),
+            (
+                2,
+                3,
+                &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"][..],
+            ),
+            (
+                1,
+                5,
+                &[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ][..],
+            ),
+        ] {
+            let sized = MerkleTree::rfc6962(&CT_LEAVES[..size]);
+            let proof = sized.generate_proof(leaf_index).unwrap();
+            let hex: Vec<_> = proof.siblings.iter().map(|hash| hex(hash)).collect();
+            assert_eq!(hex, path, "leaf {leaf_index} of {size}");
+            assert!(CtTree::verify_audit_path(
+                &sized.root(),
+                &sized.levels[0][leaf_index],
+                leaf_index,
+                size,
+                &proof.siblings
+            ));
+        }
+
+        // Every leaf of every size checks with the CT algorithm, and fails for
+        // another root or an index past the last leaf.
+        let data: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i]).collect();
+        for size in 1..=data.len() {
+            let tree = MerkleTree::rfc6962(&data[..size]);
+            for leaf_index in 0..size {
+                let leaf = tree.levels[0][leaf_index];
+                let path = tree.generate_proof(leaf_index).unwrap().siblings;
+                assert!(CtTree::verify_audit_path(
+                    &tree.root(),
+                    &leaf,
+                    leaf_index,
+                    size,
+                    &path
+                ));
+                assert!(!CtTree::verify_audit_path(
+                    &[1; 32], &leaf, leaf_index, size, &path
+                ));
+                assert!(!CtTree::verify_audit_path(
+                    &tree.root(),
+                    &leaf,
+                    size,
+                    size,
+                    &path
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_consistency_proof_vectors() {
+        type CtTree = MerkleTree<Rfc6962Hasher>;
+        let tree = CtTree::rfc6962(&CT_LEAVES);
+        let root = |size| CtTree::rfc6962(&CT_LEAVES[..size]).root();
+        for (old_size, new_size, hashes) in [
+            (1, 1, &[][..]),
+            (
+                1,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ][..],
+            ),
+            (
+                6,
+                8,
+                &[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ][..],
+            ),
+            (
+                2,
+                5,
+                &[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ][..],
+            ),
+        ] {
+            let proof = tree.generate_consistency_proof(old_size, new_size);
+            let hex: Vec<_> = proof.hashes.iter().map(|hash| hex(hash)).collect();
+            assert_eq!(hex, hashes, "{old_size} to {new_size}");
+            assert!(CtTree::verify_consistency(
+                &root(old_size),
+                old_size,
+                &root(new_size),
+                new_size,
+                &proof
+            ));
+        }
+    }
+
+    #[test]
+    fn test_consistency_proofs_between_all_sizes() {
+        let data: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i]).collect();
+        let tree = MerkleTree::new(&data);
+        let roots: Vec<_> = (1..=data.len())
+            .map(|size| MerkleTree::new(&data[..size]).root())
+            .collect();
+        let root = |size: usize| roots[size - 1];
+        for new_size in 1..=data.len() {
+            let proof = tree.generate_consistency_proof(0, new_size);
+            assert!(proof.hashes.is_empty());
+            assert!(<MerkleTree>::verify_consistency(
+                &[0; 32],
+                0,
+                &root(new_size),
+                new_size,
+                &proof
+            ));
+            for old_size in 1..=new_size {
+                let proof = tree.generate_consistency_proof(old_size, new_size);
+                assert_eq!(proof.hashes.is_empty(), old_size == new_size);
+                let (old_root, new_root) = (root(old_size), root(new_size));
+                assert!(<MerkleTree>::verify_consistency(
+                    &old_root, old_size, &new_root, new_size, &proof
+                ));
+
+                // Any other root or hash, or the sizes swapped, fails.
+                let wrong = [1; 32];
+                assert!(!<MerkleTree>::verify_consistency(
+                    &wrong, old_size, &new_root, new_size, &proof
+                ));
+                assert!(!<MerkleTree>::verify_consistency(
+                    &old_root, old_size, &wrong, new_size, &proof
+                ));
+                if old_size < new_size {
+                    assert!(!<MerkleTree>::verify_consistency(
+                        &new_root, new_size, &old_root, old_size, &proof
+                    ));
+                    for i in 0..proof.hashes.len() {
+                        let mut tampered = proof.clone();
+                        tampered.hashes[i][0] ^= 1;
+                        assert!(!<MerkleTree>::verify_consistency(
+                            &old_root, old_size, &new_root, new_size, &tampered
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bitcoin_block_100000() {
+        let txids: Vec<[u8; 32]> = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|txid| bitcoin_hash_from_hex(txid).unwrap())
+        .collect();
+        let root = bitcoin_merkle_root(&txids);
+        assert_eq!(
+            bitcoin_hash_to_hex(&root),
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766"
+        );
+
+        let tree = MerkleTree::bitcoin(&txids);
+        assert!(!tree.is_mutated());
+        for (index, txid) in txids.iter().enumerate() {
+            let branch = tree.generate_proof(index).unwrap().siblings;
+            assert_eq!(branch.len(), 2);
+            assert!(MerkleTree::<BitcoinHasher>::verify_indexed(
+                &root, txid, index, &branch
+            ));
+            assert!(!MerkleTree::<BitcoinHasher>::verify_indexed(
+                &root,
+                txid,
+                index ^ 1,
+                &branch
+            ));
+        }
+    }
+
+    #[test]
+    fn test_bitcoin_single_and_odd_blocks() {
+        // The genesis block holds its coinbase alone, whose txid is the root.
+        let coinbase = bitcoin_hash_from_hex(
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        )
+        .unwrap();
+        assert_eq!(bitcoin_merkle_root(&[coinbase]), coinbase);
+
+        let txids: Vec<[u8; 32]> = (0..7u8)
+            .map(|i| <MerkleTree<BitcoinHasher>>::hash_leaf(&[i]))
+            .collect();
+        let dsha = |left: &[u8; 32], right: &[u8; 32]| {
+            let inner = Sha256::new_with_prefix(left).chain_update(right).finalize();
+            <[u8; 32]>::from(Sha256::digest(inner))
+        };
+        let (a, b, c) = (txids[0], txids[1], txids[2]);
+        let ab = dsha(&a, &b);
+        assert_eq!(bitcoin_merkle_root(&[a, b, c]), dsha(&ab, &dsha(&c, &c)));
+        for size in 1..=txids.len() {
+            let tree = MerkleTree::bitcoin(&txids[..size]);
+            for (index, txid) in txids[..size].iter().enumerate() {
+                let branch = tree.generate_proof(index).unwrap().siblings;
+                assert!(MerkleTree::<BitcoinHasher>::verify_indexed(
+                    &tree.root(),
+                    txid,
+                    index,
+                    &branch
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_bitcoin_duplicate_txid_quirk() {
+        let txids: Vec<[u8; 32]> = (0..6u8)
+            .map(|i| <MerkleTree<BitcoinHasher>>::hash_leaf(&[i]))
+            .collect();
+        let (a, b, c) = (txids[0], txids[1], txids[2]);
+        let honest = MerkleTree::bitcoin(&[a, b, c]);
+        let mutated = MerkleTree::bitcoin(&[a, b, c, c]);
+        assert_eq!(honest.root(), mutated.root());
+        assert!(!honest.is_mutated());
+        assert!(mutated.is_mutated());
+
+        // Repeating the last two of six txids repeats a node a level up.
+        let mut repeated = txids.clone();
+        repeated.extend_from_slice(&txids[4..]);
+        let (honest, mutated) = (MerkleTree::bitcoin(&txids), MerkleTree::bitcoin(&repeated));
+        assert_eq!(honest.root(), mutated.root());
+        assert!(!honest.is_mutated());
+        assert!(mutated.is_mutated());
+
+        // A branch passes for the phantom index of the duplicated txid too.
+        let tree = MerkleTree::bitcoin(&[a, b, c]);
+        let branch = tree.generate_proof(2).unwrap().siblings;
+        assert!(MerkleTree::<BitcoinHasher>::verify_indexed(
+            &tree.root(),
+            &c,
+            3,
+            &branch
+        ));
+    }
+
+    #[test]
+    fn test_bitcoin_hash_hex() {
+        let hex = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let hash = bitcoin_hash_from_hex(hex).unwrap();
+        assert_eq!(hash[31], 0);
+        assert_eq!(hash[0], 0x6f);
+        assert_eq!(bitcoin_hash_to_hex(&hash), hex);
+        assert_eq!(bitcoin_hash_from_hex(&format!("0x{hex}")), Ok(hash));
+        assert_eq!(
+            bitcoin_hash_from_hex("abcd"),
+            Err(MerkleError::InvalidHashLength(2))
+        );
+        assert!(matches!(
+            bitcoin_hash_from_hex("zz"),
+            Err(MerkleError::InvalidHex(_))
+        ));
     }
 }
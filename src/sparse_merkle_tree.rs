@@ -1,137 +1,1208 @@
-use sha2::{Digest, Sha256};
+use cache::{NodeCache, ProofCache};
+use proof::{bit, ct_eq, truncate, DEFAULT_LEAF};
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
 
-const TREE_DEPTH: usize = 128;
+#[cfg(feature = "async")]
+mod async_store;
+mod bulk;
+mod bundle;
+mod cache;
+mod checkpoint;
+mod deletion;
+mod diff;
+mod evm;
+mod integrity;
+mod interned_store;
+mod merge;
+mod metrics;
+mod multiproof;
+mod non_inclusion;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod partial;
+mod persist;
+#[cfg(feature = "poseidon")]
+mod poseidon;
+mod proof;
+mod sharded;
+mod shared;
+mod stats;
+mod store;
+mod transition;
+mod trie_store;
+mod typed;
+mod u128_keys;
+mod undo;
+mod verifier;
+mod versioned;
+mod witness;
 
-struct SparseMerkleTree {
-    root: Vec<u8>,
-    default_nodes: Vec<Vec<u8>>,
+#[cfg(feature = "async")]
+pub use async_store::AsyncNodeStore;
+pub use bundle::SmtProofBundle;
+pub use checkpoint::CheckpointId;
+pub use diff::SmtDiff;
+pub use evm::{verify_evm_encoding, EvmProof};
+pub use integrity::IntegrityError;
+pub use interned_store::InternedStore;
+pub use merge::{ConflictPolicy, MergeConflict};
+pub use metrics::SmtMetrics;
+pub use multiproof::SmtMultiProof;
+pub use non_inclusion::{NonInclusionWitness, SmtNonInclusionProof};
+pub use partial::PartialSmt;
+#[cfg(feature = "poseidon")]
+pub use poseidon::PoseidonHasher;
+pub(crate) use proof::decode_hex;
+pub use proof::{
+    BitOrder, Keccak256Hasher, ProofHexError, ProofStats, Sha256Hasher, SmtError, SmtFlavor,
+    SmtHasher, SmtProof,
+};
+pub use sharded::{ShardedSmt, ShardedSparseMerkleTree};
+pub use shared::{SharedSmt, SharedSparseMerkleTree};
+pub use stats::TreeStats;
+pub use store::{FileStore, MemoryStore, NodeStore};
+pub use transition::{SmtOperation, SmtTransitionProof};
+pub use trie_store::TrieStore;
+#[cfg(feature = "serde")]
+pub use typed::Json;
+pub use typed::{AsBytes, TypedSmt, TypedSparseMerkleTree, ValueEncoding};
+pub use undo::SmtOp;
+pub use verifier::SmtVerifier;
+pub use versioned::{VersionedSmt, VersionedSparseMerkleTree};
+pub use witness::SmtWitness;
+
+#[cfg(test)]
+thread_local! {
+    static HASH_PAIR_CALLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// (level, path prefix) identifying a node, as used by `MemoryStore`.
+type NodePosition<const N: usize> = (usize, [u8; N]);
+
+/// Sparse merkle tree keyed by `N`-byte keys, with up to `8 * N` levels. Any depth
+/// in that range works, e.g. 160 for 20-byte addresses with `Smt<20>`.
+#[derive(Clone)]
+pub struct Smt<const N: usize, H = Sha256Hasher, S = MemoryStore<N>> {
+    root: [u8; 32],
+    depth: usize,
+    flavor: SmtFlavor,
+    bit_order: BitOrder,
+    // Value whose insertion deletes the key instead, see `with_default_value`.
+    default_value: Option<Vec<u8>>,
+    // Whether values are kept next to their leaves, see `with_store_values`.
+    store_values: bool,
+    default_nodes: Arc<[[u8; 32]]>,
+    // Non-default nodes and leaf values. Level 0 is the root and level `depth`
+    // holds the leaves.
+    store: S,
+    cache: NodeCache<N>,
+    proof_cache: ProofCache<N>,
+    // Open checkpoints, oldest first, with the root and storage to restore.
+    checkpoints: Vec<(CheckpointId, [u8; 32], S)>,
+    next_checkpoint: u64,
+    metrics: Option<Arc<SmtMetrics>>,
+    // Most recent writes last, at most `undo_depth` of them, see `undo`.
+    undo_log: VecDeque<SmtOp<N>>,
+    undo_depth: usize,
+    hasher: PhantomData<H>,
+}
+
+/// 128-level tree keyed by 16-byte keys.
+pub type SparseMerkleTree<H = Sha256Hasher, S = MemoryStore<16>> = Smt<16, H, S>;
+
+/// 256-level tree keyed by 32-byte keys, e.g. the hash of an address.
+pub type SparseMerkleTree256<H = Sha256Hasher, S = MemoryStore<32>> = Smt<32, H, S>;
+
+/// A proof with the default siblings left out. Bit `j` of `bitmask` (read
+/// MSB-first, like a path) is set when sibling `j`, counted from the leaf as in
+/// `generate_proof`, is non-default and is therefore present in `siblings`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedProof<const N: usize> {
+    pub bitmask: [u8; N],
+    pub flavor: SmtFlavor,
+    pub hashed_key: bool,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl<const N: usize> Smt<N> {
+    pub fn new() -> Self {
+        Self::with_hasher()
+    }
+
+    /// Creates an empty tree with `depth` levels below the root, i.e. room for 2^depth leaves.
+    /// The path is read from the first `depth` bits of the key (see the example on `insert`),
+    /// so only the first `key_len()` bytes are significant and any bits past `depth` must be zero.
+    pub fn with_depth(depth: usize) -> Self {
+        Self::with_depth_and_hasher(depth)
+    }
+}
+
+impl<const N: usize> Default for Smt<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, S: NodeStore<N>> Smt<N, Sha256Hasher, S> {
+    /// Creates a full-depth tree on top of `store`, picking up any nodes already
+    /// in it. Roots match an in-memory tree given the same inserts.
+    pub fn with_store(store: S) -> Self {
+        Self::from_store(N * 8, store)
+    }
+}
+
+impl<const N: usize, H: SmtHasher> Smt<N, H> {
+    /// Creates an empty full-depth tree hashed with `H`.
+    pub fn with_hasher() -> Self {
+        Self::with_depth_and_hasher(N * 8)
+    }
+
+    /// Like `with_depth`, hashed with `H`.
+    pub fn with_depth_and_hasher(depth: usize) -> Self {
+        Self::from_store(depth, MemoryStore::default())
+    }
+
+    /// O(1) copy of the tree. Both copies share their nodes and values until either
+    /// is written to, at which point the writer takes its own copy of the storage,
+    /// so changes to one never show up in the other. The node cache isn't shared.
+    pub fn snapshot(&self) -> Self {
+        self.fork()
+    }
+
+    /// Every key currently set, with its latest value, in ascending key order.
+    /// Inserting the pairs into an empty tree of the same depth rebuilds this one.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8; N], &[u8])> {
+        let mut leaves: Vec<_> = self
+            .store
+            .values
+            .iter()
+            .map(|(key, value)| (key, value.as_slice()))
+            .collect();
+        leaves.sort_unstable_by_key(|&(key, _)| key);
+        leaves.into_iter()
+    }
+
+    /// Keys set in `[start, end)`, with their values, in ascending key order. Walks
+    /// down only into non-empty subtrees that overlap the range, so the cost grows
+    /// with the number of keys returned rather than the size of the range. Panics
+    /// for `BitOrder::LsbFirst` trees, whose leaves aren't in key order.
+    pub fn range<'a>(
+        &'a self,
+        start: &'a [u8; N],
+        end: &'a [u8; N],
+    ) -> impl Iterator<Item = (&'a [u8; N], &'a [u8])> + 'a {
+        assert_eq!(
+            self.bit_order,
+            BitOrder::MsbFirst,
+            "range needs leaves in key order"
+        );
+        // Left children are pushed last so they're visited first.
+        let mut stack = vec![(0, [0; N])];
+        std::iter::from_fn(move || {
+            while let Some((level, prefix)) = stack.pop() {
+                if prefix < truncate(start, level)
+                    || prefix > truncate(end, level)
+                    || self.node(level, prefix) == self.default_nodes[level]
+                {
+                    continue;
+                }
+                if level < self.depth {
+                    stack.push((level + 1, with_bit(&prefix, level, true)));
+                    stack.push((level + 1, prefix));
+                } else if (start..end).contains(&&prefix) {
+                    if let Some((key, value)) = self.store.values.get_key_value(&prefix) {
+                        return Some((key, value.as_slice()));
+                    }
+                }
+            }
+            None
+        })
+    }
+}
+
+impl<'a, const N: usize, H: SmtHasher> IntoIterator for &'a Smt<N, H> {
+    type Item = (&'a [u8; N], &'a [u8]);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl<const N: usize, H: SmtHasher> IntoIterator for Smt<N, H> {
+    type Item = ([u8; N], Vec<u8>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut leaves: Vec<_> = Arc::unwrap_or_clone(self.store.values)
+            .into_iter()
+            .collect();
+        leaves.sort_unstable_by_key(|&(key, _)| key);
+        leaves.into_iter()
+    }
+}
+
+impl<const N: usize, H, S> Smt<N, H, S> {
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// The root as lowercase hex with a `0x` prefix, like `SmtProof::to_hex`.
+    pub fn root_hex(&self) -> String {
+        format!("0x{}", store::hex(&self.root))
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn flavor(&self) -> SmtFlavor {
+        self.flavor
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+}
+
+impl<const N: usize, H, S: NodeStore<N>> Smt<N, H, S> {
+    /// Number of keys currently set. Overwriting a key doesn't change it, and
+    /// deleting one (or writing the default value) decrements it. Trees that don't
+    /// retain values count their leaves instead, the stored nodes at full depth:
+    /// default nodes are never stored.
+    pub fn len(&self) -> usize {
+        if self.store_values {
+            self.store.value_count()
+        } else {
+            let nodes = self.store.all_nodes();
+            nodes
+                .iter()
+                .filter(|(level, _, _)| *level == self.depth)
+                .count()
+        }
+    }
+
+    /// Whether no key is set, in which case the root is the empty tree's.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
-impl SparseMerkleTree {
-    fn new() -> Self {
-        let mut default_nodes = vec![vec![0; 32]; TREE_DEPTH + 1];
-        for i in (0..TREE_DEPTH).rev() {
-            default_nodes[i] = Self::hash_pair(&default_nodes[i + 1], &default_nodes[i + 1]);
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    const MAX_DEPTH: usize = N * 8;
+
+    /// Creates a tree with `depth` levels on top of `store`. A store written by a
+    /// tree of another depth or hasher will produce garbage roots.
+    pub fn from_store(depth: usize, store: S) -> Self {
+        if let Err(err) = Self::check_depth(depth) {
+            panic!("{err}");
         }
+        let default_nodes = Self::default_nodes(depth);
+        let root = store.get(0, &[0; N]).unwrap_or(default_nodes[0]);
+        Self {
+            root,
+            depth,
+            flavor: SmtFlavor::Plain,
+            bit_order: BitOrder::MsbFirst,
+            default_value: None,
+            store_values: true,
+            default_nodes: default_nodes.into(),
+            store,
+            cache: NodeCache::default(),
+            proof_cache: ProofCache::default(),
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+            metrics: None,
+            undo_log: VecDeque::new(),
+            undo_depth: 0,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Copy of the tree sharing its store, which a store with copy-on-write clones
+    /// makes cheap. The copy starts with empty caches, undo log and checkpoints.
+    fn fork(&self) -> Self
+    where
+        S: Clone,
+    {
+        let mut cache = NodeCache::default();
+        cache.set_capacity(self.cache.capacity());
+        let mut proof_cache = ProofCache::default();
+        proof_cache.set_capacity(self.proof_cache.capacity());
         Self {
-            root: default_nodes[0].clone(),
-            default_nodes,
+            root: self.root,
+            depth: self.depth,
+            flavor: self.flavor,
+            bit_order: self.bit_order,
+            default_value: self.default_value.clone(),
+            store_values: self.store_values,
+            default_nodes: Arc::clone(&self.default_nodes),
+            store: self.store.clone(),
+            cache,
+            proof_cache,
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+            metrics: self.metrics.clone(),
+            undo_log: VecDeque::new(),
+            undo_depth: self.undo_depth,
+            hasher: PhantomData,
         }
     }
 
+    /// Roots of empty subtrees, indexed by level from the root (0) down to the leaves (`depth`).
+    fn default_nodes(depth: usize) -> Vec<[u8; 32]> {
+        let mut default_nodes = H::zero_hashes(depth);
+        default_nodes.reverse();
+        default_nodes
+    }
+
+    /// Roots of empty subtrees of a full-depth tree by height, from the empty leaf
+    /// (height 0) up to the empty root (height `8 * N`), the constants other
+    /// implementations hard-code. Call it as `<SparseMerkleTree>::zero_hashes()`.
+    pub fn zero_hashes() -> Vec<[u8; 32]> {
+        H::zero_hashes(Self::MAX_DEPTH)
+    }
+
+    /// Root of an empty subtree of `height` levels, or `None` above `8 * N`.
+    pub fn zero_hash_at(height: usize) -> Option<[u8; 32]> {
+        (height <= Self::MAX_DEPTH).then(|| H::zero_hashes(height)[height])
+    }
+
+    /// Switches an empty tree to another leaf hashing flavor. Proofs record the
+    /// flavor and only verify against trees of the same flavor.
+    pub fn with_flavor(mut self, flavor: SmtFlavor) -> Self {
+        assert!(
+            self.root == self.default_nodes[0],
+            "the flavor can only be changed on an empty tree"
+        );
+        self.flavor = flavor;
+        self.proof_cache.clear();
+        self
+    }
+
+    /// Switches an empty tree to reading key bits in another order, which moves
+    /// every leaf and so changes the root. Proofs record the order and only verify
+    /// under it. `range` and `generate_non_inclusion_proof` rely on leaves being in
+    /// key order and panic for `BitOrder::LsbFirst`; subtree prefixes are read as
+    /// paths, not keys.
+    pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        assert!(
+            self.root == self.default_nodes[0],
+            "the bit order can only be changed on an empty tree"
+        );
+        self.bit_order = bit_order;
+        self.proof_cache.clear();
+        self
+    }
+
+    /// Makes inserting `value` into an empty tree delete the key instead, as account
+    /// tries do for zero balances. The key then reads as `None` and has a non-inclusion
+    /// proof, exactly as if it had never been set. Without this every value, including
+    /// an empty one, is stored as plain data.
+    pub fn with_default_value(mut self, value: &[u8]) -> Self {
+        assert!(
+            self.root == self.default_nodes[0],
+            "the default value can only be set on an empty tree"
+        );
+        self.default_value = Some(value.to_vec());
+        self
+    }
+
+    /// The value treated as a deletion, if any. See `with_default_value`.
+    pub fn default_value(&self) -> Option<&[u8]> {
+        self.default_value.as_deref()
+    }
+
+    /// With `false`, makes an empty tree keep only the 32-byte leaf hash of each
+    /// value, for values too large to hold on to. Roots and proofs are the same,
+    /// but `try_get` fails with `ValueNotRetained` for keys that are set, and `get`
+    /// and `update` read `None` for them. Iteration, `entries`, `diff` and the undo
+    /// log only see retained values, so they find none, and serialization fails.
+    pub fn with_store_values(mut self, store_values: bool) -> Self {
+        assert!(
+            self.root == self.default_nodes[0],
+            "value retention can only be changed on an empty tree"
+        );
+        self.store_values = store_values;
+        self
+    }
+
+    pub fn stores_values(&self) -> bool {
+        self.store_values
+    }
+
+    fn is_default_value(&self, value: &[u8]) -> bool {
+        self.default_value.as_deref() == Some(value)
+    }
+
     /**
     ===============================
     Path derivation example
     ===============================
 
-     Key: [0b1101] (13 in decimal)
+     The path is the key read MSB-first: bit i of the path (i = 0 at the root)
+     is bit (7 - i % 8) of key[i / 8]. For a 16-byte key this is the same as
+     `key_to_path(key)`, i.e. the key as a big-endian u128, walked from its top bit.
+
+     Key:        [0b1101_0000, 0, 0, ..]
      Tree depth: 4 bits
 
-     Initialize:
-       path = 0b0000
-       key  = 0b1101
-
-     i = 3 (most significant bit):
-       Extract: key & (1 << 3) = 0b1101 & 0b1000 = 0b1000  // Isolate leftmost bit
-       Shift:   0b1000 << 3    = 0b1000                    // Position the bit
-       Update:  path |= 0b1000                             // Set the bit in path
-       Result:  path = 0b1000
-
-     i = 2:
-       Extract: key & (1 << 2) = 0b1101 & 0b0100 = 0b0100  // Isolate second bit
-       Shift:   0b0100 << 2    = 0b0100
-       Update:  path |= 0b0100
-       Result:  path = 0b1100
-
-     i = 1:
-       Extract: key & (1 << 1) = 0b1101 & 0b0010 = 0b0000  // Isolate third bit
-       Shift:   0b0000 << 1    = 0b0000
-       Update:  path |= 0b0000                             // No change to path
-       Result:  path = 0b1100 (unchanged)
-
-     i = 0 (least significant bit):
-       Extract: key & (1 << 0) = 0b1101 & 0b0001 = 0b0001  // Isolate rightmost bit
-       Shift:   0b0001 << 0    = 0b0001
-       Update:  path |= 0b0001
-       Result:  path = 0b1101
-
-     Final result: path = 0b1101
-
-     This path (1101) represents the following tree traversal:
-       1 - Go right at the first level (from the root)
-       1 - Go right at the second level
-       0 - Go left at the third level
-       1 - Go right at the fourth level (to the leaf)
+     i = 0: key[0] & 0b1000_0000 != 0  -> 1, go right from the root
+     i = 1: key[0] & 0b0100_0000 != 0  -> 1, go right
+     i = 2: key[0] & 0b0010_0000 == 0  -> 0, go left
+     i = 3: key[0] & 0b0001_0000 != 0  -> 1, go right (to the leaf)
+
+     Final result: path = 1101, the leaf at index 13 of 16.
 
+     Bits 4.. of the key are past the depth and must be zero. Keys differing only
+     in their last significant bit are siblings at the leaf level.
+
+    Returns the new root, as do the other writes except `update`.
     */
+    pub fn insert(&mut self, key: &[u8; N], value: &[u8]) -> [u8; 32] {
+        self.update(key, value);
+        self.root
+    }
+
+    /// Inserts or overwrites `key`, returning the value it previously held rather than
+    /// the new root. Writing the default value (see `with_default_value`) deletes the key.
+    pub fn update(&mut self, key: &[u8; N], value: &[u8]) -> Option<Vec<u8>> {
+        if self.is_default_value(value) {
+            return self.remove(key);
+        }
+        let path = self.path(key); // see above example for details
+        self.update_path(path, self.hash_value(&path, value));
+        if !self.store_values {
+            return None;
+        }
+        self.count_write();
+        let previous = self.store.put_value(*key, value.to_vec());
+        self.log_op(key, previous.as_deref(), Some(value));
+        previous
+    }
 
-    fn insert(&mut self, key: &[u8; 16], value: &[u8]) {
-        let mut current_node = Self::hash_leaf(value);
-        let mut path = 0u128;
+    /// Inserts all entries, then rehashes the union of their paths once, level by level.
+    /// Later entries win over earlier ones with the same key, as with sequential inserts,
+    /// and entries holding the default value delete their key.
+    pub fn insert_batch(&mut self, entries: &[([u8; N], Vec<u8>)]) -> [u8; 32] {
+        let leaves = self.batch_leaves(entries);
+        let nodes = self.rehash_batch(self.depth, leaves, 0);
+        self.apply_batch(entries, nodes);
+        self.root
+    }
 
-        for i in (0..TREE_DEPTH).rev() {
-            path |= (key[i / 8] as u128 & (1 << (i % 8))) << i;
-            let sibling = if path & (1 << i) == 0 {
-                &self.default_nodes[i + 1]
+    /// Leaf hashes of `entries` sorted by path, keeping the last entry for each key.
+    fn batch_leaves(&self, entries: &[([u8; N], Vec<u8>)]) -> Vec<([u8; N], [u8; 32])> {
+        let mut leaves: HashMap<[u8; N], [u8; 32]> = HashMap::with_capacity(entries.len());
+        for (key, value) in entries {
+            let path = self.path(key);
+            let leaf = if self.is_default_value(value) {
+                self.default_nodes[self.depth]
             } else {
-                &self.default_nodes[i + 1]
+                self.hash_value(&path, value)
             };
-            current_node = if path & (1 << i) == 0 {
-                Self::hash_pair(&current_node, sibling)
+            leaves.insert(path, leaf);
+        }
+        let mut leaves: Vec<_> = leaves.into_iter().collect();
+        leaves.sort_unstable_by_key(|&(path, _)| path);
+        leaves
+    }
+
+    /// Given new `nodes` at level `from`, sorted by prefix, computes every node that
+    /// changes above them up to level `to`. Nothing is written; siblings outside
+    /// the batch are read from the tree.
+    fn rehash_batch(
+        &self,
+        from: usize,
+        nodes: Vec<([u8; N], [u8; 32])>,
+        to: usize,
+    ) -> Vec<(NodePosition<N>, [u8; 32])> {
+        let mut prefixes: Vec<[u8; N]> = nodes.iter().map(|&(prefix, _)| prefix).collect();
+        let mut updated: HashMap<NodePosition<N>, [u8; 32]> = nodes
+            .into_iter()
+            .map(|(prefix, node)| ((from, prefix), node))
+            .collect();
+        let node = |updated: &HashMap<_, _>, level, prefix| {
+            updated
+                .get(&(level, prefix))
+                .copied()
+                .unwrap_or_else(|| self.node(level, prefix))
+        };
+
+        for i in (to..from).rev() {
+            // Truncating keeps the prefixes sorted, so shared ancestors collapse with a plain dedup.
+            for prefix in prefixes.iter_mut() {
+                *prefix = truncate(prefix, i);
+            }
+            prefixes.dedup();
+
+            for &prefix in &prefixes {
+                let parent = self.hash_children(
+                    i,
+                    &node(&updated, i + 1, prefix),
+                    &node(&updated, i + 1, with_bit(&prefix, i, true)),
+                );
+                updated.insert((i, prefix), parent);
+            }
+        }
+        updated.into_iter().collect()
+    }
+
+    /// Writes the values and rehashed nodes of a batch and picks up the new root.
+    fn apply_batch(
+        &mut self,
+        entries: &[([u8; N], Vec<u8>)],
+        nodes: Vec<(NodePosition<N>, [u8; 32])>,
+    ) {
+        for (key, value) in entries {
+            self.count_write();
+            if self.is_default_value(value) {
+                let previous = self.store.delete_value(key);
+                if previous.is_some() {
+                    self.log_op(key, previous.as_deref(), None);
+                }
+            } else if self.store_values {
+                let previous = self.store.put_value(*key, value.clone());
+                self.log_op(key, previous.as_deref(), Some(value));
+            }
+        }
+        for ((level, prefix), node) in nodes {
+            self.set_node(level, prefix, node);
+        }
+        self.root = self.node(0, [0; N]);
+    }
+
+    /// Removes `key`, restoring its leaf to the default node. Deleting an absent key is a no-op.
+    pub fn delete(&mut self, key: &[u8; N]) -> [u8; 32] {
+        self.remove(key);
+        self.root
+    }
+
+    /// Like `delete`, returning the value `key` held.
+    fn remove(&mut self, key: &[u8; N]) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        self.count_write();
+        let previous = self.store.delete_value(key);
+        if let Some(previous) = &previous {
+            self.log_op(key, Some(previous), None);
+        } else if self.store_values || !self.contains_key(key) {
+            return None;
+        }
+        self.update_path(path, self.default_nodes[self.depth]);
+        previous
+    }
+
+    /// Writes `leaf` at the end of `path` and rehashes every node up to the root.
+    /// Rewriting a leaf with the hash it already has leaves the path untouched.
+    fn update_path(&mut self, path: [u8; N], leaf: [u8; 32]) {
+        if self.load_node(self.depth, path) == leaf {
+            return;
+        }
+        let mut current_node = leaf;
+        self.set_node(self.depth, path, current_node);
+
+        for i in (0..self.depth).rev() {
+            let sibling = self.load_node(i + 1, Self::sibling(&path, i + 1));
+            current_node = if !bit(&path, i) {
+                self.hash_children(i, &current_node, &sibling)
             } else {
-                Self::hash_pair(sibling, &current_node)
+                self.hash_children(i, &sibling, &current_node)
             };
+            self.set_node(i, truncate(&path, i), current_node);
         }
         self.root = current_node;
     }
 
-    fn generate_proof(&self, key: &[u8; 16]) -> Vec<Vec<u8>> {
-        let mut proof = Vec::new();
-        let mut path = 0u128;
+    /// Value stored under `key`, or `None` if the key was never set. Also `None`
+    /// for keys whose value isn't retained, see `try_get`.
+    pub fn get(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.count_read();
+        self.store.get_value(key)
+    }
+
+    /// Like `get`, but fails with `ValueNotRetained` for a key that is set in a
+    /// tree that doesn't keep values (see `with_store_values`), rather than
+    /// reading as absent.
+    pub fn try_get(&self, key: &[u8; N]) -> Result<Option<Vec<u8>>, SmtError> {
+        if !self.store_values && self.contains_key(key) {
+            return Err(SmtError::ValueNotRetained);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Whether `key` is set, empty values included, without reading its value: a
+    /// key is set exactly when its leaf isn't the default node.
+    pub fn contains_key(&self, key: &[u8; N]) -> bool {
+        self.node(self.depth, self.bit_order.path(key, self.depth))
+            != self.default_nodes[self.depth]
+    }
 
-        for i in (0..TREE_DEPTH).rev() {
-            path |= (key[i / 8] as u128 & (1 << (i % 8))) << i; // see above example for details
-            proof.push(self.default_nodes[i + 1].clone());
+    /// For an absent key this is a non-inclusion proof, verified with `value = None`.
+    pub fn generate_proof(&self, key: &[u8; N]) -> SmtProof {
+        if self.proof_cache.capacity() == 0 {
+            return self.proof_for_path(self.path(key), false);
         }
+        if let Some(proof) = self.proof_cache.get(key, &self.root) {
+            return proof;
+        }
+        let proof = self.proof_for_path(self.path(key), false);
+        self.proof_cache.insert(*key, &self.root, proof.clone());
         proof
     }
 
-    fn verify_proof(&self, key: &[u8; 16], value: Option<&[u8]>, proof: &[Vec<u8>]) -> bool {
-        let mut current_node = value.map_or_else(
-            || self.default_nodes[TREE_DEPTH].clone(),
-            |v| Self::hash_leaf(v),
+    /// `value = None` proves `key` is absent, `Some(value)` that it holds `value`. An
+    /// empty value is an entry like any other: it is proven with `Some(b"")`, and a
+    /// key holding it has no non-inclusion proof. To check a proof against a bare
+    /// root, without the tree, use `SmtVerifier::verify`, which this is.
+    ///
+    /// Fails with `DepthMismatch`, `FlavorMismatch` or `BitOrderMismatch` for proofs
+    /// generated for a different depth, flavor or bit order, and like
+    /// `SmtVerifier::verify` for malformed proofs.
+    pub fn verify_proof(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        self.verifier().verify(key, value, proof)
+    }
+
+    /// Path of an arbitrary-length key: the first `key_len()` bytes of `H::hash(key)`,
+    /// with the bits past the tree depth cleared. Distinct keys collide only if their
+    /// hashes share the first `depth` bits.
+    pub fn hash_key(&self, key: &[u8]) -> [u8; N] {
+        Self::hash_key_at(key, self.depth)
+    }
+
+    /// Like `insert`, with the path derived from `key` by `hash_key`.
+    pub fn insert_hashed(&mut self, key: &[u8], value: &[u8]) -> [u8; 32] {
+        self.insert(&self.hash_key(key), value)
+    }
+
+    pub fn get_hashed(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(&self.hash_key(key))
+    }
+
+    pub fn delete_hashed(&mut self, key: &[u8]) -> [u8; 32] {
+        self.delete(&self.hash_key(key))
+    }
+
+    /// Proof for a key inserted with `insert_hashed`, marked as such.
+    pub fn generate_proof_hashed(&self, key: &[u8]) -> SmtProof {
+        self.proof_for_path(self.path(&self.hash_key(key)), true)
+    }
+
+    pub fn verify_proof_hashed(
+        &self,
+        key: &[u8],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        Self::verify_hashed(&self.verifier(), key, value, proof)
+    }
+
+    /// Stateless counterpart of `verify_proof_hashed`, against the root and tree
+    /// shape of `verifier` as `SmtVerifier::verify`. A proof for a raw key verifies
+    /// as `false`.
+    pub fn verify_hashed(
+        verifier: &SmtVerifier<N, H>,
+        key: &[u8],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        verifier.check_proof(proof)?;
+        let depth = verifier.depth();
+        let path = verifier
+            .bit_order()
+            .path(&Self::hash_key_at(key, depth), depth);
+        Ok(proof.hashed_key && ct_eq(&Self::fold_path(&path, value, proof), &verifier.root()))
+    }
+
+    fn hash_key_at(key: &[u8], depth: usize) -> [u8; N] {
+        let mut path = [0u8; N];
+        path.copy_from_slice(&H::hash(key)[..N]);
+        truncate(&path, depth)
+    }
+
+    fn proof_for_path(&self, path: [u8; N], hashed_key: bool) -> SmtProof {
+        self.proof_for_node(self.depth, path, hashed_key)
+    }
+
+    /// Siblings from the node at `level` on `path` up to the root, as a proof of depth `level`.
+    fn proof_for_node(&self, level: usize, path: [u8; N], hashed_key: bool) -> SmtProof {
+        let siblings = (0..level)
+            .rev()
+            .map(|i| self.node(i + 1, Self::sibling(&path, i + 1)))
+            .collect();
+        SmtProof {
+            depth: level,
+            flavor: self.flavor,
+            hashed_key,
+            bit_order: self.bit_order,
+            siblings,
+        }
+    }
+
+    fn check_depth(depth: usize) -> Result<(), SmtError> {
+        proof::check_depth::<N>(depth)
+    }
+
+    fn check_key(key: &[u8; N], depth: usize) -> Result<(), SmtError> {
+        proof::check_key(key, depth)
+    }
+
+    fn fold_path(path: &[u8; N], value: Option<&[u8]>, proof: &SmtProof) -> [u8; 32] {
+        proof::fold_path::<N, H>(path, value, proof)
+    }
+
+    fn fold_node(path: &[u8; N], node: [u8; 32], siblings: &[[u8; 32]]) -> [u8; 32] {
+        proof::fold_node::<N, H>(path, node, siblings)
+    }
+
+    /// Root after changing `key` from `old_value` to `new_value`, given only a
+    /// verifier for the old root and a proof for `key` against it. `None` values
+    /// stand for an absent key, so this covers inserts, updates and deletions.
+    /// Returns `Ok(None)` if the proof doesn't verify `old_value` under the old
+    /// root, and fails like `SmtVerifier::verify` if it's malformed or from another
+    /// kind of tree.
+    pub fn compute_root_after_update(
+        verifier: &SmtVerifier<N, H>,
+        key: &[u8; N],
+        old_value: Option<&[u8]>,
+        new_value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<Option<[u8; 32]>, SmtError> {
+        let path = verifier.bit_order().path(key, verifier.depth());
+        Ok(verifier
+            .verify(key, old_value, proof)?
+            .then(|| Self::fold_path(&path, new_value, proof)))
+    }
+
+    /// Root of the subtree holding every key whose first `prefix_bits` bits match
+    /// `prefix`, or the default node at that level if none is set. Bits of `prefix`
+    /// past `prefix_bits` are ignored, so a full key can be passed. Two trees that
+    /// agree on every key under the prefix have the same subtree root.
+    pub fn subtree_root(&self, prefix: &[u8], prefix_bits: usize) -> [u8; 32] {
+        self.node(prefix_bits, self.subtree_path(prefix, prefix_bits))
+    }
+
+    /// Proof of `subtree_root(prefix, prefix_bits)` against the root, made of the
+    /// `prefix_bits` siblings above the subtree. Its depth is `prefix_bits`, so it
+    /// only verifies with `verify_subtree`.
+    pub fn generate_subtree_proof(&self, prefix: &[u8], prefix_bits: usize) -> SmtProof {
+        self.proof_for_node(prefix_bits, self.subtree_path(prefix, prefix_bits), false)
+    }
+
+    /// Verifies that `subtree_root` is the node under `prefix` in the tree committed to
+    /// by `root`. Fails with `DepthMismatch` or `InvalidProofLength` if the proof doesn't
+    /// have `prefix_bits` siblings, `InvalidDepth` if `prefix_bits` is more than a key
+    /// holds and `PrefixLength` if `prefix` is too short for it.
+    pub fn verify_subtree(
+        root: &[u8; 32],
+        prefix: &[u8],
+        prefix_bits: usize,
+        subtree_root: &[u8; 32],
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        let path = Self::prefix_path(prefix, prefix_bits)?;
+        if proof.depth != prefix_bits {
+            return Err(SmtError::DepthMismatch {
+                expected: prefix_bits,
+                actual: proof.depth,
+            });
+        }
+        if proof.siblings.len() != proof.depth {
+            return Err(SmtError::InvalidProofLength {
+                expected: proof.depth,
+                actual: proof.siblings.len(),
+            });
+        }
+        Ok(ct_eq(
+            &Self::fold_node(&path, *subtree_root, &proof.siblings),
+            root,
+        ))
+    }
+
+    fn subtree_path(&self, prefix: &[u8], prefix_bits: usize) -> [u8; N] {
+        assert!(
+            prefix_bits <= self.depth,
+            "prefix_bits must be at most the tree depth of {}",
+            self.depth
         );
-        let mut path = 0u128;
+        Self::prefix_path(prefix, prefix_bits).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// The first `bits` bits of `prefix` as a path, with the rest cleared.
+    fn prefix_path(prefix: &[u8], bits: usize) -> Result<[u8; N], SmtError> {
+        if bits > Self::MAX_DEPTH {
+            return Err(SmtError::InvalidDepth {
+                depth: bits,
+                max: Self::MAX_DEPTH,
+            });
+        }
+        let len = bits.div_ceil(8);
+        if prefix.len() < len {
+            return Err(SmtError::PrefixLength {
+                bits,
+                len: prefix.len(),
+            });
+        }
+        let mut path = [0u8; N];
+        path[..len].copy_from_slice(&prefix[..len]);
+        Ok(truncate(&path, bits))
+    }
+
+    pub fn compress_proof(&self, proof: &SmtProof) -> CompressedProof<N> {
+        let mut bitmask = [0u8; N];
+        let mut siblings = Vec::new();
+        for (j, sibling) in proof.siblings.iter().enumerate().take(self.depth) {
+            if *sibling != self.default_nodes[self.depth - j] {
+                bitmask = with_bit(&bitmask, j, true);
+                siblings.push(*sibling);
+            }
+        }
+        CompressedProof {
+            bitmask,
+            flavor: proof.flavor,
+            hashed_key: proof.hashed_key,
+            siblings,
+        }
+    }
+
+    /// Expands a compressed proof. Fails with `InvalidBitmask` if bits are set past
+    /// the tree depth and `InvalidProofLength` if the bitmask doesn't match the
+    /// siblings provided.
+    pub fn decompress_proof(&self, compressed: &CompressedProof<N>) -> Result<SmtProof, SmtError> {
+        if truncate(&compressed.bitmask, self.depth) != compressed.bitmask {
+            return Err(SmtError::InvalidBitmask);
+        }
+        let set_bits: u32 = compressed.bitmask.iter().map(|b| b.count_ones()).sum();
+        if set_bits as usize != compressed.siblings.len() {
+            return Err(SmtError::InvalidProofLength {
+                expected: set_bits as usize,
+                actual: compressed.siblings.len(),
+            });
+        }
+        let mut siblings = compressed.siblings.iter().copied();
+        let siblings = (0..self.depth)
+            .map(|j| {
+                if bit(&compressed.bitmask, j) {
+                    siblings.next().unwrap()
+                } else {
+                    self.default_nodes[self.depth - j]
+                }
+            })
+            .collect();
+        Ok(SmtProof {
+            depth: self.depth,
+            flavor: compressed.flavor,
+            hashed_key: compressed.hashed_key,
+            bit_order: self.bit_order,
+            siblings,
+        })
+    }
+
+    pub fn verify_compressed(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        compressed: &CompressedProof<N>,
+    ) -> Result<bool, SmtError> {
+        self.verify_proof(key, value, &self.decompress_proof(compressed)?)
+    }
+
+    /// Stored node at `level` with the given path prefix, or the default node if that subtree is empty.
+    fn node(&self, level: usize, prefix: [u8; N]) -> [u8; 32] {
+        self.cache
+            .peek(&(level, prefix))
+            .copied()
+            .unwrap_or_else(|| {
+                self.count_read();
+                self.store
+                    .get(level, &prefix)
+                    .unwrap_or(self.default_nodes[level])
+            })
+    }
+
+    /// Like `node`, keeping the result in the cache.
+    fn load_node(&mut self, level: usize, prefix: [u8; N]) -> [u8; 32] {
+        if let Some(node) = self.cache.get(&(level, prefix)) {
+            return *node;
+        }
+        let node = self.node(level, prefix);
+        self.cache.insert((level, prefix), node);
+        node
+    }
+
+    /// Stores a node, deleting it instead if it equals the default so the store stays sparse.
+    fn set_node(&mut self, level: usize, prefix: [u8; N], node: [u8; 32]) {
+        self.count_write();
+        if node == self.default_nodes[level] {
+            self.store.delete(level, &prefix);
+        } else {
+            self.store.put(level, prefix, node);
+        }
+        self.cache.insert((level, prefix), node);
+    }
+
+    /// Number of node hashes kept in memory in front of the store, 0 (the default)
+    /// if caching is off. Roots and proofs are the same either way.
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    /// Caches up to `capacity` recently used node hashes, saving store reads when
+    /// writes keep touching the same paths, e.g. keys with a common prefix. Mostly
+    /// useful for stores slower than `MemoryStore`.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache.set_capacity(capacity);
+    }
+
+    /// Number of proofs `generate_proof` keeps for reuse, 0 (the default) if
+    /// proof caching is off.
+    pub fn proof_cache_capacity(&self) -> usize {
+        self.proof_cache.capacity()
+    }
+
+    /// Keeps up to `capacity` recently generated proofs, so asking again for the
+    /// proof of a key costs a copy of it rather than `depth` node reads. Proofs
+    /// are kept for the current root only: the first call to `generate_proof`
+    /// after the root changes drops them all.
+    pub fn set_proof_cache_capacity(&mut self, capacity: usize) {
+        self.proof_cache.set_capacity(capacity);
+    }
+
+    /// Whether both trees have the same root, ignoring the depth that `==` also
+    /// compares.
+    pub fn root_eq<T: NodeStore<N>>(&self, other: &Smt<N, H, T>) -> bool {
+        self.root == other.root
+    }
+
+    /// Every key set with its value, in ascending key order, read from the stored
+    /// values alone. Works with any store, unlike `iter`, and feeding the result to
+    /// `from_entries` (or `from_sorted_entries`) rebuilds a full-depth tree.
+    pub fn entries(&self) -> Vec<([u8; N], Vec<u8>)> {
+        let mut entries = self.store.all_values();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        entries
+    }
+
+    /// Number of leading key bytes that carry path bits for this tree's depth.
+    pub fn key_len(&self) -> usize {
+        self.depth.div_ceil(8)
+    }
+
+    /// The key read as a bit path in the tree's bit order, with the root branch in
+    /// the most significant bit of the path's first byte.
+    fn path(&self, key: &[u8; N]) -> [u8; N] {
+        if let Err(err) = Self::check_key(key, self.depth) {
+            panic!("{err}");
+        }
+        self.bit_order.path(key, self.depth)
+    }
+
+    /// Prefix of the sibling of the node at `level` (>= 1) on `path`.
+    fn sibling(path: &[u8; N], level: usize) -> [u8; N] {
+        with_bit(&truncate(path, level), level - 1, !bit(path, level - 1))
+    }
+
+    fn leaf_hash(flavor: SmtFlavor, path: &[u8; N], value: &[u8]) -> [u8; 32] {
+        proof::leaf_hash::<N, H>(flavor, path, value)
+    }
 
-        for i in (0..TREE_DEPTH).rev() {
-            path |= (key[i / 8] as u128 & (1 << (i % 8))) << i;
-            current_node = if path & (1 << i) == 0 {
-                Self::hash_pair(&current_node, &proof[TREE_DEPTH - 1 - i])
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        #[cfg(test)]
+        HASH_PAIR_CALLS.with(|calls| calls.set(calls.get() + 1));
+        H::hash_pair(left, right)
+    }
+
+    /// Paths of the non-default leaves in ascending order, found by walking down
+    /// the non-default nodes only.
+    fn leaf_paths(&self) -> Vec<[u8; N]> {
+        let mut paths = Vec::new();
+        let mut stack = vec![(0, [0; N])];
+        while let Some((level, prefix)) = stack.pop() {
+            if self.node(level, prefix) == self.default_nodes[level] {
+                continue;
+            }
+            if level == self.depth {
+                paths.push(prefix);
             } else {
-                Self::hash_pair(&proof[TREE_DEPTH - 1 - i], &current_node)
-            };
+                stack.push((level + 1, with_bit(&prefix, level, true)));
+                stack.push((level + 1, prefix));
+            }
+        }
+        paths
+    }
+}
+
+/// Trees are equal when they commit to the same content: the same root at the same
+/// depth. The hasher is part of the type, so trees with different hashers don't
+/// compare at all. Stores, caches and insertion order don't matter.
+impl<const N: usize, H, S, T> PartialEq<Smt<N, H, T>> for Smt<N, H, S> {
+    fn eq(&self, other: &Smt<N, H, T>) -> bool {
+        self.root == other.root && self.depth == other.depth
+    }
+}
+
+impl<const N: usize, H, S> Eq for Smt<N, H, S> {}
+
+/// The root as hex, the depth, the number of keys set and the flavor, without
+/// any nodes. Use `Display` to list the leaves.
+impl<const N: usize, H, S: NodeStore<N>> fmt::Debug for Smt<N, H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Smt")
+            .field("root", &format_args!("0x{}", store::hex(&self.root)))
+            .field("depth", &self.depth)
+            .field("len", &self.len())
+            .field("flavor", &self.flavor)
+            .finish()
+    }
+}
+
+/// A summary line followed by one line per set key, in path order (key order
+/// unless bits are read `LsbFirst`): the first `key_len()` bytes of the path and the first 4 bytes of the leaf hash, in hex.
+///
+/// ```text
+/// root 6b5e998d, depth 4, 3 leaves
+///   50 022a6979
+///   60 597fcb31
+/// ```
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> fmt::Display for Smt<N, H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self.leaf_paths();
+        write!(
+            f,
+            "root {}, depth {}, {} leaves",
+            store::hex(&self.root[..4]),
+            self.depth,
+            paths.len()
+        )?;
+        for path in paths {
+            let leaf = self.node(self.depth, path);
+            write!(
+                f,
+                "\n  {} {}",
+                store::hex(&path[..self.key_len()]),
+                store::hex(&leaf[..4])
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized form of a tree: its depth, root and leaves in key order. Default
+/// and internal nodes are rebuilt from the leaves, and the rebuilt root must match,
+/// so trees that don't store values (see `with_store_values`) can't be serialized.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SmtState {
+    depth: usize,
+    #[serde(default)]
+    flavor: SmtFlavor,
+    #[serde(default)]
+    bit_order: BitOrder,
+    #[serde(default)]
+    default_value: Option<Vec<u8>>,
+    root: Vec<u8>,
+    leaves: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[cfg(feature = "serde")]
+impl<const N: usize, H: SmtHasher> serde::Serialize for Smt<N, H> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.store_values {
+            return Err(serde::ser::Error::custom(
+                "a tree that doesn't store values can't be rebuilt from its leaves",
+            ));
+        }
+        let leaves = self
+            .iter()
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect();
+        SmtState {
+            depth: self.depth,
+            flavor: self.flavor,
+            bit_order: self.bit_order,
+            default_value: self.default_value.clone(),
+            root: self.root.to_vec(),
+            leaves,
         }
-        current_node == self.root
+        .serialize(serializer)
     }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize, H: SmtHasher> serde::Deserialize<'de> for Smt<N, H> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
 
-    fn hash_leaf(leaf: &[u8]) -> Vec<u8> {
-        Sha256::digest(leaf).to_vec()
+        let state = SmtState::deserialize(deserializer)?;
+        Self::check_depth(state.depth).map_err(D::Error::custom)?;
+        let mut tree = Self::with_depth_and_hasher(state.depth)
+            .with_flavor(state.flavor)
+            .with_bit_order(state.bit_order);
+        tree.default_value = state.default_value;
+        let mut entries = Vec::with_capacity(state.leaves.len());
+        for (key, value) in state.leaves {
+            let key: [u8; N] = key
+                .try_into()
+                .map_err(|_| D::Error::custom(format!("keys must be {N} bytes")))?;
+            Self::check_key(&key, state.depth).map_err(D::Error::custom)?;
+            entries.push((key, value));
+        }
+        tree.insert_batch(&entries);
+        if tree.root[..] != state.root {
+            return Err(D::Error::custom("leaves don't match the serialized root"));
+        }
+        Ok(tree)
     }
+}
+
+/// Path of a 16-byte key: the key as a big-endian integer, so the root branch is
+/// the most significant bit and the leaf branch the least significant bit. A tree
+/// of depth `d` reads the top `d` bits of the path. This is the path the tree
+/// itself derives for `BitOrder::MsbFirst`, as a number.
+pub fn key_to_path(key: &[u8; 16]) -> u128 {
+    u128::from_be_bytes(*key)
+}
+
+/// The key whose path is `path`, the inverse of `key_to_path`.
+pub fn path_to_key(path: u128) -> [u8; 16] {
+    path.to_be_bytes()
+}
 
-    fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(left);
-        hasher.update(right);
-        hasher.finalize().to_vec()
+/// `bits` with bit `i`, counted as in `bit`, set to `value`.
+fn with_bit<const N: usize>(bits: &[u8; N], i: usize, value: bool) -> [u8; N] {
+    let mut bits = *bits;
+    if value {
+        bits[i / 8] |= 0x80 >> (i % 8);
+    } else {
+        bits[i / 8] &= !(0x80 >> (i % 8));
     }
+    bits
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
 
     fn setup_tree() -> SparseMerkleTree {
-        let mut tree = SparseMerkleTree::new();
+        setup_tree_with_hasher()
+    }
+
+    fn setup_tree_with_hasher<H: SmtHasher>() -> SparseMerkleTree<H> {
+        let mut tree = SparseMerkleTree::<H>::with_hasher();
 
         let key1 = [0u8; 16];
         let value1 = b"value1";
@@ -154,6 +1225,43 @@ mod tests {
         assert_ne!(tree.root, SparseMerkleTree::new().root);
     }
 
+    #[test]
+    fn test_writes_return_new_root() {
+        let key = |path: u8| {
+            let mut key = [0u8; 16];
+            key[0] = path;
+            key
+        };
+        let mut tree = SparseMerkleTree::with_depth(4);
+        let empty_root = tree.root();
+        assert_eq!(
+            hex(&empty_root),
+            "30765fef341bdfe749c391bf956a9f03d363941b2eb8f85ab16bb6eb0d3c4def"
+        );
+        assert_eq!(
+            hex(&tree.insert(&key(0x50), b"a")),
+            "54ff378a04f04aca02c084b52de6917192a9fc6423f25eef4432c4397f3eac20"
+        );
+        assert_eq!(
+            hex(&tree.insert(&key(0xc0), b"b")),
+            "29d4995d58aef4a58e3262d955a78bb367c0ca2aa7680d319968042ec1bf169b"
+        );
+        let root = tree.insert_batch(&[(key(0x60), b"c".to_vec())]);
+        assert_eq!(
+            hex(&root),
+            "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d"
+        );
+        assert_eq!(
+            hex(&tree.delete(&key(0xc0))),
+            "57b2d54e88da2ace20f4aef93fa74893bf8081da7ab7fd3f2d52e30814f74dc5"
+        );
+        // Deleting an absent key changes nothing and returns the same root.
+        assert_eq!(tree.delete(&key(0xc0)), tree.root());
+        tree.delete(&key(0x50));
+        assert_eq!(tree.delete_hashed(b"absent"), tree.root());
+        assert_eq!(tree.delete(&key(0x60)), empty_root);
+    }
+
     #[test]
     fn test_inclusion_proof() {
         let tree = setup_tree();
@@ -161,17 +1269,1062 @@ mod tests {
         let value1 = b"value3";
 
         let proof1 = tree.generate_proof(&key1);
-        assert!(tree.verify_proof(&key1, Some(value1), &proof1));
+        assert_eq!(tree.verify_proof(&key1, Some(value1), &proof1), Ok(true));
     }
 
     #[test]
-    fn test_non_inclusion_proof() {
+    fn test_verify_proof_checks_sibling_count() {
         let tree = setup_tree();
-        let non_existent_key = [2u8; 16];
-
-        let proof_non_existent = tree.generate_proof(&non_existent_key);
-        assert!(!tree.verify_proof(&non_existent_key, None, &proof_non_existent));
-    }
+        let key = [2u8; 16];
+        let proof = tree.generate_proof(&key);
+        for len in [0, 1, 127, 128, 129, 256] {
+            let mut resized = proof.clone();
+            resized.siblings.resize(len, [0; 32]);
+            let expected = if len == 128 {
+                Ok(true)
+            } else {
+                Err(SmtError::InvalidProofLength {
+                    expected: 128,
+                    actual: len,
+                })
+            };
+            assert_eq!(tree.verify_proof(&key, Some(b"value3"), &resized), expected);
+            assert_eq!(
+                tree.verifier().verify(&key, Some(b"value3"), &resized),
+                expected
+            );
+            assert_eq!(
+                tree.verify_proof_streaming(&key, Some(b"value3"), &resized.siblings),
+                len == 128
+            );
+        }
+    }
+
+    #[test]
+    fn test_inclusion_proofs_share_root() {
+        let mut tree = SparseMerkleTree::new();
+        let key1 = [0u8; 16];
+        let key2 = [1u8; 16];
+        tree.insert(&key1, b"value1");
+        tree.insert(&key2, b"value2");
+
+        let proof1 = tree.generate_proof(&key1);
+        let proof2 = tree.generate_proof(&key2);
+        assert_eq!(tree.verify_proof(&key1, Some(b"value1"), &proof1), Ok(true));
+        assert_eq!(tree.verify_proof(&key2, Some(b"value2"), &proof2), Ok(true));
+    }
+
+    #[test]
+    fn test_proofs_for_keys_sharing_a_long_prefix() {
+        let mut tree = SparseMerkleTree::new();
+        // The keys differ only in their last bit, so they are siblings at the leaves.
+        let key1 = [0xab; 16];
+        let mut key2 = key1;
+        key2[15] ^= 1;
+        tree.insert(&key1, b"value1");
+        tree.insert(&key2, b"value2");
+
+        let proof1 = tree.generate_proof(&key1);
+        let proof2 = tree.generate_proof(&key2);
+        assert_eq!(proof1.siblings[0], tree.node(128, key2));
+        assert_eq!(proof2.siblings[0], tree.node(128, key1));
+        assert_ne!(proof1.siblings[0], tree.default_nodes[128]);
+        // Every other sibling is an empty subtree.
+        assert_eq!(proof1.siblings[1..], proof2.siblings[1..]);
+        for (j, sibling) in proof1.siblings.iter().enumerate().skip(1) {
+            assert_eq!(*sibling, tree.default_nodes[128 - j]);
+        }
+        assert_eq!(tree.verify_proof(&key1, Some(b"value1"), &proof1), Ok(true));
+        assert_eq!(tree.verify_proof(&key2, Some(b"value2"), &proof2), Ok(true));
+        assert_eq!(
+            tree.verify_proof(&key1, Some(b"value2"), &proof1),
+            Ok(false)
+        );
+
+        // A default sibling in place of the other leaf no longer matches the root.
+        let mut stale = proof1;
+        stale.siblings[0] = tree.default_nodes[128];
+        assert_eq!(tree.verify_proof(&key1, Some(b"value1"), &stale), Ok(false));
+    }
+
+    #[test]
+    fn test_get() {
+        let mut tree = setup_tree();
+        assert_eq!(tree.get(&[1u8; 16]), Some(b"value2".to_vec()));
+        assert_eq!(tree.get(&[3u8; 16]), None);
+
+        tree.insert(&[1u8; 16], b"updated");
+        assert_eq!(tree.get(&[1u8; 16]), Some(b"updated".to_vec()));
+
+        tree.insert(&[3u8; 16], b"");
+        assert_eq!(tree.get(&[3u8; 16]), Some(vec![]));
+    }
+
+    #[test]
+    fn test_entries_round_trip() {
+        let mut tree = setup_tree();
+        tree.insert(&[0xff; 16], b"");
+        tree.insert(&[5; 16], b"deleted");
+        tree.delete(&[5; 16]);
+        let entries = tree.entries();
+        assert_eq!(
+            entries,
+            [
+                ([0; 16], b"value1".to_vec()),
+                ([1; 16], b"value2".to_vec()),
+                ([2; 16], b"value3".to_vec()),
+                ([0xff; 16], vec![]),
+            ]
+        );
+        assert_eq!(SparseMerkleTree::from_entries(entries).unwrap(), tree);
+        assert!(SparseMerkleTree::new().entries().is_empty());
+    }
+
+    #[test]
+    fn test_contains_key_lifecycle() {
+        let mut tree = SparseMerkleTree::with_depth(8).with_default_value(b"zero");
+        let mut key = [0u8; 16];
+        key[0] = 7;
+        assert!(!tree.contains_key(&key));
+
+        tree.insert(&key, b"value");
+        assert!(tree.contains_key(&key));
+        tree.insert(&key, b"");
+        assert!(tree.contains_key(&key));
+        tree.delete(&key);
+        assert!(!tree.contains_key(&key));
+        tree.insert(&key, b"again");
+        assert!(tree.contains_key(&key));
+        // Writing the default value deletes the key.
+        tree.insert(&key, b"zero");
+        assert!(!tree.contains_key(&key));
+
+        // Keys past the depth are never set, and asking doesn't panic.
+        key[15] = 1;
+        assert!(!tree.contains_key(&key));
+    }
+
+    #[test]
+    fn test_len() {
+        let mut tree = SparseMerkleTree::new();
+        assert!(tree.is_empty());
+
+        tree.insert(&[1u8; 16], b"one");
+        tree.insert(&[2u8; 16], b"two");
+        tree.insert(&[1u8; 16], b"overwritten");
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.is_empty());
+
+        tree.insert_batch(&[([2u8; 16], b"again".to_vec()), ([3u8; 16], Vec::new())]);
+        assert_eq!(tree.len(), 3);
+
+        tree.delete(&[9u8; 16]);
+        assert_eq!(tree.len(), 3);
+        for key in [[1u8; 16], [2u8; 16], [3u8; 16]] {
+            tree.delete(&key);
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.root(), SparseMerkleTree::new().root());
+
+        let mut zeroing = SparseMerkleTree::new().with_default_value(b"");
+        zeroing.insert(&[1u8; 16], b"one");
+        zeroing.insert(&[1u8; 16], b"");
+        assert!(zeroing.is_empty());
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut tree = setup_tree();
+        let key = [1u8; 16];
+        tree.delete(&key);
+
+        assert_eq!(tree.get(&key), None);
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, None, &proof), Ok(true));
+        assert_eq!(tree.verify_proof(&key, Some(b"value2"), &proof), Ok(false));
+
+        tree.delete(&[0u8; 16]);
+        tree.delete(&[2u8; 16]);
+        assert_eq!(tree.root, SparseMerkleTree::new().root);
+        assert!(tree.store.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_delete_absent_key_is_noop() {
+        let mut tree = setup_tree();
+        let root = tree.root;
+        tree.delete(&[9u8; 16]);
+        assert_eq!(tree.root, root);
+    }
+
+    #[test]
+    fn test_update_returns_previous_value() {
+        let mut tree = setup_tree();
+        let key = [7u8; 16];
+
+        assert_eq!(tree.update(&key, b"first"), None);
+        assert_eq!(tree.update(&key, b"second"), Some(b"first".to_vec()));
+        assert_eq!(tree.update(&key, b"third"), Some(b"second".to_vec()));
+
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, Some(b"third"), &proof), Ok(true));
+        assert_eq!(tree.verify_proof(&key, Some(b"second"), &proof), Ok(false));
+
+        let mut fresh = setup_tree();
+        fresh.insert(&key, b"third");
+        assert_eq!(tree.root, fresh.root);
+    }
+
+    #[test]
+    fn test_insert_batch_matches_sequential_inserts() {
+        let entries: Vec<([u8; 16], Vec<u8>)> = (0..3000u32)
+            .map(|i| {
+                let mut key = [0u8; 16];
+                key[12..].copy_from_slice(&i.to_be_bytes());
+                (key, i.to_be_bytes().to_vec())
+            })
+            .collect();
+
+        let mut sequential = SparseMerkleTree::new();
+        HASH_PAIR_CALLS.with(|calls| calls.set(0));
+        for (key, value) in &entries {
+            sequential.insert(key, value);
+        }
+        let sequential_hashes = HASH_PAIR_CALLS.with(Cell::get);
+
+        let mut batched = SparseMerkleTree::new();
+        HASH_PAIR_CALLS.with(|calls| calls.set(0));
+        batched.insert_batch(&entries);
+        let batched_hashes = HASH_PAIR_CALLS.with(Cell::get);
+
+        assert_eq!(batched.root, sequential.root);
+        assert!(batched_hashes * 10 < sequential_hashes);
+
+        let (key, value) = &entries[1234];
+        let proof = batched.generate_proof(key);
+        assert_eq!(batched.verify_proof(key, Some(value), &proof), Ok(true));
+    }
+
+    #[test]
+    fn test_insert_batch_duplicate_keys_last_wins() {
+        let mut tree = setup_tree();
+        tree.insert_batch(&[([5u8; 16], b"old".to_vec()), ([5u8; 16], b"new".to_vec())]);
+
+        let mut expected = setup_tree();
+        expected.insert(&[5u8; 16], b"new");
+        assert_eq!(tree.root, expected.root);
+        assert_eq!(tree.get(&[5u8; 16]), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_compressed_proof_round_trip() {
+        let tree = setup_tree();
+        let key = [2u8; 16];
+        let proof = tree.generate_proof(&key);
+
+        let compressed = tree.compress_proof(&proof);
+        assert!(compressed.siblings.len() < 4);
+        assert_eq!(tree.decompress_proof(&compressed), Ok(proof));
+        assert_eq!(
+            tree.verify_compressed(&key, Some(b"value3"), &compressed),
+            Ok(true)
+        );
+        assert_eq!(
+            tree.verify_compressed(&key, Some(b"value1"), &compressed),
+            Ok(false)
+        );
+
+        let absent = [3u8; 16];
+        let compressed = tree.compress_proof(&tree.generate_proof(&absent));
+        assert_eq!(tree.verify_compressed(&absent, None, &compressed), Ok(true));
+    }
+
+    #[test]
+    fn test_proof_stats_count_occupied_subtrees() {
+        let mut tree = SparseMerkleTree::new();
+        let stats = tree.generate_proof(&[0; 16]).stats::<Sha256Hasher>();
+        assert_eq!(
+            stats,
+            ProofStats {
+                depth: 128,
+                non_default_siblings: 0,
+                byte_len_compressed: 3 + 16,
+            }
+        );
+
+        // Keys differing in the first bit: each one's only non-default sibling is
+        // the other half of the tree, just below the root.
+        let mut low = [0u8; 16];
+        let mut high = [0u8; 16];
+        high[0] = 0x80;
+        tree.insert(&low, b"low");
+        tree.insert(&high, b"high");
+        let proof = tree.generate_proof(&low);
+        assert_eq!(proof.stats::<Sha256Hasher>().non_default_siblings, 1);
+        assert_ne!(proof.siblings[127], tree.default_nodes[1]);
+
+        // A third key under `low`'s half adds the sibling at the second level, for
+        // `low` and also for an absent key in `high`'s half.
+        low[0] = 0x40;
+        tree.insert(&low, b"second");
+        for key in [[0; 16], [0xc0; 16]] {
+            let proof = tree.generate_proof(&key);
+            let stats = proof.stats::<Sha256Hasher>();
+            assert_eq!(stats.non_default_siblings, 2);
+            assert_eq!(stats.byte_len_compressed, 3 + 16 + 2 * 32);
+            assert_eq!(
+                stats.byte_len_compressed,
+                proof.encode::<Sha256Hasher>().len()
+            );
+            assert_eq!(tree.compress_proof(&proof).siblings.len(), 2);
+        }
+        let proof = tree.generate_proof(&high);
+        assert_eq!(proof.stats::<Sha256Hasher>().non_default_siblings, 1);
+    }
+
+    #[test]
+    fn test_compressed_proof_malformed_bitmask() {
+        let tree = setup_tree();
+        let key = [2u8; 16];
+        let mut compressed = tree.compress_proof(&tree.generate_proof(&key));
+
+        let siblings = compressed.siblings.len();
+        compressed.bitmask = with_bit(&compressed.bitmask, 100, true);
+        assert_eq!(
+            tree.decompress_proof(&compressed),
+            Err(SmtError::InvalidProofLength {
+                expected: siblings + 1,
+                actual: siblings
+            })
+        );
+
+        compressed.bitmask = [0; 16];
+        assert_eq!(
+            tree.verify_compressed(&key, Some(b"value3"), &compressed),
+            Err(SmtError::InvalidProofLength {
+                expected: 0,
+                actual: compressed.siblings.len()
+            })
+        );
+
+        let shallow = SparseMerkleTree::with_depth(32);
+        let mut compressed = shallow.compress_proof(&shallow.generate_proof(&[0; 16]));
+        compressed.bitmask[4] = 1;
+        compressed.siblings.push([1; 32]);
+        assert_eq!(
+            shallow.verify_compressed(&[0; 16], None, &compressed),
+            Err(SmtError::InvalidBitmask)
+        );
+    }
+
+    #[test]
+    fn test_custom_depth() {
+        let mut tree = SparseMerkleTree::with_depth(32);
+        assert_eq!(tree.key_len(), 4);
+
+        let mut key1 = [0u8; 16];
+        key1[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        let mut key2 = [0u8; 16];
+        key2[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xee]);
+        tree.insert(&key1, b"value1");
+        tree.insert(&key2, b"value2");
+
+        let proof = tree.generate_proof(&key1);
+        assert_eq!(proof.depth, 32);
+        assert_eq!(proof.siblings.len(), 32);
+        assert_eq!(tree.verify_proof(&key1, Some(b"value1"), &proof), Ok(true));
+        assert_eq!(
+            tree.verify_proof(&key2, Some(b"value2"), &tree.generate_proof(&key2)),
+            Ok(true)
+        );
+        assert_ne!(tree.root, SparseMerkleTree::with_depth(32).root);
+    }
+
+    #[test]
+    fn test_proof_from_different_depth_is_rejected() {
+        let mut shallow = SparseMerkleTree::with_depth(32);
+        let mut deep = SparseMerkleTree::new();
+        let key = [0u8; 16];
+        shallow.insert(&key, b"value");
+        deep.insert(&key, b"value");
+
+        let shallow_proof = shallow.generate_proof(&key);
+        let deep_proof = deep.generate_proof(&key);
+        assert_eq!(
+            deep.verify_proof(&key, Some(b"value"), &shallow_proof),
+            Err(SmtError::DepthMismatch {
+                expected: 128,
+                actual: 32
+            })
+        );
+        assert_eq!(
+            shallow.verify_proof(&key, Some(b"value"), &deep_proof),
+            Err(SmtError::DepthMismatch {
+                expected: 32,
+                actual: 128
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "past the tree depth")]
+    fn test_key_longer_than_depth_panics() {
+        let mut tree = SparseMerkleTree::with_depth(32);
+        tree.insert(&[1u8; 16], b"value");
+    }
+
+    #[test]
+    fn test_256_bit_keys_differing_in_last_bit() {
+        let mut tree = SparseMerkleTree256::new();
+        let key1 = [0xabu8; 32];
+        let mut key2 = key1;
+        key2[31] ^= 1;
+
+        tree.insert(&key1, b"value1");
+        let root_with_key1 = tree.root;
+        tree.insert(&key2, b"value2");
+        assert_ne!(tree.root, root_with_key1);
+
+        let proof1 = tree.generate_proof(&key1);
+        let proof2 = tree.generate_proof(&key2);
+        assert_eq!(proof1.siblings.len(), 256);
+        assert_eq!(tree.verify_proof(&key1, Some(b"value1"), &proof1), Ok(true));
+        assert_eq!(tree.verify_proof(&key2, Some(b"value2"), &proof2), Ok(true));
+        assert_eq!(
+            tree.verify_proof(&key2, Some(b"value1"), &proof1),
+            Ok(false)
+        );
+
+        // The deepest sibling of each key is the other key's leaf.
+        assert_eq!(proof1.siblings[0], Sha256Hasher::hash_leaf(b"value2"));
+
+        tree.delete(&key2);
+        assert_eq!(tree.root, root_with_key1);
+        assert_eq!(
+            tree.verify_proof(&key2, None, &tree.generate_proof(&key2)),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_depths_past_128() {
+        // 160 levels for 20-byte addresses, and a depth that isn't a whole byte.
+        let mut addresses = Smt::<20>::new();
+        let mut truncated = SparseMerkleTree256::with_depth(160);
+        let mut odd = SparseMerkleTree256::with_depth(201);
+        assert_eq!(addresses.depth(), 160);
+        assert_eq!(addresses.root, truncated.root);
+        for i in 0..20u8 {
+            let mut address = [i.wrapping_mul(0x3d); 20];
+            address[19] = i;
+            let mut key = [0u8; 32];
+            key[..20].copy_from_slice(&address);
+            addresses.insert(&address, &[i]);
+            truncated.insert(&key, &[i]);
+            key[25] = (i % 2) * 0x80;
+            odd.insert(&key, &[i]);
+        }
+        assert_eq!(addresses.root, truncated.root);
+
+        let mut key = [0u8; 32];
+        key[..20].copy_from_slice(&[0x3d; 20]);
+        key[19] = 1;
+        key[25] = 0x80;
+        let proof = odd.generate_proof(&key);
+        assert_eq!(proof.siblings.len(), 201);
+        assert_eq!(odd.verify_proof(&key, Some(&[1]), &proof), Ok(true));
+        key[25] = 0xc0;
+        assert_eq!(
+            odd.verify_proof(&key, Some(&[1]), &proof),
+            Err(SmtError::KeyLength { depth: 201 })
+        );
+        assert_eq!(
+            <SparseMerkleTree256>::check_depth(257),
+            Err(SmtError::InvalidDepth {
+                depth: 257,
+                max: 256
+            })
+        );
+    }
+
+    #[test]
+    fn test_bit_helpers() {
+        let bits = [0b1010_0000u8, 0b0000_0001];
+        assert!(bit(&bits, 0));
+        assert!(!bit(&bits, 1));
+        assert!(bit(&bits, 2));
+        assert!(bit(&bits, 15));
+        assert_eq!(truncate(&bits, 2), [0b1000_0000, 0]);
+        assert_eq!(truncate(&bits, 16), bits);
+        assert_eq!(with_bit(&bits, 1, true), [0b1110_0000, 0b0000_0001]);
+        assert_eq!(with_bit(&bits, 15, false), [0b1010_0000, 0]);
+    }
+
+    #[test]
+    fn test_sha256_known_roots() {
+        assert_eq!(
+            hex(&SparseMerkleTree::new().root),
+            "9c337c9f019bbd96d4e11587fb18c0a032c3bb4db6af7d319694c2777f33f17e"
+        );
+        assert_eq!(
+            hex(&setup_tree().root),
+            "29eff5026564ef69bd14a9c6f34b84aa2e83c59eb764fd640a9bd2d3c48a0694"
+        );
+    }
+
+    #[test]
+    fn test_zero_hashes() {
+        let zero_hashes = <SparseMerkleTree>::zero_hashes();
+        assert_eq!(zero_hashes.len(), 129);
+        let expected = [
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "ae0798d0ecaed2b778eddebf18f071a561c53658c05e76cedecc27cafbdbc577",
+            "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+            "bea16162721bca4b6e1782cbdc695a471522157c6716f508db47c599195340f4",
+            "30765fef341bdfe749c391bf956a9f03d363941b2eb8f85ab16bb6eb0d3c4def",
+        ];
+        for (height, expected) in expected.iter().enumerate() {
+            assert_eq!(hex(&zero_hashes[height]), *expected, "height {height}");
+        }
+        assert_eq!(zero_hashes[128], SparseMerkleTree::new().root());
+        assert_eq!(
+            zero_hashes[4],
+            SparseMerkleTree::with_depth(4).default_nodes[0]
+        );
+
+        assert_eq!(<SparseMerkleTree>::zero_hash_at(0), Some(DEFAULT_LEAF));
+        assert_eq!(<SparseMerkleTree>::zero_hash_at(3), Some(zero_hashes[3]));
+        assert_eq!(
+            <SparseMerkleTree>::zero_hash_at(128),
+            Some(zero_hashes[128])
+        );
+        assert_eq!(<SparseMerkleTree>::zero_hash_at(129), None);
+        assert_eq!(
+            <SparseMerkleTree256>::zero_hash_at(256),
+            Some(SparseMerkleTree256::new().root())
+        );
+    }
+
+    #[test]
+    fn test_empty_root_hex() {
+        assert_eq!(
+            SparseMerkleTree::new().root_hex(),
+            "0x9c337c9f019bbd96d4e11587fb18c0a032c3bb4db6af7d319694c2777f33f17e"
+        );
+        assert_eq!(
+            SparseMerkleTree256::new().root_hex(),
+            "0x6155289130893872355eac98042d22aefa2c2e708bea169402760e3b55f9a2dc"
+        );
+        let tree = setup_tree();
+        assert_eq!(tree.root_hex(), format!("0x{}", hex(&tree.root())));
+    }
+
+    #[test]
+    fn test_keccak256_known_roots() {
+        assert_eq!(
+            hex(&SparseMerkleTree::<Keccak256Hasher>::with_hasher().root),
+            "497399c797d1139102187ed5135c812b0e6c183314010cc759a8fff6bb5ba43d"
+        );
+
+        let tree = setup_tree_with_hasher::<Keccak256Hasher>();
+        assert_eq!(
+            hex(&tree.root),
+            "e8733b08d66ea0e7f3321a8ce2e313670f269cedd0fa7f1246afc4c0c4388ddb"
+        );
+
+        let key = [1u8; 16];
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, Some(b"value2"), &proof), Ok(true));
+        assert_eq!(
+            setup_tree().verify_proof(&key, Some(b"value2"), &proof),
+            Ok(false)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut tree = setup_tree();
+        tree.delete(&[1u8; 16]);
+        let json = serde_json::to_string(&tree).unwrap();
+        let default_root = serde_json::to_string(&tree.default_nodes[0]).unwrap();
+        assert!(!json.contains(&default_root));
+
+        let restored: SparseMerkleTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.root, tree.root);
+        assert_eq!(restored.store, tree.store);
+        for key in [[0u8; 16], [1u8; 16], [2u8; 16]] {
+            assert_eq!(restored.generate_proof(&key), tree.generate_proof(&key));
+            assert_eq!(restored.get(&key), tree.get(&key));
+        }
+
+        let shallow = SparseMerkleTree::with_depth(8);
+        let restored: SparseMerkleTree =
+            serde_json::from_str(&serde_json::to_string(&shallow).unwrap()).unwrap();
+        assert_eq!(restored.depth, 8);
+        assert_eq!(restored.root, shallow.root);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_restored_tree_accepts_inserts() {
+        let tree = setup_tree();
+        let mut restored: SparseMerkleTree =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+
+        let mut expected = setup_tree();
+        expected.insert(&[9u8; 16], b"value9");
+        restored.insert(&[9u8; 16], b"value9");
+        assert_eq!(restored.root, expected.root);
+        assert_eq!(
+            restored.verify_proof(
+                &[0u8; 16],
+                Some(b"value1"),
+                &restored.generate_proof(&[0u8; 16])
+            ),
+            Ok(true)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_inconsistent_state() {
+        let json = serde_json::to_string(&setup_tree()).unwrap();
+        let value1 = serde_json::to_string(&b"value1".to_vec()).unwrap();
+        let tampered = json.replace(&value1, "[1,2,3]");
+        assert_ne!(tampered, json);
+        assert!(serde_json::from_str::<SparseMerkleTree>(&tampered).is_err());
+
+        // Right leaves, wrong hasher.
+        assert!(serde_json::from_str::<SparseMerkleTree<Keccak256Hasher>>(&json).is_err());
+    }
+
+    #[test]
+    fn test_proof_bytes_round_trip() {
+        let tree = setup_tree();
+        let proof = tree.generate_proof(&[2u8; 16]);
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes.len(), 3 + 128 * 32);
+
+        let decoded = SmtProof::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(
+            tree.verify_proof(&[2u8; 16], Some(b"value3"), &decoded),
+            Ok(true)
+        );
+
+        assert_eq!(
+            SmtProof::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SmtError::InvalidProofLength {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+        assert_eq!(
+            SmtProof::from_bytes(&[0]),
+            Err(SmtError::InvalidProofLength {
+                expected: 3,
+                actual: 1
+            })
+        );
+
+        let mut unknown_flag = bytes.clone();
+        unknown_flag[2] = 0b1_0000;
+        assert_eq!(
+            SmtProof::from_bytes(&unknown_flag),
+            Err(SmtError::InvalidFlags(0b1_0000))
+        );
+        let mut two_flavors = bytes.clone();
+        two_flavors[2] = 0b110;
+        assert_eq!(
+            SmtProof::from_bytes(&two_flavors),
+            Err(SmtError::InvalidFlags(0b110))
+        );
+    }
+
+    #[test]
+    fn test_proof_encoding_vector() {
+        let mut tree = SparseMerkleTree::with_depth(4);
+        for (path, value) in [(0x50, b"a"), (0xc0, b"b"), (0x60, b"c")] {
+            let mut key = [0u8; 16];
+            key[0] = path;
+            tree.insert(&key, value);
+        }
+        let mut key = [0u8; 16];
+        key[0] = 0x50;
+        let proof = tree.generate_proof(&key);
+        let encoded = proof.encode::<Sha256Hasher>();
+        assert_eq!(
+            hex(&encoded),
+            "01000350\
+             df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a\
+             87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09"
+        );
+        assert_eq!(SmtProof::decode::<Sha256Hasher>(&encoded), Ok(proof));
+    }
+
+    #[test]
+    fn test_proof_encoding_round_trip() {
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..20 {
+            let depth = (next() % 128 + 1) as usize;
+            let mut tree = SparseMerkleTree::with_depth(depth);
+            if next() % 2 == 0 {
+                tree = tree.with_flavor(SmtFlavor::PathCommitting);
+            }
+            let random_key = |bits: u128| (bits << (128 - depth)).to_be_bytes();
+            let keys: Vec<_> = (0..next() % 50)
+                .map(|_| random_key(u128::from(next()) << 64 | u128::from(next())))
+                .collect();
+            for key in &keys {
+                tree.insert(key, &key[..4]);
+            }
+            for key in keys.iter().take(3).chain([&random_key(u128::from(next()))]) {
+                let proof = tree.generate_proof(key);
+                let encoded = proof.encode::<Sha256Hasher>();
+                assert!(encoded.len() <= 3 + depth.div_ceil(8) + 32 * depth);
+                assert_eq!(SmtProof::decode::<Sha256Hasher>(&encoded), Ok(proof));
+            }
+            let proof = tree.generate_proof_hashed(b"hashed");
+            assert_eq!(
+                SmtProof::decode::<Sha256Hasher>(&proof.encode::<Sha256Hasher>()),
+                Ok(proof)
+            );
+        }
+    }
+
+    #[test]
+    fn test_proof_decoding_errors() {
+        let tree = setup_tree();
+        let encoded = tree.generate_proof(&[2u8; 16]).encode::<Sha256Hasher>();
+        let decode = SmtProof::decode::<Sha256Hasher>;
+        assert_eq!(encoded.len(), 3 + 16 + 32);
+
+        assert_eq!(
+            decode(&[&encoded[..], &[0]].concat()),
+            Err(SmtError::InvalidProofLength {
+                expected: encoded.len(),
+                actual: encoded.len() + 1
+            })
+        );
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 32]),
+            Err(SmtError::InvalidProofLength {
+                expected: encoded.len(),
+                actual: encoded.len() - 32
+            })
+        );
+        assert_eq!(
+            decode(&encoded[..10]),
+            Err(SmtError::InvalidProofLength {
+                expected: 19,
+                actual: 10
+            })
+        );
+        let mut future = encoded.clone();
+        future[0] = 2;
+        assert_eq!(decode(&future), Err(SmtError::UnsupportedVersion(2)));
+        let mut unknown_flag = encoded.clone();
+        unknown_flag[1] = 0b1_0000;
+        assert_eq!(decode(&unknown_flag), Err(SmtError::InvalidFlags(0b1_0000)));
+
+        // An extra bitmap bit claims a sibling the bytes don't hold.
+        let mut extra_bit = encoded.clone();
+        extra_bit[3 + 8] |= 1;
+        assert!(matches!(
+            decode(&extra_bit),
+            Err(SmtError::InvalidProofLength { .. })
+        ));
+        let mut depth_4 = vec![1, 0, 3, 0x58];
+        depth_4.extend_from_slice(&[0; 64]);
+        assert_eq!(decode(&depth_4), Err(SmtError::InvalidBitmask));
+    }
+
+    #[test]
+    fn test_proof_with_wrong_depth_is_rejected() {
+        let tree = setup_tree();
+        let key = [2u8; 16];
+
+        let mut proof = tree.generate_proof(&key);
+        proof.depth = 64;
+        assert_eq!(
+            tree.verify_proof(&key, Some(b"value3"), &proof),
+            Err(SmtError::DepthMismatch {
+                expected: 128,
+                actual: 64
+            })
+        );
+
+        let mut proof = tree.generate_proof(&key);
+        proof.siblings.pop();
+        assert_eq!(
+            tree.verify_proof(&key, Some(b"value3"), &proof),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 127
+            })
+        );
+    }
+
+    #[test]
+    fn test_stateless_verify_against_wire_root() {
+        let tree = setup_tree();
+        let root_over_the_wire: [u8; 32] = tree.root();
+        let proof = SmtProof::from_bytes(&tree.generate_proof(&[1u8; 16]).to_bytes()).unwrap();
+        let verifier = SmtVerifier::<16>::new(root_over_the_wire, 128, SmtFlavor::Plain);
+
+        assert_eq!(
+            verifier.verify(&[1u8; 16], Some(b"value2"), &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            verifier.verify(&[1u8; 16], Some(b"value1"), &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verifier.with_root(SparseMerkleTree::new().root()).verify(
+                &[1u8; 16],
+                Some(b"value2"),
+                &proof
+            ),
+            Ok(false)
+        );
+
+        let absent = tree.generate_proof(&[3u8; 16]);
+        assert_eq!(verifier.verify(&[3u8; 16], None, &absent), Ok(true));
+    }
+
+    #[test]
+    fn test_stateless_verify_rejects_malformed_proofs() {
+        let tree = SparseMerkleTree::with_depth(8);
+        let key = [0x80u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verifier().verify(&key, None, &proof), Ok(true));
+
+        // Bits past the proof's depth would be silently ignored, so they're rejected.
+        let mut long_key = key;
+        long_key[1] = 1;
+        assert_eq!(
+            tree.verifier().verify(&long_key, None, &proof),
+            Err(SmtError::KeyLength { depth: 8 })
+        );
+
+        let too_deep = SmtProof {
+            depth: 129,
+            flavor: SmtFlavor::Plain,
+            hashed_key: false,
+            bit_order: BitOrder::MsbFirst,
+            siblings: vec![[0; 32]; 129],
+        };
+        assert_eq!(
+            tree.verifier().verify(&key, None, &too_deep),
+            Err(SmtError::DepthMismatch {
+                expected: 8,
+                actual: 129
+            })
+        );
+    }
+
+    #[test]
+    fn test_non_inclusion_proof() {
+        let tree = setup_tree();
+        let non_existent_key = [3u8; 16];
+
+        let proof_non_existent = tree.generate_proof(&non_existent_key);
+        assert_eq!(
+            tree.verify_proof(&non_existent_key, None, &proof_non_existent),
+            Ok(true)
+        );
+        assert_eq!(
+            tree.verify_proof(&non_existent_key, Some(b"value3"), &proof_non_existent),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_non_inclusion_proof_next_to_existing_key() {
+        let tree = setup_tree();
+        let mut neighbour = [2u8; 16];
+        neighbour[15] ^= 1; // same path as an existing key except for the last bit
+
+        let proof = tree.generate_proof(&neighbour);
+        assert_eq!(tree.verify_proof(&neighbour, None, &proof), Ok(true));
+    }
+
+    #[test]
+    fn test_non_inclusion_proof_fails_for_existing_key() {
+        let tree = setup_tree();
+        let existing_key = [2u8; 16];
+
+        let proof = tree.generate_proof(&existing_key);
+        assert_eq!(tree.verify_proof(&existing_key, None, &proof), Ok(false));
+
+        // A non-inclusion proof for an absent key can't be reused for a present one.
+        let absent_proof = tree.generate_proof(&[3u8; 16]);
+        assert_eq!(
+            tree.verify_proof(&existing_key, None, &absent_proof),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_empty_value_is_an_entry() {
+        let mut tree = setup_tree();
+        let root_before = tree.root();
+        let key = [3u8; 16];
+        tree.insert(&key, b"");
+
+        assert_ne!(tree.root(), root_before);
+        assert_eq!(tree.get(&key), Some(Vec::new()));
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, Some(b""), &proof), Ok(true));
+        assert_eq!(tree.verify_proof(&key, None, &proof), Ok(false));
+        assert_eq!(tree.verifier().verify(&key, Some(b""), &proof), Ok(true));
+        assert_eq!(tree.verifier().verify(&key, None, &proof), Ok(false));
+
+        // Deleting the empty value makes the key absent again.
+        tree.delete(&key);
+        assert_eq!(tree.root(), root_before);
+        assert_eq!(tree.get(&key), None);
+        assert_eq!(
+            tree.verify_proof(&key, None, &tree.generate_proof(&key)),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_absent_key_is_not_an_empty_value() {
+        let tree = setup_tree();
+        let key = [3u8; 16];
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, None, &proof), Ok(true));
+        assert_eq!(tree.verify_proof(&key, Some(b""), &proof), Ok(false));
+
+        let mut batched = SparseMerkleTree::new();
+        batched.insert_batch(&[(key, Vec::new())]);
+        assert_ne!(batched.root(), SparseMerkleTree::new().root());
+        assert_eq!(
+            batched.verify_proof(&key, Some(b""), &batched.generate_proof(&key)),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_inserting_default_value_deletes() {
+        let mut tree = SparseMerkleTree::new().with_default_value(&[0; 32]);
+        assert_eq!(tree.default_value(), Some(&[0u8; 32][..]));
+        let empty_root = tree.root();
+        let key = [3u8; 16];
+
+        // Never set: a no-op.
+        assert_eq!(tree.update(&key, &[0; 32]), None);
+        assert_eq!(tree.root(), empty_root);
+
+        tree.insert(&key, b"balance");
+        assert_eq!(tree.update(&key, &[0; 32]), Some(b"balance".to_vec()));
+        assert_eq!(tree.root(), empty_root);
+        assert_eq!(tree.get(&key), None);
+        let proof = tree.generate_proof(&key);
+        assert_eq!(tree.verify_proof(&key, None, &proof), Ok(true));
+        assert_eq!(tree.verify_proof(&key, Some(&[0; 32]), &proof), Ok(false));
+        assert!(tree.store.nodes.is_empty());
+
+        // Off by default, where it is plain data.
+        let mut plain = SparseMerkleTree::new();
+        assert_eq!(plain.default_value(), None);
+        plain.insert(&key, &[0; 32]);
+        assert_eq!(plain.get(&key), Some(vec![0; 32]));
+        assert_ne!(plain.root(), empty_root);
+    }
+
+    #[test]
+    fn test_tree_without_values() {
+        let mut hashes_only = SparseMerkleTree::new().with_store_values(false);
+        let mut reference = SparseMerkleTree::new();
+        assert!(!hashes_only.stores_values());
+        for i in 0..8u8 {
+            assert_eq!(
+                hashes_only.insert(&[i; 16], &[i; 64]),
+                reference.insert(&[i; 16], &[i; 64])
+            );
+        }
+        assert_eq!(
+            hashes_only.insert_batch(&[([8; 16], vec![8]), ([9; 16], vec![9])]),
+            reference.insert_batch(&[([8; 16], vec![8]), ([9; 16], vec![9])])
+        );
+        assert_eq!(hashes_only.delete(&[3; 16]), reference.delete(&[3; 16]));
+        assert_eq!(hashes_only.delete(&[3; 16]), reference.root());
+        assert_eq!(hashes_only.update(&[4; 16], b"new"), None);
+        reference.insert(&[4; 16], b"new");
+        assert_eq!(hashes_only.root(), reference.root());
+
+        assert!(hashes_only.store.values.is_empty());
+        assert_eq!(hashes_only.len(), 9);
+        assert_eq!(hashes_only.get(&[1; 16]), None);
+        assert_eq!(
+            hashes_only.try_get(&[1; 16]),
+            Err(SmtError::ValueNotRetained)
+        );
+        assert_eq!(hashes_only.try_get(&[3; 16]), Ok(None));
+        assert_eq!(reference.try_get(&[1; 16]), Ok(Some(vec![1; 64])));
+
+        let proof = hashes_only.generate_proof(&[1; 16]);
+        assert_eq!(proof, reference.generate_proof(&[1; 16]));
+        assert_eq!(
+            hashes_only.verify_proof(&[1; 16], Some(&[1; 64]), &proof),
+            Ok(true)
+        );
+        assert_eq!(hashes_only.verify_integrity(), Ok(()));
+        assert!(format!("{hashes_only:?}").contains("len: 9"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_tree_without_values() {
+        let mut tree = SparseMerkleTree::new().with_store_values(false);
+        tree.insert(&[1; 16], b"one");
+        let err = serde_json::to_string(&tree).unwrap_err();
+        assert!(err.to_string().contains("doesn't store values"), "{err}");
+        assert!(serde_json::to_string(&SparseMerkleTree::new().with_store_values(false)).is_err());
+    }
+
+    #[test]
+    fn test_batch_with_default_value() {
+        let mut tree = SparseMerkleTree::new().with_default_value(b"");
+        tree.insert(&[1u8; 16], b"one");
+        tree.insert(&[2u8; 16], b"two");
+        tree.insert_batch(&[
+            ([1u8; 16], Vec::new()),
+            ([3u8; 16], b"three".to_vec()),
+            ([4u8; 16], Vec::new()),
+            ([2u8; 16], Vec::new()),
+            ([2u8; 16], b"two again".to_vec()),
+        ]);
+
+        let mut expected = SparseMerkleTree::new();
+        expected.insert(&[2u8; 16], b"two again");
+        expected.insert(&[3u8; 16], b"three");
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.store, expected.store);
+        assert_eq!(tree.get(&[1u8; 16]), None);
+        assert_eq!(tree.get(&[4u8; 16]), None);
+        for key in [[1u8; 16], [4u8; 16]] {
+            let proof = tree.generate_proof(&key);
+            assert_eq!(tree.verify_proof(&key, None, &proof), Ok(true));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "only be set on an empty tree")]
+    fn test_default_value_cannot_change_on_non_empty_tree() {
+        setup_tree().with_default_value(b"");
+    }
 
     #[test]
     fn test_proof_fails_for_wrong_key() {
@@ -181,7 +2334,7 @@ mod tests {
         let value1 = b"value1";
 
         let proof1 = tree.generate_proof(&key1);
-        assert!(!tree.verify_proof(&key2, Some(value1), &proof1));
+        assert_eq!(tree.verify_proof(&key2, Some(value1), &proof1), Ok(false));
     }
 
     #[test]
@@ -191,6 +2344,940 @@ mod tests {
         let wrong_value = b"wrong_value";
 
         let proof1 = tree.generate_proof(&key1);
-        assert!(!tree.verify_proof(&key1, Some(wrong_value), &proof1));
+        assert_eq!(
+            tree.verify_proof(&key1, Some(wrong_value), &proof1),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_iter_in_key_order() {
+        let mut tree = setup_tree();
+        tree.insert(&[1u8; 16], b"updated");
+        tree.insert(&[9u8; 16], b"value9");
+        tree.delete(&[0u8; 16]);
+
+        let entries: Vec<_> = tree.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                (&[1u8; 16], &b"updated"[..]),
+                (&[2u8; 16], &b"value3"[..]),
+                (&[9u8; 16], &b"value9"[..]),
+            ]
+        );
+        assert_eq!((&tree).into_iter().collect::<Vec<_>>(), entries);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut tree = SparseMerkleTree::new();
+        for i in (0..=250u8).step_by(10) {
+            tree.insert(&[i; 16], &[i]);
+        }
+        let keys = |start: [u8; 16], end: [u8; 16]| -> Vec<u8> {
+            tree.range(&start, &end).map(|(key, _)| key[0]).collect()
+        };
+
+        assert_eq!(keys([0; 16], [0xff; 16]).len(), 26);
+        assert!(tree.range(&[0; 16], &[0xff; 16]).eq(tree.iter()));
+        assert_eq!(keys([20; 16], [50; 16]), [20, 30, 40]);
+        assert_eq!(keys([21; 16], [51; 16]), [30, 40, 50]);
+        assert!(keys([19; 16], [20; 16]).is_empty());
+        assert!(keys([20; 16], [20; 16]).is_empty());
+        assert!(keys([50; 16], [20; 16]).is_empty());
+        assert_eq!(
+            tree.range(&[30; 16], &[31; 16]).collect::<Vec<_>>(),
+            [(&[30; 16], &[30][..])]
+        );
+
+        // Boundaries inside a key's leaf byte.
+        let mut start = [40; 16];
+        start[15] = 41;
+        let mut end = [60; 16];
+        end[15] = 61;
+        assert_eq!(keys(start, end), [50, 60]);
+        end[15] = 60;
+        assert_eq!(keys(start, end), [50]);
+
+        assert_eq!(
+            SparseMerkleTree::new().range(&[0; 16], &[0xff; 16]).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_range_matches_filtered_iter() {
+        let mut tree = SparseMerkleTree::with_depth(24);
+        for i in 0..300u32 {
+            let mut key = [0u8; 16];
+            key[..3].copy_from_slice(&i.wrapping_mul(0x9e3779b9).to_be_bytes()[..3]);
+            tree.insert(&key, &i.to_be_bytes());
+        }
+        for i in 0..50u32 {
+            let mut start = [0u8; 16];
+            let mut end = [0u8; 16];
+            start[..4].copy_from_slice(&i.wrapping_mul(0x3b9aca07).to_be_bytes());
+            end[..4].copy_from_slice(&i.wrapping_mul(0x1f1f1f1f).to_be_bytes());
+            let expected: Vec<_> = tree
+                .iter()
+                .filter(|(key, _)| (&start..&end).contains(key))
+                .collect();
+            assert_eq!(tree.range(&start, &end).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_debug_and_display() {
+        let mut tree = SparseMerkleTree::with_depth(4);
+        for (path, value) in [(0x50, b"a"), (0xc0, b"b"), (0x60, b"c")] {
+            let mut key = [0u8; 16];
+            key[0] = path;
+            tree.insert(&key, value);
+        }
+        assert_eq!(
+            format!("{tree:?}"),
+            "Smt { root: 0x6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d, \
+             depth: 4, len: 3, flavor: Plain }"
+        );
+        assert_eq!(
+            tree.to_string(),
+            "root 6b5e998d, depth 4, 3 leaves\n  50 022a6979\n  60 597fcb31\n  c0 57eb3561"
+        );
+
+        let mut tree = SparseMerkleTree::new();
+        assert_eq!(
+            tree.to_string(),
+            format!("root {}, depth 128, 0 leaves", &hex(&tree.root())[..8])
+        );
+        tree.insert(&[0xab; 16], b"value");
+        let leaf = hex(&Sha256Hasher::hash_leaf(b"value"));
+        assert!(tree.to_string().ends_with(&format!(
+            "1 leaves\n  abababababababababababababababab {}",
+            &leaf[..8]
+        )));
+    }
+
+    #[test]
+    fn test_equality_by_content() {
+        let mut forward = SparseMerkleTree::new();
+        let mut backward = SparseMerkleTree::new();
+        for i in 0..10u8 {
+            forward.insert(&[i; 16], &[i]);
+            backward.insert(&[9 - i; 16], &[9 - i]);
+        }
+        backward.set_cache_capacity(16);
+        assert_eq!(forward, backward);
+        assert!(forward.root_eq(&backward));
+
+        backward.insert(&[10; 16], b"extra");
+        assert_ne!(forward, backward);
+        assert!(!forward.root_eq(&backward));
+        backward.delete(&[10; 16]);
+        assert_eq!(forward, backward);
+
+        let mut other_depth = forward.snapshot();
+        other_depth.depth = 64;
+        assert_ne!(forward, other_depth);
+        assert!(forward.root_eq(&other_depth));
+        assert_ne!(
+            SparseMerkleTree::with_depth(4),
+            SparseMerkleTree::with_depth(8)
+        );
+    }
+
+    #[test]
+    fn test_into_iter_rebuilds_identical_tree() {
+        let tree = setup_tree();
+        let mut rebuilt = SparseMerkleTree::new();
+        for (key, value) in tree.clone() {
+            rebuilt.insert(&key, &value);
+        }
+        assert_eq!(rebuilt.root(), tree.root());
+        assert_eq!(SparseMerkleTree::new().iter().count(), 0);
+    }
+
+    #[test]
+    fn test_hashed_key_vectors() {
+        let tree = SparseMerkleTree::new();
+        assert_eq!(
+            hex(&tree.hash_key(b"alice")),
+            "2bd806c97f0e00af1a1fc3328fa763a9"
+        );
+        let long_key = b"an identifier much longer than sixteen bytes";
+        assert_eq!(
+            hex(&tree.hash_key(long_key)),
+            "7c5b047a2aabac77ba13bac9da1945b9"
+        );
+        assert_eq!(
+            hex(&SparseMerkleTree::<Keccak256Hasher>::with_hasher().hash_key(b"alice")),
+            "9c0257114eb9399a2985f8e75dad7600"
+        );
+        // Only the first `depth` bits of the hash are kept.
+        assert_eq!(
+            hex(&SparseMerkleTree::with_depth(8).hash_key(b"alice")[..2]),
+            "2b00"
+        );
+
+        let mut tree = tree;
+        tree.insert_hashed(b"alice", b"1");
+        tree.insert_hashed(long_key, b"2");
+        assert_eq!(
+            hex(&tree.root()),
+            "ccec2b9c1916b4896a9cf8ba05ab9e3834f80c3d93598f28394ff366db4833b1"
+        );
+        assert_eq!(tree.get_hashed(b"alice"), Some(b"1".to_vec()));
+        tree.delete_hashed(b"alice");
+        assert_eq!(tree.get_hashed(b"alice"), None);
+    }
+
+    #[test]
+    fn test_hashed_key_proofs() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert_hashed(b"alice", b"1");
+
+        let proof = tree.generate_proof_hashed(b"alice");
+        assert!(proof.hashed_key);
+        assert_eq!(
+            tree.verify_proof_hashed(b"alice", Some(b"1"), &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            <SparseMerkleTree>::verify_hashed(&tree.verifier(), b"alice", Some(b"1"), &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            tree.verify_proof_hashed(b"bob", Some(b"1"), &proof),
+            Ok(false)
+        );
+
+        let absent = tree.generate_proof_hashed(b"bob");
+        assert_eq!(tree.verify_proof_hashed(b"bob", None, &absent), Ok(true));
+
+        // The same siblings can't be passed off as a proof for the raw path, or vice versa.
+        let path = tree.hash_key(b"alice");
+        assert_eq!(tree.verify_proof(&path, Some(b"1"), &proof), Ok(false));
+        let raw = tree.generate_proof(&path);
+        assert_eq!(tree.verify_proof(&path, Some(b"1"), &raw), Ok(true));
+        assert_eq!(
+            tree.verify_proof_hashed(b"alice", Some(b"1"), &raw),
+            Ok(false)
+        );
+
+        let decoded = SmtProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(
+            tree.verify_proof_hashed(b"alice", Some(b"1"), &decoded),
+            Ok(true)
+        );
+        let compressed = tree.compress_proof(&proof);
+        assert_eq!(tree.decompress_proof(&compressed), Ok(proof));
+    }
+
+    /// The pre-prefix construction, kept to show the forgery below used to work.
+    struct UnseparatedSha256;
+
+    impl SmtHasher for UnseparatedSha256 {
+        fn hash(data: &[u8]) -> [u8; 32] {
+            Sha256::digest(data).into()
+        }
+
+        fn hash_leaf(value: &[u8]) -> [u8; 32] {
+            Self::hash(value)
+        }
+
+        fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+            Self::hash(&[left.as_slice(), right].concat())
+        }
+    }
+
+    /// Claims the parent of two leaves is itself a leaf one level up, holding
+    /// the two leaf hashes as its value, with a proof one sibling shorter.
+    fn forge_internal_node_proof<H: SmtHasher>() -> bool {
+        let mut tree = SparseMerkleTree::<H>::with_depth_and_hasher(8);
+        let left = [0u8; 16];
+        let mut right = [0u8; 16];
+        right[0] = 1;
+        tree.insert(&left, b"left");
+        tree.insert(&right, b"right");
+
+        let proof = tree.generate_proof(&left);
+        let forged_value = [H::hash_leaf(b"left"), H::hash_leaf(b"right")].concat();
+        let forged = SmtProof {
+            depth: 7,
+            flavor: SmtFlavor::Plain,
+            hashed_key: false,
+            bit_order: BitOrder::MsbFirst,
+            siblings: proof.siblings[1..].to_vec(),
+        };
+        SmtVerifier::<16, H>::new(tree.root(), 7, SmtFlavor::Plain)
+            .verify(&left, Some(&forged_value), &forged)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_forged_internal_node_is_rejected() {
+        assert!(forge_internal_node_proof::<UnseparatedSha256>());
+        assert!(!forge_internal_node_proof::<Sha256Hasher>());
+        assert!(!forge_internal_node_proof::<Keccak256Hasher>());
+    }
+
+    #[test]
+    fn test_key_to_path_vectors() {
+        let mut top = [0u8; 16];
+        top[0] = 0x80;
+        let mut bottom = [0u8; 16];
+        bottom[15] = 0x01;
+        let counting: [u8; 16] = core::array::from_fn(|i| i as u8);
+
+        assert_eq!(key_to_path(&[0; 16]), 0);
+        assert_eq!(key_to_path(&top), 1 << 127);
+        assert_eq!(key_to_path(&bottom), 1);
+        assert_eq!(key_to_path(&[0xff; 16]), u128::MAX);
+        assert_eq!(key_to_path(&counting), 0x000102030405060708090a0b0c0d0e0f);
+
+        // The path's top bit is the root branch, i.e. bit 0 as read by the tree.
+        for i in 0..128 {
+            assert_eq!(
+                bit(&counting, i),
+                key_to_path(&counting) >> (127 - i) & 1 == 1
+            );
+        }
+    }
+
+    /// `key_to_path` spelled out: bit `i` of the key, counted from the top bit of
+    /// `key[0]`, shifted into bit `127 - i` of the path.
+    fn key_to_path_bitwise(key: &[u8; 16]) -> u128 {
+        (0..128).fold(0, |path, i| {
+            let bit = (key[i / 8] >> (7 - i % 8)) & 1;
+            path | u128::from(bit) << (127 - i)
+        })
+    }
+
+    #[test]
+    fn test_key_to_path_matches_bitwise_reference() {
+        let mut keys: Vec<[u8; 16]> = (0..128).map(|i| with_bit(&[0; 16], i, true)).collect();
+        // Single-bit keys map to single-bit paths, each bit to its own.
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(key_to_path(key), 1 << (127 - i));
+            assert_eq!(key_to_path(key).count_ones(), 1);
+        }
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        keys.extend((0..64).map(|_| {
+            core::array::from_fn(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+        }));
+        keys.extend([[0; 16], [0xff; 16]]);
+
+        for key in &keys {
+            let path = key_to_path(key);
+            assert_eq!(path, key_to_path_bitwise(key));
+            assert_eq!(path_to_key(path), *key);
+            // The tree's own derivation agrees, and LsbFirst reverses it.
+            assert_eq!(path, u128::from_be_bytes(BitOrder::MsbFirst.path(key, 128)));
+            assert_eq!(
+                path.reverse_bits(),
+                u128::from_be_bytes(BitOrder::LsbFirst.path(key, 128))
+            );
+            for i in 0..128 {
+                assert_eq!(bit(key, i), path >> (127 - i) & 1 == 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_path_derivation_root_vectors() {
+        let mut top = [0u8; 16];
+        top[0] = 0x80;
+        let mut bottom = [0u8; 16];
+        bottom[15] = 0x01;
+
+        // Differing in the top bit puts the keys on opposite sides of the root.
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[0; 16], b"a");
+        tree.insert(&top, b"b");
+        assert_eq!(
+            hex(&tree.root()),
+            "0f448754e24380eb01468877cbd8fe4285be2bb2c1764f4d67503d4b3b509086"
+        );
+        assert_eq!(
+            tree.generate_proof(&[0; 16]).siblings[127],
+            tree.node(1, top)
+        );
+
+        // Differing in the bottom bit makes them siblings at the leaf level.
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[0; 16], b"a");
+        tree.insert(&bottom, b"b");
+        assert_eq!(
+            hex(&tree.root()),
+            "b281ea4d8d83b0b0c826a0b80c7f79861054c2f2646c5ba9a7f79bc9626260a0"
+        );
+        assert_eq!(
+            tree.generate_proof(&[0; 16]).siblings[0],
+            Sha256Hasher::hash_leaf(b"b")
+        );
+
+        // The example above: key 0b1101 at depth 4 is leaf 13.
+        let mut key = [0u8; 16];
+        key[0] = 0b1101_0000;
+        let mut tree = SparseMerkleTree::with_depth(4);
+        tree.insert(&key, b"value");
+        assert_eq!(key_to_path(&key) >> 124, 13);
+        assert_eq!(
+            hex(&tree.root()),
+            "5d72d93d7775e836bd8b1d8acb7145b9e457a340bf5f7098f467b8e591e7a26e"
+        );
+    }
+
+    #[test]
+    fn test_rewriting_same_value_skips_rehashing() {
+        let mut tree = setup_tree();
+        let root = tree.root;
+
+        HASH_PAIR_CALLS.with(|calls| calls.set(0));
+        assert_eq!(tree.update(&[1u8; 16], b"value2"), Some(b"value2".to_vec()));
+        tree.delete(&[3u8; 16]);
+        assert_eq!(HASH_PAIR_CALLS.with(Cell::get), 0);
+        assert_eq!(tree.root, root);
+
+        tree.insert(&[1u8; 16], b"changed");
+        assert_eq!(HASH_PAIR_CALLS.with(Cell::get), 128);
+    }
+
+    #[test]
+    fn test_compute_root_after_update() {
+        let mut tree = setup_tree();
+        let inserted = &b"inserted"[..];
+        let transitions = [
+            ([5u8; 16], None, Some(inserted)),
+            ([1u8; 16], Some(&b"value2"[..]), Some(b"updated")),
+            ([0u8; 16], Some(b"value1"), None),
+            ([7u8; 16], None, None),
+        ];
+        for (key, old_value, new_value) in transitions {
+            let proof = tree.generate_proof(&key);
+            let new_root = <SparseMerkleTree>::compute_root_after_update(
+                &tree.verifier(),
+                &key,
+                old_value,
+                new_value,
+                &proof,
+            );
+
+            let root = match new_value {
+                Some(value) => tree.insert(&key, value),
+                None => tree.delete(&key),
+            };
+            assert_eq!(new_root, Ok(Some(root)));
+            assert_eq!(root, tree.root());
+        }
+    }
+
+    #[test]
+    fn test_compute_root_after_update_rejects_stale_inputs() {
+        let tree = setup_tree();
+        let key = [1u8; 16];
+        let proof = tree.generate_proof(&key);
+        let root = tree.root;
+        let empty_root = SparseMerkleTree::new().root;
+
+        let update = |root: &[u8; 32], old_value, proof| {
+            <SparseMerkleTree>::compute_root_after_update(
+                &tree.verifier().with_root(*root),
+                &key,
+                old_value,
+                Some(b"new"),
+                proof,
+            )
+        };
+        assert!(update(&root, Some(b"value2"), &proof).unwrap().is_some());
+        assert_eq!(update(&root, Some(b"value1"), &proof), Ok(None));
+        assert_eq!(update(&root, None, &proof), Ok(None));
+        assert_eq!(update(&empty_root, Some(b"value2"), &proof), Ok(None));
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert_eq!(
+            update(&root, Some(b"value2"), &short),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 127
+            })
+        );
+    }
+
+    #[test]
+    fn test_subtree_root_by_prefix() {
+        // Both trees hold the same keys under 0x01.., and differ under 0x02...
+        let mut left = SparseMerkleTree::new();
+        let mut right = SparseMerkleTree::new();
+        for tree in [&mut left, &mut right] {
+            tree.insert(
+                &[0x01, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                b"a",
+            );
+            tree.insert(
+                &[0x01, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                b"b",
+            );
+        }
+        left.insert(&[2u8; 16], b"left");
+        right.insert(&[2u8; 16], b"right");
+        assert_ne!(left.root(), right.root());
+
+        assert_eq!(
+            left.subtree_root(&[0x01], 8),
+            right.subtree_root(&[0x01], 8)
+        );
+        // Bits past the prefix are ignored.
+        assert_eq!(
+            left.subtree_root(&[0x01, 0x10], 8),
+            left.subtree_root(&[0x01], 8)
+        );
+        assert_ne!(
+            left.subtree_root(&[0x02], 8),
+            right.subtree_root(&[0x02], 8)
+        );
+        assert_eq!(left.subtree_root(&[], 0), left.root());
+        assert_eq!(left.subtree_root(&[0x80], 1), left.default_nodes[1]);
+        assert_eq!(left.subtree_root(&[0x03], 8), left.default_nodes[8]);
+    }
+
+    #[test]
+    fn test_subtree_proof() {
+        let tree = setup_tree();
+        let prefix = [0x01, 0x01, 0x01];
+        let subtree_root = tree.subtree_root(&prefix, 17);
+        assert_ne!(subtree_root, tree.default_nodes[17]);
+        let proof = tree.generate_subtree_proof(&prefix, 17);
+        assert_eq!(proof.siblings.len(), 17);
+
+        let verify = |prefix: &[u8], bits, subtree_root, proof| {
+            <SparseMerkleTree>::verify_subtree(&tree.root(), prefix, bits, subtree_root, proof)
+        };
+        assert_eq!(verify(&prefix, 17, &subtree_root, &proof), Ok(true));
+        assert_eq!(verify(&prefix, 17, &[1; 32], &proof), Ok(false));
+        assert_eq!(
+            verify(&[0x01, 0x01, 0x80], 17, &subtree_root, &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verify(&prefix, 16, &subtree_root, &proof),
+            Err(SmtError::DepthMismatch {
+                expected: 16,
+                actual: 17
+            })
+        );
+        assert_eq!(
+            verify(&prefix[..2], 17, &subtree_root, &proof),
+            Err(SmtError::PrefixLength { bits: 17, len: 2 })
+        );
+        assert_eq!(
+            verify(&[0; 17], 129, &subtree_root, &proof),
+            Err(SmtError::InvalidDepth {
+                depth: 129,
+                max: 128
+            })
+        );
+
+        // An empty subtree is proven by its default node.
+        let empty = [0xf0];
+        let proof = tree.generate_subtree_proof(&empty, 4);
+        assert_eq!(verify(&empty, 4, &tree.default_nodes[4], &proof), Ok(true));
+    }
+
+    #[test]
+    fn test_snapshot_is_isolated_from_later_writes() {
+        let mut tree = setup_tree();
+        let snapshot = tree.snapshot();
+        assert!(Arc::ptr_eq(&tree.store.nodes, &snapshot.store.nodes));
+        assert!(Arc::ptr_eq(&tree.store.values, &snapshot.store.values));
+
+        let root = snapshot.root;
+        let proof = snapshot.generate_proof(&[1u8; 16]);
+        tree.insert(&[1u8; 16], b"speculative");
+        tree.insert(&[9u8; 16], b"value9");
+        tree.delete(&[0u8; 16]);
+
+        assert_ne!(tree.root, root);
+        assert_eq!(snapshot.root, root);
+        assert_eq!(snapshot.generate_proof(&[1u8; 16]), proof);
+        assert_eq!(
+            snapshot.verify_proof(&[1u8; 16], Some(b"value2"), &proof),
+            Ok(true)
+        );
+        assert_eq!(snapshot.get(&[0u8; 16]), Some(b"value1".to_vec()));
+        assert_eq!(snapshot.get(&[9u8; 16]), None);
+        assert_eq!(snapshot.root, setup_tree().root);
+
+        // Writes to the snapshot don't leak back either.
+        let mut snapshot = snapshot;
+        let diverged = tree.root;
+        snapshot.insert(&[4u8; 16], b"value4");
+        assert_eq!(tree.root, diverged);
+        assert_eq!(tree.get(&[4u8; 16]), None);
+    }
+
+    #[test]
+    fn test_proof_hex_round_trip() {
+        let tree = setup_tree();
+        let proof = tree.generate_proof(&[2u8; 16]);
+        let hex = proof.to_hex();
+        assert!(hex.starts_with("0x0080"));
+        assert_eq!(hex.len(), 2 + 2 * (3 + 128 * 32));
+
+        for input in [hex.clone(), hex[2..].to_string(), hex.to_uppercase()] {
+            let decoded = SmtProof::from_hex(&input).unwrap();
+            assert_eq!(decoded, proof);
+            assert_eq!(
+                tree.verify_proof(&[2u8; 16], Some(b"value3"), &decoded),
+                Ok(true)
+            );
+        }
+    }
+
+    #[test]
+    fn test_proof_hex_errors() {
+        let hex = setup_tree().generate_proof(&[2u8; 16]).to_hex();
+
+        assert_eq!(
+            SmtProof::from_hex(&hex[..hex.len() - 1]),
+            Err(ProofHexError::OddLength)
+        );
+        assert_eq!(
+            SmtProof::from_hex("0x00g0"),
+            Err(ProofHexError::InvalidCharacter {
+                index: 2,
+                character: 'g'
+            })
+        );
+        assert_eq!(
+            SmtProof::from_hex(&hex[..hex.len() - 2]),
+            Err(ProofHexError::InvalidProof(SmtError::InvalidProofLength {
+                expected: 3 + 128 * 32,
+                actual: 2 + 128 * 32
+            }))
+        );
+        assert_eq!(
+            SmtProof::from_hex(""),
+            Err(ProofHexError::InvalidProof(SmtError::InvalidProofLength {
+                expected: 3,
+                actual: 0
+            }))
+        );
+        assert_eq!(
+            ProofHexError::OddLength.to_string(),
+            "hex string has an odd number of digits"
+        );
+    }
+
+    #[test]
+    fn test_path_committing_vectors() {
+        let tree = setup_tree().iter().fold(
+            SparseMerkleTree::new().with_flavor(SmtFlavor::PathCommitting),
+            |mut tree, (key, value)| {
+                tree.insert(key, value);
+                tree
+            },
+        );
+        assert_eq!(
+            hex(&tree.root()),
+            "107d1b5ffd35bf301b86f149e19a4dfa1d6df9ecadea7b7ee6d5ba4195296037"
+        );
+
+        let vectors: [(&[u8], &str); 2] = [
+            (
+                b"value",
+                "ea1c09e7520bae7392dfa586daa90f40871eb3ab573fc95e1e6896557e023d5d",
+            ),
+            (
+                b"",
+                "95a14ec2065bf1c937a309ca3abbf000126aca2e7737c648902051b75a764c69",
+            ),
+        ];
+        for (value, root) in vectors {
+            let mut tree = SparseMerkleTree::new().with_flavor(SmtFlavor::PathCommitting);
+            tree.insert(&[7u8; 16], value);
+            assert_eq!(hex(&tree.root()), root);
+        }
+
+        let mut keccak = SparseMerkleTree::<Keccak256Hasher>::with_hasher()
+            .with_flavor(SmtFlavor::PathCommitting);
+        for (key, value) in &setup_tree() {
+            keccak.insert(key, value);
+        }
+        assert_eq!(
+            hex(&keccak.root()),
+            "22bab436aa70be7f300a18f3f6b792cce0e91e6958d3d4515581fb304d3d56ef"
+        );
+
+        let mut key = [0u8; 16];
+        key[0] = 0b1101_0000;
+        let mut shallow = SparseMerkleTree::with_depth(4).with_flavor(SmtFlavor::PathCommitting);
+        shallow.insert(&key, b"value");
+        assert_eq!(
+            hex(&shallow.root()),
+            "cfe8327cf74a1518e1599418819428f3df9bd2b4b123e19d457f6ce9a46484b1"
+        );
+    }
+
+    #[test]
+    fn test_path_committing_binds_proofs_to_keys() {
+        // 0100 and 0101 share their first three bits and hold the same value.
+        let (mut a_key, mut b_key) = ([0u8; 16], [0u8; 16]);
+        a_key[0] = 0x40;
+        b_key[0] = 0x50;
+        for (flavor, replays) in [(SmtFlavor::Plain, true), (SmtFlavor::PathCommitting, false)] {
+            let mut tree = SparseMerkleTree::with_depth(4).with_flavor(flavor);
+            tree.insert(&a_key, b"same");
+            tree.insert(&b_key, b"same");
+            let proof = tree.generate_proof(&a_key);
+            assert_eq!(tree.verify_proof(&a_key, Some(b"same"), &proof), Ok(true));
+            assert_eq!(
+                tree.verify_proof(&b_key, Some(b"same"), &proof),
+                Ok(replays)
+            );
+        }
+    }
+
+    #[test]
+    fn test_length_prefixed_vectors() {
+        let key = |path: u8| {
+            let mut key = [0u8; 16];
+            key[0] = path;
+            key
+        };
+        let leaf = |value: &[u8]| {
+            <SparseMerkleTree>::leaf_hash(SmtFlavor::LengthPrefixed, &[0; 16], value)
+        };
+        // sha256(0x00 || 01 00 00 00 00 00 00 00 || "a")
+        assert_eq!(
+            hex(&leaf(b"a")),
+            "07cd453d9d749236dc7ce0ba53f7dee08794e5b9594a671247c765389d0b903f"
+        );
+        assert_eq!(
+            hex(&leaf(b"")),
+            "3e7077fd2f66d689e0cee6a7cf5b37bf2dca7c979af356d0a31cbc5c85605c7d"
+        );
+        // A value that starts with a length no longer hashes like the shorter value.
+        assert_ne!(
+            leaf(b"a"),
+            <SparseMerkleTree>::leaf_hash(SmtFlavor::Plain, &[0; 16], b"a")
+        );
+        assert_ne!(
+            leaf(&[&[1, 0, 0, 0, 0, 0, 0, 0], &b"a"[..]].concat()),
+            leaf(b"a")
+        );
+
+        let mut tree = SparseMerkleTree::with_depth(4).with_flavor(SmtFlavor::LengthPrefixed);
+        for (path, value) in [(0x50, b"a"), (0xc0, b"b"), (0x60, b"c")] {
+            tree.insert(&key(path), value);
+        }
+        assert_eq!(
+            hex(&tree.root()),
+            "c9e8554fb62ecb244d064e1adb4a590b94adcf568db8520e8cb27f33c533501b"
+        );
+
+        let proof = tree.generate_proof(&key(0x50));
+        assert_eq!(proof.flavor, SmtFlavor::LengthPrefixed);
+        assert_eq!(tree.verify_proof(&key(0x50), Some(b"a"), &proof), Ok(true));
+        assert_eq!(proof.to_bytes()[2], 0b100);
+        assert_eq!(SmtProof::from_bytes(&proof.to_bytes()), Ok(proof.clone()));
+        assert_eq!(
+            SmtProof::decode::<Sha256Hasher>(&proof.encode::<Sha256Hasher>()),
+            Ok(proof)
+        );
+    }
+
+    #[test]
+    fn test_proofs_do_not_cross_flavors() {
+        let plain = setup_tree();
+        let mut committing = SparseMerkleTree::new().with_flavor(SmtFlavor::PathCommitting);
+        committing.insert_batch(&[
+            ([0u8; 16], b"value1".to_vec()),
+            ([1u8; 16], b"value2".to_vec()),
+            ([2u8; 16], b"value3".to_vec()),
+        ]);
+        assert_ne!(committing.root(), plain.root());
+
+        for key in [[1u8; 16], [3u8; 16]] {
+            let value = plain.get(&key);
+            let plain_proof = plain.generate_proof(&key);
+            let committing_proof = committing.generate_proof(&key);
+            assert_eq!(committing_proof.flavor, SmtFlavor::PathCommitting);
+            assert_eq!(
+                committing.verify_proof(&key, value.as_deref(), &committing_proof),
+                Ok(true)
+            );
+            assert_eq!(
+                committing.verify_proof(&key, value.as_deref(), &plain_proof),
+                Err(SmtError::FlavorMismatch {
+                    expected: SmtFlavor::PathCommitting,
+                    actual: SmtFlavor::Plain
+                })
+            );
+            assert_eq!(
+                plain.verify_proof(&key, value.as_deref(), &committing_proof),
+                Err(SmtError::FlavorMismatch {
+                    expected: SmtFlavor::Plain,
+                    actual: SmtFlavor::PathCommitting
+                })
+            );
+        }
+
+        // A relabelled flavor is checked against the verifier, not trusted.
+        let mut relabelled = plain.generate_proof(&[1u8; 16]);
+        relabelled.flavor = SmtFlavor::PathCommitting;
+        assert_eq!(
+            plain
+                .verifier()
+                .verify(&[1u8; 16], Some(b"value2"), &relabelled),
+            Err(SmtError::FlavorMismatch {
+                expected: SmtFlavor::Plain,
+                actual: SmtFlavor::PathCommitting
+            })
+        );
+
+        let proof = committing.generate_proof(&[2u8; 16]);
+        let decoded = SmtProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded, proof);
+        let compressed = committing.compress_proof(&proof);
+        assert_eq!(
+            committing.verify_compressed(&[2u8; 16], Some(b"value3"), &compressed),
+            Ok(true)
+        );
+        assert_eq!(
+            plain.verify_compressed(&[2u8; 16], Some(b"value3"), &compressed),
+            Err(SmtError::FlavorMismatch {
+                expected: SmtFlavor::Plain,
+                actual: SmtFlavor::PathCommitting
+            })
+        );
+    }
+
+    #[test]
+    fn test_bit_order_vectors() {
+        let key = |path: u8| {
+            let mut key = [0u8; 16];
+            key[0] = path;
+            key
+        };
+        let entries = [(key(0x50), b"a"), (key(0x60), b"c"), (key(0xc0), b"b")];
+        let tree = |bit_order| {
+            let mut tree = SparseMerkleTree::with_depth(4).with_bit_order(bit_order);
+            for (key, value) in &entries {
+                tree.insert(key, *value);
+            }
+            tree
+        };
+        let msb = tree(BitOrder::MsbFirst);
+        let lsb = tree(BitOrder::LsbFirst);
+        assert_eq!(
+            hex(&msb.root()),
+            "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d"
+        );
+        // The same leaves at paths 1010, 0110 and 0011.
+        assert_eq!(
+            hex(&lsb.root()),
+            "5222ac5e6b0ced481a171fd63544f80b396c0880c937d340a81b11dc65d5c764"
+        );
+
+        for (key, value) in &entries {
+            let value = Some(&value[..]);
+            let msb_proof = msb.generate_proof(key);
+            let lsb_proof = lsb.generate_proof(key);
+            assert_eq!(lsb_proof.bit_order, BitOrder::LsbFirst);
+            assert_eq!(msb.verify_proof(key, value, &msb_proof), Ok(true));
+            assert_eq!(lsb.verify_proof(key, value, &lsb_proof), Ok(true));
+            assert_eq!(
+                msb.verify_proof(key, value, &lsb_proof),
+                Err(SmtError::BitOrderMismatch {
+                    expected: BitOrder::MsbFirst,
+                    actual: BitOrder::LsbFirst
+                })
+            );
+            let mut relabeled = lsb_proof.clone();
+            relabeled.bit_order = BitOrder::MsbFirst;
+            assert_eq!(
+                lsb.verifier().verify(key, value, &relabeled),
+                Err(SmtError::BitOrderMismatch {
+                    expected: BitOrder::LsbFirst,
+                    actual: BitOrder::MsbFirst
+                })
+            );
+
+            // The order survives both encodings.
+            assert_eq!(lsb_proof.to_bytes()[2], 0b1000);
+            assert_eq!(
+                SmtProof::from_bytes(&lsb_proof.to_bytes()),
+                Ok(lsb_proof.clone())
+            );
+            let encoded = lsb_proof.encode::<Sha256Hasher>();
+            assert_eq!(
+                SmtProof::decode::<Sha256Hasher>(&encoded),
+                Ok(lsb_proof.clone())
+            );
+            let witness =
+                <SparseMerkleTree>::to_witness(&lsb.verifier(), key, value, &lsb_proof).unwrap();
+            assert_eq!(
+                <SparseMerkleTree>::verify_witness(&lsb.root(), &witness),
+                Ok(true)
+            );
+        }
+
+        let keys: Vec<_> = entries.iter().map(|(key, _)| *key).collect();
+        let multiproof = lsb.generate_multiproof(&keys);
+        let proven: Vec<_> = entries
+            .iter()
+            .map(|(key, value)| (*key, Some(&value[..])))
+            .collect();
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&lsb.verifier(), &proven, &multiproof),
+            Ok(true)
+        );
+        assert_eq!(lsb.verify_integrity(), Ok(()));
+        let mut changed = lsb.snapshot();
+        changed.delete(&key(0xc0));
+        changed.insert(&key(0x10), b"d");
+        let keys: Vec<_> = lsb.diff(&changed).iter().map(|diff| diff.key).collect();
+        assert_eq!(keys, [key(0x10), key(0xc0)]);
+    }
+
+    #[test]
+    fn test_relabelled_bit_order_cannot_forge_a_key() {
+        let mut tree = SparseMerkleTree::with_depth(4);
+        let mut key = [0u8; 16];
+        key[0] = 0x50;
+        tree.insert(&key, b"a");
+
+        // Read LsbFirst, the path of 0101 is that of 1010 read MsbFirst.
+        let mut forged = tree.generate_proof(&key);
+        forged.bit_order = BitOrder::LsbFirst;
+        let mut reversed = [0u8; 16];
+        reversed[0] = 0xa0;
+        let trusting = tree.verifier().with_bit_order(BitOrder::LsbFirst);
+        assert_eq!(trusting.verify(&reversed, Some(b"a"), &forged), Ok(true));
+
+        assert_eq!(
+            tree.verifier().verify(&reversed, Some(b"a"), &forged),
+            Err(SmtError::BitOrderMismatch {
+                expected: BitOrder::MsbFirst,
+                actual: BitOrder::LsbFirst
+            })
+        );
+        assert_eq!(
+            tree.verify_proof(&reversed, Some(b"a"), &tree.generate_proof(&reversed)),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "only be changed on an empty tree")]
+    fn test_flavor_cannot_change_on_non_empty_tree() {
+        setup_tree().with_flavor(SmtFlavor::PathCommitting);
     }
 }
@@ -0,0 +1,286 @@
+use super::cache::{NodeCache, ProofCache};
+use super::{bit, proof, truncate, with_bit, BitOrder, Smt, SmtFlavor, SmtHasher, SmtProof};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// Storage for trees whose nodes live behind a network, the async counterpart of
+/// `NodeStore`. Each tree operation reads all the nodes it needs with one
+/// `get_nodes` call and writes them back with one `write_nodes` call, so a remote
+/// store can serve either in a single round trip. Implementations may write
+/// `async fn` for each method.
+pub trait AsyncNodeStore<const N: usize> {
+    /// Hashes of the nodes at `positions`, given as `(level, path)` like
+    /// `NodeStore::get`, in the same order.
+    fn get_nodes(
+        &self,
+        positions: &[(usize, [u8; N])],
+    ) -> impl Future<Output = Vec<Option<[u8; 32]>>> + Send;
+    /// Stores each `(level, path, hash)`, deleting the node where `hash` is `None`.
+    fn write_nodes(
+        &mut self,
+        nodes: Vec<(usize, [u8; N], Option<[u8; 32]>)>,
+    ) -> impl Future<Output = ()> + Send;
+
+    fn get_value(&self, key: &[u8; N]) -> impl Future<Output = Option<Vec<u8>>> + Send;
+    /// Stores a leaf value, returning the one it replaced.
+    fn put_value(
+        &mut self,
+        key: [u8; N],
+        value: Vec<u8>,
+    ) -> impl Future<Output = Option<Vec<u8>>> + Send;
+    /// Removes a leaf value, returning it if it was present.
+    fn delete_value(&mut self, key: &[u8; N]) -> impl Future<Output = Option<Vec<u8>>> + Send;
+}
+
+/// Async counterparts of the core operations, with the same roots and proofs as
+/// the sync ones. Trees over an async store are `SmtFlavor::Plain` with no
+/// default value, and don't use the node cache or count into `SmtMetrics`.
+impl<const N: usize, H: SmtHasher, S: AsyncNodeStore<N>> Smt<N, H, S> {
+    /// Opens a tree of `depth` levels over `store`, reading its root. Panics like
+    /// `from_store` for a depth outside `1..=8 * N`.
+    pub async fn from_async_store(depth: usize, store: S) -> Self {
+        if let Err(err) = proof::check_depth::<N>(depth) {
+            panic!("{err}");
+        }
+        let mut default_nodes = H::zero_hashes(depth);
+        default_nodes.reverse();
+        let root = store.get_nodes(&[(0, [0; N])]).await[0].unwrap_or(default_nodes[0]);
+        Self {
+            root,
+            depth,
+            flavor: SmtFlavor::Plain,
+            bit_order: BitOrder::MsbFirst,
+            default_value: None,
+            store_values: true,
+            default_nodes: default_nodes.into(),
+            store,
+            cache: NodeCache::default(),
+            proof_cache: ProofCache::default(),
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+            metrics: None,
+            undo_log: VecDeque::new(),
+            undo_depth: 0,
+            hasher: PhantomData,
+        }
+    }
+
+    pub async fn get_async(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.store.get_value(&self.async_path(key)).await
+    }
+
+    /// Same proof as `generate_proof`, read with a single `get_nodes` call.
+    pub async fn generate_proof_async(&self, key: &[u8; N]) -> SmtProof {
+        let path = self.async_path(key);
+        SmtProof {
+            depth: self.depth,
+            flavor: self.flavor,
+            bit_order: self.bit_order,
+            hashed_key: false,
+            siblings: self.siblings_async(&path).await,
+        }
+    }
+
+    /// Like `insert`, returning the new root.
+    pub async fn insert_async(&mut self, key: &[u8; N], value: &[u8]) -> [u8; 32] {
+        let path = self.async_path(key);
+        let leaf = proof::leaf_hash::<N, H>(self.flavor, &path, value);
+        self.update_path_async(path, leaf).await;
+        self.store.put_value(path, value.to_vec()).await;
+        self.root
+    }
+
+    /// Like `delete`, returning the new root.
+    pub async fn delete_async(&mut self, key: &[u8; N]) -> [u8; 32] {
+        let path = self.async_path(key);
+        if self.store.delete_value(&path).await.is_some() {
+            self.update_path_async(path, self.default_nodes[self.depth])
+                .await;
+        }
+        self.root
+    }
+
+    fn async_path(&self, key: &[u8; N]) -> [u8; N] {
+        if let Err(err) = proof::check_key(key, self.depth) {
+            panic!("{err}");
+        }
+        *key
+    }
+
+    /// Siblings along `path` from the leaf up, defaults filled in.
+    async fn siblings_async(&self, path: &[u8; N]) -> Vec<[u8; 32]> {
+        let positions: Vec<_> = (1..=self.depth)
+            .rev()
+            .map(|level| {
+                let sibling = with_bit(&truncate(path, level), level - 1, !bit(path, level - 1));
+                (level, sibling)
+            })
+            .collect();
+        let nodes = self.store.get_nodes(&positions).await;
+        positions
+            .iter()
+            .zip(nodes)
+            .map(|(&(level, _), node)| node.unwrap_or(self.default_nodes[level]))
+            .collect()
+    }
+
+    /// Writes `leaf` at the end of `path` and rehashes up to the root, as one read
+    /// and one write batch.
+    async fn update_path_async(&mut self, path: [u8; N], leaf: [u8; 32]) {
+        let siblings = self.siblings_async(&path).await;
+        let mut writes = Vec::with_capacity(self.depth + 1);
+        let mut current_node = leaf;
+        for (i, sibling) in (0..=self.depth)
+            .rev()
+            .zip(siblings.iter().map(Some).chain([None]))
+        {
+            let default = current_node == self.default_nodes[i];
+            writes.push((i, truncate(&path, i), (!default).then_some(current_node)));
+            if let Some(sibling) = sibling {
+                let default = &self.default_nodes[i];
+                current_node = if current_node == *default && sibling == default {
+                    self.default_nodes[i - 1]
+                } else if !bit(&path, i - 1) {
+                    H::hash_pair(&current_node, sibling)
+                } else {
+                    H::hash_pair(sibling, &current_node)
+                };
+            }
+        }
+        self.store.write_nodes(writes).await;
+        self.root = current_node;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NodeStore, SparseMerkleTree};
+    use std::collections::HashMap;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    /// In-memory stand-in for a remote store, counting node round trips.
+    #[derive(Default)]
+    struct MockStore {
+        nodes: HashMap<(usize, [u8; 16]), [u8; 32]>,
+        values: HashMap<[u8; 16], Vec<u8>>,
+        round_trips: AtomicUsize,
+    }
+
+    impl AsyncNodeStore<16> for MockStore {
+        async fn get_nodes(&self, positions: &[(usize, [u8; 16])]) -> Vec<Option<[u8; 32]>> {
+            self.round_trips.fetch_add(1, Ordering::Relaxed);
+            positions
+                .iter()
+                .map(|position| self.nodes.get(position).copied())
+                .collect()
+        }
+
+        async fn write_nodes(&mut self, nodes: Vec<(usize, [u8; 16], Option<[u8; 32]>)>) {
+            self.round_trips.fetch_add(1, Ordering::Relaxed);
+            for (level, path, hash) in nodes {
+                match hash {
+                    Some(hash) => self.nodes.insert((level, path), hash),
+                    None => self.nodes.remove(&(level, path)),
+                };
+            }
+        }
+
+        async fn get_value(&self, key: &[u8; 16]) -> Option<Vec<u8>> {
+            self.values.get(key).cloned()
+        }
+
+        async fn put_value(&mut self, key: [u8; 16], value: Vec<u8>) -> Option<Vec<u8>> {
+            self.values.insert(key, value)
+        }
+
+        async fn delete_value(&mut self, key: &[u8; 16]) -> Option<Vec<u8>> {
+            self.values.remove(key)
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct ThreadWaker(Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_store_matches_sync_tree() {
+        block_on(async {
+            let mut tree: Smt<16, crate::Sha256Hasher, MockStore> =
+                Smt::from_async_store(128, MockStore::default()).await;
+            let mut reference = SparseMerkleTree::new();
+            assert_eq!(tree.root(), reference.root());
+
+            for i in 0..20u8 {
+                assert_eq!(
+                    tree.insert_async(&[i; 16], &[i]).await,
+                    reference.insert(&[i; 16], &[i])
+                );
+            }
+            assert_eq!(
+                tree.insert_async(&[3; 16], b"changed").await,
+                reference.insert(&[3; 16], b"changed")
+            );
+            for i in [5, 7, 42] {
+                assert_eq!(
+                    tree.delete_async(&[i; 16]).await,
+                    reference.delete(&[i; 16])
+                );
+            }
+
+            for key in [[3; 16], [5; 16], [9; 16]] {
+                assert_eq!(tree.get_async(&key).await, reference.get(&key));
+                let proof = tree.generate_proof_async(&key).await;
+                assert_eq!(proof, reference.generate_proof(&key));
+            }
+            // Only non-default nodes are kept, as in the sync store.
+            assert_eq!(tree.store.nodes.len(), reference.store.all_nodes().len());
+
+            // Reopening the store finds the same root.
+            let depth = tree.depth;
+            let reopened: Smt<16, crate::Sha256Hasher, MockStore> =
+                Smt::from_async_store(depth, tree.store).await;
+            assert_eq!(reopened.root(), reference.root());
+        });
+    }
+
+    #[test]
+    fn test_async_store_batches_reads() {
+        block_on(async {
+            let mut tree: Smt<16, crate::Sha256Hasher, MockStore> =
+                Smt::from_async_store(128, MockStore::default()).await;
+            tree.insert_async(&[1; 16], b"one").await;
+            tree.insert_async(&[2; 16], b"two").await;
+
+            // One read and one write per write, one read per proof.
+            tree.store.round_trips.store(0, Ordering::Relaxed);
+            tree.insert_async(&[3; 16], b"three").await;
+            assert_eq!(tree.store.round_trips.load(Ordering::Relaxed), 2);
+            tree.generate_proof_async(&[1; 16]).await;
+            assert_eq!(tree.store.round_trips.load(Ordering::Relaxed), 3);
+
+            // The futures can be spawned on multi-threaded runtimes.
+            fn assert_send<T: Send>(_: T) {}
+            assert_send(tree.insert_async(&[4; 16], b"four"));
+        });
+    }
+}
@@ -0,0 +1,232 @@
+use super::{bit, truncate, NodeStore, Smt, SmtError, SmtHasher};
+use std::collections::btree_map::{BTreeMap, Entry};
+
+impl<const N: usize> Smt<N> {
+    /// Builds a full-depth tree from `(key, value)` pairs in strictly ascending key
+    /// order, in a single pass. Each node is hashed once, when the keys move past
+    /// its subtree, so only one path's worth of pending nodes is held at a time.
+    /// Fails with `UnsortedKey` for a key not after the previous one, including a
+    /// repeated key. The root matches inserting the same entries one by one.
+    pub fn from_sorted_entries<V: AsRef<[u8]>>(
+        entries: impl IntoIterator<Item = ([u8; N], V)>,
+    ) -> Result<Self, SmtError> {
+        let mut tree = Self::new();
+        tree.load_sorted(entries)?;
+        Ok(tree)
+    }
+
+    /// Builds a full-depth tree from `(key, value)` pairs in any order, with the
+    /// same root whatever the order. A key may repeat with the same value, but
+    /// fails with `ConflictingKey` if it repeats with another one.
+    pub fn from_entries<V: AsRef<[u8]>>(
+        entries: impl IntoIterator<Item = ([u8; N], V)>,
+    ) -> Result<Self, SmtError> {
+        let mut sorted = BTreeMap::new();
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            match sorted.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(entry) if entry.get().as_ref() != value.as_ref() => {
+                    return Err(SmtError::ConflictingKey { index });
+                }
+                Entry::Occupied(_) => {}
+            }
+        }
+        Self::from_sorted_entries(sorted)
+    }
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Writes sorted entries into an empty tree, see `from_sorted_entries`.
+    pub(super) fn load_sorted<V: AsRef<[u8]>>(
+        &mut self,
+        entries: impl IntoIterator<Item = ([u8; N], V)>,
+    ) -> Result<(), SmtError> {
+        // Roots of subtrees still waiting for their right siblings, deepest last.
+        let mut pending: Vec<(usize, [u8; N], [u8; 32])> = Vec::new();
+        let mut previous: Option<[u8; N]> = None;
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            Self::check_key(&key, self.depth)?;
+            if let Some(previous) = previous {
+                if key <= previous {
+                    return Err(SmtError::UnsortedKey { index });
+                }
+                // Everything below where the paths split is complete.
+                let split = (0..self.depth).find(|&i| bit(&key, i) != bit(&previous, i));
+                self.fold_pending(&mut pending, split.unwrap() + 1);
+            }
+            let value = value.as_ref();
+            let leaf = self.hash_value(&key, value);
+            self.set_node(self.depth, key, leaf);
+            self.count_write();
+            self.store.put_value(key, value.to_vec());
+            pending.push((self.depth, key, leaf));
+            previous = Some(key);
+        }
+        self.fold_pending(&mut pending, 0);
+        self.root = pending
+            .pop()
+            .map_or(self.default_nodes[0], |(_, _, root)| root);
+        Ok(())
+    }
+
+    /// Hashes the deepest pending subtree up to `level`, pairing it with its left
+    /// sibling when that is pending too and with a default node otherwise.
+    fn fold_pending(&mut self, pending: &mut Vec<(usize, [u8; N], [u8; 32])>, level: usize) {
+        while let Some(&(node_level, prefix, node)) = pending.last() {
+            if node_level <= level {
+                break;
+            }
+            pending.pop();
+            let parent_level = node_level - 1;
+            let parent = match pending.last() {
+                Some(&(left_level, _, left)) if left_level == node_level => {
+                    pending.pop();
+                    self.hash_children(parent_level, &left, &node)
+                }
+                _ if bit(&prefix, parent_level) => {
+                    self.hash_children(parent_level, &self.default_nodes[node_level], &node)
+                }
+                _ => self.hash_children(parent_level, &node, &self.default_nodes[node_level]),
+            };
+            let parent_prefix = truncate(&prefix, parent_level);
+            self.set_node(parent_level, parent_prefix, parent);
+            pending.push((parent_level, parent_prefix, parent));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::HASH_PAIR_CALLS;
+    use super::*;
+    use crate::{SparseMerkleTree, SparseMerkleTree256};
+
+    /// Sorted, distinct pseudo-random keys.
+    fn random_keys<const N: usize>(count: usize, seed: u64) -> Vec<[u8; N]> {
+        let mut state = seed;
+        let mut keys: Vec<[u8; N]> = (0..count)
+            .map(|_| {
+                let mut key = [0u8; N];
+                for byte in key.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = state as u8;
+                }
+                key
+            })
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    #[test]
+    fn test_from_sorted_entries_matches_inserts() {
+        for (count, seed) in [(0, 1), (1, 2), (2, 3), (37, 4), (500, 5)] {
+            let keys = random_keys::<16>(count, seed);
+            let mut expected = SparseMerkleTree::new();
+            for (i, key) in keys.iter().enumerate() {
+                expected.insert(key, &i.to_be_bytes());
+            }
+            let tree = SparseMerkleTree::from_sorted_entries(
+                keys.iter()
+                    .enumerate()
+                    .map(|(i, key)| (*key, i.to_be_bytes())),
+            )
+            .unwrap();
+            assert_eq!(tree.root, expected.root);
+            assert_eq!(tree.len(), keys.len());
+            assert_eq!(tree.store, expected.store);
+        }
+
+        // Keys sharing long prefixes, down to siblings at the leaf level.
+        let keys: Vec<[u8; 32]> = (0..64u8)
+            .map(|i| {
+                let mut key = [0xab; 32];
+                key[31] = i * 3;
+                key
+            })
+            .collect();
+        let mut expected = SparseMerkleTree256::new();
+        for key in &keys {
+            expected.insert(key, key);
+        }
+        let tree = SparseMerkleTree256::from_sorted_entries(keys.iter().map(|key| (*key, key)));
+        assert_eq!(tree.unwrap().root, expected.root);
+    }
+
+    #[test]
+    fn test_from_sorted_entries_hashes_each_node_once() {
+        let keys = random_keys::<16>(1000, 6);
+        HASH_PAIR_CALLS.with(|calls| calls.set(0));
+        let tree =
+            SparseMerkleTree::from_sorted_entries(keys.iter().map(|key| (*key, b"value"))).unwrap();
+        let hashes = HASH_PAIR_CALLS.with(|calls| calls.get());
+        let internal_nodes = tree.store.nodes.keys().filter(|(level, _)| *level < 128);
+        assert_eq!(hashes, internal_nodes.count());
+    }
+
+    #[test]
+    fn test_from_sorted_entries_rejects_bad_input() {
+        let entries = [([1u8; 16], b"a"), ([3u8; 16], b"b"), ([2u8; 16], b"c")];
+        assert_eq!(
+            SparseMerkleTree::from_sorted_entries(entries).unwrap_err(),
+            SmtError::UnsortedKey { index: 2 }
+        );
+        let entries = [([1u8; 16], b"a"), ([1u8; 16], b"b")];
+        assert_eq!(
+            SparseMerkleTree::from_sorted_entries(entries).unwrap_err(),
+            SmtError::UnsortedKey { index: 1 }
+        );
+    }
+
+    #[test]
+    fn test_from_entries_ignores_order() {
+        let entries: Vec<_> = random_keys::<16>(300, 7)
+            .into_iter()
+            .enumerate()
+            .map(|(i, key)| (key, i.to_be_bytes().to_vec()))
+            .collect();
+        let expected = SparseMerkleTree::from_sorted_entries(entries.clone()).unwrap();
+
+        let mut state = 8u64;
+        for _ in 0..5 {
+            let mut shuffled = entries.clone();
+            // Fisher-Yates with the same xorshift as `random_keys`.
+            for i in (1..shuffled.len()).rev() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                shuffled.swap(i, state as usize % (i + 1));
+            }
+            let tree = SparseMerkleTree::from_entries(shuffled).unwrap();
+            assert_eq!(tree.root, expected.root);
+            assert_eq!(tree.store, expected.store);
+        }
+        assert_eq!(
+            SparseMerkleTree::from_entries(Vec::<([u8; 16], Vec<u8>)>::new())
+                .unwrap()
+                .root,
+            SparseMerkleTree::new().root
+        );
+    }
+
+    #[test]
+    fn test_from_entries_rejects_conflicting_duplicates() {
+        let entries = [([2u8; 16], b"a"), ([1u8; 16], b"b"), ([2u8; 16], b"a")];
+        let tree = SparseMerkleTree::from_entries(entries).unwrap();
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(&[2u8; 16]), Some(b"a".to_vec()));
+
+        let entries = [([2u8; 16], b"a"), ([1u8; 16], b"b"), ([2u8; 16], b"c")];
+        let err = SparseMerkleTree::from_entries(entries).unwrap_err();
+        assert_eq!(err, SmtError::ConflictingKey { index: 2 });
+        assert_eq!(
+            err.to_string(),
+            "key 2 repeats an earlier key with a different value"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use super::proof::{BitOrder, SmtFlavor, SmtHasher, SmtProof};
+use super::verifier::SmtVerifier;
+use alloc::vec::Vec;
+
+/// A proof together with the key and value it proves, so it can be shipped and
+/// checked as one object, see `SmtProof::into_bundle`. `value = None` proves the
+/// key absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmtProofBundle<const N: usize> {
+    #[cfg_attr(feature = "serde", serde(with = "key_bytes"))]
+    pub key: [u8; N],
+    pub value: Option<Vec<u8>>,
+    pub depth: usize,
+    pub flavor: SmtFlavor,
+    pub hashed_key: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bit_order: BitOrder,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl SmtProof {
+    /// Bundles the proof with the key and value it was generated for.
+    pub fn into_bundle<const N: usize>(
+        self,
+        key: [u8; N],
+        value: Option<Vec<u8>>,
+    ) -> SmtProofBundle<N> {
+        SmtProofBundle {
+            key,
+            value,
+            depth: self.depth,
+            flavor: self.flavor,
+            hashed_key: self.hashed_key,
+            bit_order: self.bit_order,
+            siblings: self.siblings,
+        }
+    }
+}
+
+impl<const N: usize> SmtProofBundle<N> {
+    /// Whether the bundle proves its key holds its value in the tree `verifier`
+    /// checks. The bundle's own depth, flavor and bit order must match the
+    /// verifier's: a malformed bundle, one `SmtVerifier::verify` would fail on, is
+    /// `false` too.
+    pub fn verify<H: SmtHasher>(&self, verifier: &SmtVerifier<N, H>) -> bool {
+        let proof = SmtProof {
+            depth: self.depth,
+            flavor: self.flavor,
+            hashed_key: self.hashed_key,
+            bit_order: self.bit_order,
+            siblings: self.siblings.clone(),
+        };
+        verifier.verify(&self.key, self.value.as_deref(), &proof) == Ok(true)
+    }
+}
+
+/// Keys as byte sequences, since serde only derives arrays of fixed sizes.
+#[cfg(feature = "serde")]
+mod key_bytes {
+    use alloc::{format, vec::Vec};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<const N: usize, S: Serializer>(
+        key: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        key.as_slice().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, const N: usize, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        Vec::deserialize(deserializer)?
+            .try_into()
+            .map_err(|_| D::Error::custom(format!("keys must be {N} bytes")))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{BitOrder, SmtFlavor, SmtProofBundle, SmtVerifier, SparseMerkleTree};
+
+    fn setup_bundle() -> (SmtVerifier<16>, SmtProofBundle<16>) {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..8u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+        let proof = tree.generate_proof(&[3; 16]);
+        (tree.verifier(), proof.into_bundle([3; 16], Some(vec![3])))
+    }
+
+    #[test]
+    fn test_bundle_verifies() {
+        let (verifier, bundle) = setup_bundle();
+        assert!(bundle.verify(&verifier));
+        assert!(!bundle.verify(&verifier.with_root([0; 32])));
+
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[1; 16], b"one");
+        let absent = tree.generate_proof(&[2; 16]).into_bundle([2; 16], None);
+        assert!(absent.verify(&tree.verifier()));
+    }
+
+    #[test]
+    fn test_tampered_bundle_fails() {
+        let (verifier, bundle) = setup_bundle();
+        let tampered = |tamper: &dyn Fn(&mut SmtProofBundle<16>)| {
+            let mut bundle = bundle.clone();
+            tamper(&mut bundle);
+            bundle.verify(&verifier)
+        };
+
+        for i in 0..128 {
+            assert!(
+                !tampered(&|b| b.key[i / 8] ^= 0x80 >> (i % 8)),
+                "key bit {i}"
+            );
+        }
+        assert!(!tampered(&|b| b.value = Some(vec![4])));
+        assert!(!tampered(&|b| b.value = Some(vec![])));
+        assert!(!tampered(&|b| b.value = None));
+        assert!(!tampered(&|b| b.depth = 127));
+        assert!(!tampered(&|b| b.flavor = SmtFlavor::PathCommitting));
+        assert!(!tampered(&|b| b.flavor = SmtFlavor::LengthPrefixed));
+        assert!(!tampered(&|b| b.hashed_key = true));
+        assert!(!tampered(&|b| b.bit_order = BitOrder::LsbFirst));
+        // Read LsbFirst, the reversed key has the original key's path.
+        assert!(!tampered(&|b| {
+            b.bit_order = BitOrder::LsbFirst;
+            b.key = u128::from_be_bytes(b.key).reverse_bits().to_be_bytes();
+        }));
+        for i in 0..128 {
+            assert!(!tampered(&|b| b.siblings[i][31] ^= 1), "sibling {i}");
+        }
+        assert!(!tampered(&|b| {
+            b.siblings.pop();
+        }));
+        assert!(!tampered(&|b| b.siblings.push([0; 32])));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bundle_serde_round_trip() {
+        let (verifier, bundle) = setup_bundle();
+        let json = serde_json::to_string(&bundle).unwrap();
+        let restored: SmtProofBundle<16> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, bundle);
+        assert!(restored.verify(&verifier));
+
+        assert!(serde_json::from_str::<SmtProofBundle<20>>(&json).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use super::{NodePosition, SmtProof};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// Bounded least-recently-used cache of node hashes in front of the store. It is
+/// write-through: `Smt::set_node` refreshes the entries along a changed path, so
+/// nothing else ever goes stale and nothing needs invalidating.
+pub(super) type NodeCache<const N: usize> = LruCache<NodePosition<N>, [u8; 32]>;
+
+#[derive(Debug, Clone)]
+pub(super) struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    // Keys by last use, oldest first.
+    order: BTreeMap<u64, K>,
+}
+
+impl<K, V> Default for LruCache<K, V> {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Copy + Eq + Hash, V> LruCache<K, V> {
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, evicting the oldest entries if it shrank. Zero disables the cache.
+    pub(super) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    /// Looks up an entry without counting as a use, for `&self` readers.
+    pub(super) fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub(super) fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.next_tick();
+        let (value, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        self.order.insert(tick, *key);
+        *used = tick;
+        Some(value)
+    }
+
+    pub(super) fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        if let Some((_, used)) = self.entries.insert(key, (value, tick)) {
+            self.order.remove(&used);
+        } else if self.entries.len() > self.capacity {
+            self.evict();
+        }
+        self.order.insert(tick, key);
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, key)) = self.order.pop_first() {
+            self.entries.remove(&key);
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// Bounded least-recently-used cache of proofs, see `Smt::set_proof_cache_capacity`.
+/// Entries are only good for the root they were generated at: a lookup or insert
+/// at another root drops them all first, which is how writes invalidate the cache.
+/// Behind a mutex so `generate_proof` can fill it through `&self`. The capacity
+/// is kept outside it, so readers of a tree with the cache off never lock.
+#[derive(Debug, Default)]
+pub(super) struct ProofCache<const N: usize> {
+    capacity: usize,
+    inner: Mutex<([u8; 32], ProofLru<N>)>,
+}
+
+type ProofLru<const N: usize> = LruCache<[u8; N], SmtProof>;
+
+impl<const N: usize> ProofCache<N> {
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, evicting the oldest proofs if it shrank. Zero disables the cache.
+    pub(super) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.inner.get_mut().unwrap().1.set_capacity(capacity);
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.inner.get_mut().unwrap().1.clear();
+    }
+
+    pub(super) fn get(&self, key: &[u8; N], root: &[u8; 32]) -> Option<SmtProof> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::at_root(&mut inner, root).get(key).cloned()
+    }
+
+    pub(super) fn insert(&self, key: [u8; N], root: &[u8; 32], proof: SmtProof) {
+        let mut inner = self.inner.lock().unwrap();
+        Self::at_root(&mut inner, root).insert(key, proof);
+    }
+
+    /// The proofs for `root`, dropping any for another.
+    fn at_root<'a>(
+        (cached_root, proofs): &'a mut ([u8; 32], ProofLru<N>),
+        root: &[u8; 32],
+    ) -> &'a mut ProofLru<N> {
+        if cached_root != root {
+            proofs.clear();
+            *cached_root = *root;
+        }
+        proofs
+    }
+}
+
+impl<const N: usize> Clone for ProofCache<N> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            inner: Mutex::new(self.inner.lock().unwrap().clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sparse_merkle_tree::store::CountingStore;
+    use crate::{Smt, SmtMetrics, SparseMerkleTree};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = NodeCache::<1>::default();
+        cache.set_capacity(2);
+        cache.insert((1, [0]), [1; 32]);
+        cache.insert((1, [0x80]), [2; 32]);
+        assert_eq!(cache.get(&(1, [0])), Some(&[1; 32]));
+
+        // (1, [0x80]) is now the least recently used.
+        cache.insert((0, [0]), [3; 32]);
+        assert_eq!(cache.peek(&(1, [0x80])), None);
+        assert_eq!(cache.peek(&(1, [0])), Some(&[1; 32]));
+        assert_eq!(cache.peek(&(0, [0])), Some(&[3; 32]));
+
+        cache.set_capacity(0);
+        assert_eq!(cache.peek(&(0, [0])), None);
+        cache.insert((0, [0]), [3; 32]);
+        assert_eq!(cache.peek(&(0, [0])), None);
+    }
+
+    /// Minimal xorshift so the sequences are reproducible without a dependency.
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_cached_roots_match_uncached() {
+        for seed in 1..=8u64 {
+            let mut state = seed;
+            let mut cached = SparseMerkleTree::new();
+            cached.set_cache_capacity(64);
+            let mut reference = SparseMerkleTree::new();
+
+            for _ in 0..200 {
+                let r = next(&mut state);
+                // A small key space with a shared prefix forces overwrites, deletes and hot paths.
+                let mut key = [0xaa; 16];
+                key[15] = (r % 24) as u8;
+                key[8] = (r >> 8) as u8 % 3;
+                if r >> 16 & 3 == 0 {
+                    cached.delete(&key);
+                    reference.delete(&key);
+                } else {
+                    let value = [(r >> 24) as u8 % 4];
+                    cached.insert(&key, &value);
+                    reference.insert(&key, &value);
+                }
+                assert_eq!(cached.root(), reference.root());
+            }
+            let key = [0xaa; 16];
+            assert_eq!(cached.generate_proof(&key), reference.generate_proof(&key));
+        }
+    }
+
+    fn store_reads_for_hot_prefix(capacity: usize) -> usize {
+        let mut tree: Smt<16, _, _> = Smt::with_store(CountingStore::default());
+        tree.set_cache_capacity(capacity);
+        for round in 0..10u8 {
+            for last in 0..4u8 {
+                let mut key = [0x42; 16];
+                key[15] = last;
+                tree.insert(&key, &[round]);
+            }
+        }
+        tree.store.reads.get()
+    }
+
+    #[test]
+    fn test_cache_saves_store_reads_on_hot_prefix() {
+        let uncached = store_reads_for_hot_prefix(0);
+        let cached = store_reads_for_hot_prefix(1024);
+        assert!(cached * 10 < uncached, "{cached} vs {uncached}");
+    }
+
+    #[test]
+    fn test_proof_cache_invalidated_on_write() {
+        let mut tree = SparseMerkleTree::new();
+        tree.set_proof_cache_capacity(2);
+        let metrics = Arc::new(SmtMetrics::default());
+        tree.set_metrics(Arc::clone(&metrics));
+        for i in 0..10u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+
+        let key = [3; 16];
+        let proof = tree.generate_proof(&key);
+        let reads = metrics.store_reads();
+        assert_eq!(tree.generate_proof(&key), proof);
+        assert_eq!(metrics.store_reads(), reads);
+
+        // A write elsewhere changes a sibling on the key's path.
+        tree.insert(&[9; 16], b"changed");
+        let fresh = tree.generate_proof(&key);
+        assert!(metrics.store_reads() > reads);
+        assert_ne!(fresh, proof);
+        assert_eq!(tree.verify_proof(&key, Some(&[3]), &fresh), Ok(true));
+        assert_eq!(tree.verify_proof(&key, Some(&[3]), &proof), Ok(false));
+
+        // Back at the first root, the proof is generated again and comes out the same.
+        tree.delete(&[9; 16]);
+        tree.insert(&[9; 16], &[9]);
+        assert_eq!(tree.generate_proof(&key), proof);
+
+        // The least recently used proof goes first.
+        tree.generate_proof(&[4; 16]);
+        tree.generate_proof(&[5; 16]);
+        let reads = metrics.store_reads();
+        tree.generate_proof(&[4; 16]);
+        assert_eq!(metrics.store_reads(), reads);
+        tree.generate_proof(&key);
+        assert!(metrics.store_reads() > reads);
+    }
+
+    #[test]
+    fn test_disabled_proof_cache_is_never_touched() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[1; 16], b"one");
+        tree.set_proof_cache_capacity(4);
+        tree.set_proof_cache_capacity(0);
+        let proof = tree.generate_proof(&[1; 16]);
+        assert_eq!(tree.generate_proof(&[1; 16]), proof);
+
+        // Neither looked up nor filled: the cache never saw the current root.
+        let (root, proofs) = &*tree.proof_cache.inner.lock().unwrap();
+        assert_eq!(*root, [0; 32]);
+        assert!(proofs.entries.is_empty());
+    }
+}
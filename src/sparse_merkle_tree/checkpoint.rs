@@ -0,0 +1,132 @@
+use super::{MemoryStore, Smt, SmtError, SmtHasher};
+
+/// Names a checkpoint of the tree that returned it, see `Smt::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CheckpointId(u64);
+
+impl<const N: usize, H: SmtHasher> Smt<N, H, MemoryStore<N>> {
+    /// Records the current state for `rollback` to return to. Checkpoints nest,
+    /// each one taken on top of those still open. Like `snapshot` this is O(1),
+    /// though the first write after it copies the storage, which the checkpoint
+    /// keeps alive until it is rolled back or discarded.
+    pub fn checkpoint(&mut self) -> CheckpointId {
+        let id = CheckpointId(self.next_checkpoint);
+        self.next_checkpoint += 1;
+        self.checkpoints.push((id, self.root, self.store.clone()));
+        id
+    }
+
+    /// Restores the root, nodes and values recorded at `id`, closing it along with
+    /// every checkpoint taken after it. The undo log is cleared.
+    pub fn rollback(&mut self, id: CheckpointId) -> Result<(), SmtError> {
+        let index = self.checkpoint_index(id)?;
+        let (_, root, store) = self.checkpoints.swap_remove(index);
+        self.checkpoints.truncate(index);
+        self.root = root;
+        self.store = store;
+        self.undo_log.clear();
+        // The cache holds nodes written since the checkpoint.
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Keeps the changes made since `id` and closes it, along with every
+    /// checkpoint taken after it.
+    pub fn discard_checkpoint(&mut self, id: CheckpointId) -> Result<(), SmtError> {
+        let index = self.checkpoint_index(id)?;
+        self.checkpoints.truncate(index);
+        Ok(())
+    }
+
+    fn checkpoint_index(&self, id: CheckpointId) -> Result<usize, SmtError> {
+        self.checkpoints
+            .iter()
+            .position(|(open, _, _)| *open == id)
+            .ok_or(SmtError::UnknownCheckpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SmtError, SparseMerkleTree};
+
+    fn key(i: u8) -> [u8; 16] {
+        [i; 16]
+    }
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..4 {
+            tree.insert(&key(i), &[i]);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_rollback_restores_state() {
+        let mut tree = setup_tree();
+        tree.set_cache_capacity(64);
+        let root = tree.root();
+        let id = tree.checkpoint();
+        for i in 4..10 {
+            tree.insert(&key(i), b"new");
+        }
+        tree.insert(&key(1), b"changed");
+        tree.delete(&key(2));
+        assert_ne!(tree.root(), root);
+
+        assert_eq!(tree.rollback(id), Ok(()));
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.entries(), setup_tree().entries());
+        for i in 0..10 {
+            let expected = if i < 4 { Some(vec![i]) } else { None };
+            assert_eq!(tree.get(&key(i)), expected);
+        }
+        // Nodes are restored too, so proofs and further writes see the old tree.
+        let proof = tree.generate_proof(&key(2));
+        assert_eq!(tree.verify_proof(&key(2), Some(&[2]), &proof), Ok(true));
+        let mut reference = setup_tree();
+        assert_eq!(tree.insert(&key(7), b"x"), reference.insert(&key(7), b"x"));
+
+        assert_eq!(tree.rollback(id), Err(SmtError::UnknownCheckpoint));
+    }
+
+    #[test]
+    fn test_nested_checkpoints() {
+        let mut tree = setup_tree();
+        let outer_root = tree.root();
+        let outer = tree.checkpoint();
+        tree.insert(&key(4), b"a");
+        let inner_root = tree.root();
+        let inner = tree.checkpoint();
+        tree.insert(&key(5), b"b");
+
+        assert_eq!(tree.rollback(inner), Ok(()));
+        assert_eq!(tree.root(), inner_root);
+        let inner = tree.checkpoint();
+        tree.insert(&key(6), b"c");
+
+        // Rolling back the outer checkpoint closes the inner one.
+        assert_eq!(tree.rollback(outer), Ok(()));
+        assert_eq!(tree.root(), outer_root);
+        assert_eq!(tree.rollback(inner), Err(SmtError::UnknownCheckpoint));
+    }
+
+    #[test]
+    fn test_discard_checkpoint_keeps_changes() {
+        let mut tree = setup_tree();
+        let outer = tree.checkpoint();
+        let inner = tree.checkpoint();
+        tree.insert(&key(4), b"kept");
+        let root = tree.root();
+
+        assert_eq!(tree.discard_checkpoint(outer), Ok(()));
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.get(&key(4)), Some(b"kept".to_vec()));
+        assert_eq!(tree.rollback(inner), Err(SmtError::UnknownCheckpoint));
+        assert_eq!(
+            tree.discard_checkpoint(outer),
+            Err(SmtError::UnknownCheckpoint)
+        );
+    }
+}
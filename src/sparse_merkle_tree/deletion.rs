@@ -0,0 +1,99 @@
+use super::{NodeStore, Smt, SmtError, SmtHasher, SmtProof, SmtVerifier};
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Proof for deleting `key` from the tree as it is now, so call it before the
+    /// delete, or `None` if `key` isn't set. Deleting a key changes only the nodes
+    /// on its path, so its siblings are the same before and after and one proof
+    /// covers both roots, see `verify_deletion`.
+    pub fn generate_deletion_proof(&self, key: &[u8; N]) -> Option<SmtProof> {
+        self.contains_key(key).then(|| self.generate_proof(key))
+    }
+
+    /// Verifies that `key` holds `old_value` under the root of `before`, a verifier
+    /// for the tree before the delete, and that deleting it, and nothing else,
+    /// yields `root_after`: the same siblings must fold to the old root with the
+    /// old leaf and to `root_after` with the default leaf. Fails like
+    /// `SmtVerifier::verify` for malformed proofs or those from another kind of tree.
+    pub fn verify_deletion(
+        before: &SmtVerifier<N, H>,
+        root_after: &[u8; 32],
+        key: &[u8; N],
+        old_value: &[u8],
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        Ok(before.verify(key, Some(old_value), proof)?
+            && before.with_root(*root_after).verify(key, None, proof)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SmtFlavor, SparseMerkleTree};
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..8u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_deletion_proof() {
+        for flavor in [SmtFlavor::Plain, SmtFlavor::PathCommitting] {
+            let mut tree = SparseMerkleTree::new().with_flavor(flavor);
+            for i in 0..8u8 {
+                tree.insert(&[i; 16], &[i]);
+            }
+            let before = tree.verifier();
+            let proof = tree.generate_deletion_proof(&[3; 16]).unwrap();
+            let root_after = tree.delete(&[3; 16]);
+            assert_eq!(
+                <SparseMerkleTree>::verify_deletion(&before, &root_after, &[3; 16], &[3], &proof),
+                Ok(true)
+            );
+            // The roots swapped would be an insert, not a deletion.
+            assert_eq!(
+                <SparseMerkleTree>::verify_deletion(
+                    &tree.verifier(),
+                    &before.root(),
+                    &[3; 16],
+                    &[3],
+                    &proof
+                ),
+                Ok(false)
+            );
+        }
+        assert_eq!(setup_tree().generate_deletion_proof(&[9; 16]), None);
+    }
+
+    #[test]
+    fn test_deletion_proof_rejects_other_mutations() {
+        let tree = setup_tree();
+        let before = tree.verifier();
+        let root_before = before.root();
+        let proof = tree.generate_deletion_proof(&[3; 16]).unwrap();
+        let verify = |root_after: &[u8; 32], key: &[u8; 16], old_value: &[u8]| {
+            <SparseMerkleTree>::verify_deletion(&before, root_after, key, old_value, &proof)
+        };
+
+        let mut deleted = tree.snapshot();
+        let root_after = deleted.delete(&[3; 16]);
+        assert_eq!(verify(&root_after, &[3; 16], &[3]), Ok(true));
+        assert_eq!(verify(&root_after, &[3; 16], &[4]), Ok(false));
+        assert_eq!(verify(&root_after, &[4; 16], &[3]), Ok(false));
+
+        // Updating the key instead, deleting another key, or deleting it along
+        // with another change.
+        let mut updated = tree.snapshot();
+        assert_eq!(
+            verify(&updated.insert(&[3; 16], b"new"), &[3; 16], &[3]),
+            Ok(false)
+        );
+        let mut other = tree.snapshot();
+        assert_eq!(verify(&other.delete(&[5; 16]), &[3; 16], &[3]), Ok(false));
+        deleted.insert(&[9; 16], b"extra");
+        assert_eq!(verify(&deleted.root(), &[3; 16], &[3]), Ok(false));
+        assert_eq!(verify(&root_before, &[3; 16], &[3]), Ok(false));
+    }
+}
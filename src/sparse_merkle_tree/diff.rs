@@ -0,0 +1,152 @@
+use super::{with_bit, BitOrder, NodeStore, Smt, SmtHasher};
+
+/// A key whose value differs between two trees, see `Smt::diff`. `None` stands
+/// for an absent key, so inserts have no `before` and deletions no `after`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtDiff<const N: usize> {
+    pub key: [u8; N],
+    pub before: Option<Vec<u8>>,
+    pub after: Option<Vec<u8>>,
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Keys whose values differ from this tree (`before`) to `other` (`after`), in
+    /// ascending key order. Both trees are walked together and a subtree is skipped
+    /// as soon as its node is the same in both, so the cost grows with the number
+    /// of changed keys rather than the size of the trees: diffing a tree against
+    /// itself or a snapshot of it only compares the roots.
+    ///
+    /// Panics if the trees differ in depth, flavor or bit order, as their nodes can't
+    /// be compared.
+    pub fn diff<T: NodeStore<N>>(&self, other: &Smt<N, H, T>) -> Vec<SmtDiff<N>> {
+        assert_eq!(
+            self.depth, other.depth,
+            "cannot diff trees of different depths"
+        );
+        assert_eq!(
+            self.flavor, other.flavor,
+            "cannot diff trees of different flavors"
+        );
+        assert_eq!(
+            self.bit_order, other.bit_order,
+            "cannot diff trees of different bit orders"
+        );
+        let mut diffs = Vec::new();
+        // Left children are pushed last so they're visited first.
+        let mut stack = vec![(0, [0; N])];
+        while let Some((level, prefix)) = stack.pop() {
+            if self.node(level, prefix) == other.node(level, prefix) {
+                continue;
+            }
+            if level == self.depth {
+                let key = self.bit_order.path(&prefix, self.depth);
+                diffs.push(SmtDiff {
+                    key,
+                    before: self.get(&key),
+                    after: other.get(&key),
+                });
+            } else {
+                stack.push((level + 1, with_bit(&prefix, level, true)));
+                stack.push((level + 1, prefix));
+            }
+        }
+        if self.bit_order == BitOrder::LsbFirst {
+            diffs.sort_unstable_by_key(|diff| diff.key);
+        }
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sparse_merkle_tree::store::CountingStore;
+    use crate::{SmtFlavor, SparseMerkleTree};
+
+    fn key(i: u16) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..2].copy_from_slice(&i.wrapping_mul(0x9e37).to_be_bytes());
+        key
+    }
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..200u16 {
+            tree.insert(&key(i), &i.to_be_bytes());
+        }
+        tree
+    }
+
+    #[test]
+    fn test_diff_lists_inserts_deletes_and_changes() {
+        let before = setup_tree();
+        let mut after = before.snapshot();
+        after.insert(&key(500), b"inserted");
+        after.delete(&key(3));
+        after.insert(&key(7), b"changed");
+        // Rewriting a key with its value isn't a change.
+        after.insert(&key(9), &9u16.to_be_bytes());
+
+        let mut expected = vec![
+            SmtDiff {
+                key: key(500),
+                before: None,
+                after: Some(b"inserted".to_vec()),
+            },
+            SmtDiff {
+                key: key(3),
+                before: Some(3u16.to_be_bytes().to_vec()),
+                after: None,
+            },
+            SmtDiff {
+                key: key(7),
+                before: Some(7u16.to_be_bytes().to_vec()),
+                after: Some(b"changed".to_vec()),
+            },
+        ];
+        expected.sort_by_key(|diff| diff.key);
+        assert_eq!(before.diff(&after), expected);
+
+        // The other way round swaps `before` and `after`.
+        let reversed = after.diff(&before);
+        for (reversed, diff) in reversed.iter().zip(&expected) {
+            assert_eq!(reversed.key, diff.key);
+            assert_eq!(reversed.before, diff.after);
+            assert_eq!(reversed.after, diff.before);
+        }
+        assert_eq!(reversed.len(), 3);
+
+        assert_eq!(before.diff(&SparseMerkleTree::new()).len(), 200);
+    }
+
+    #[test]
+    fn test_diff_skips_equal_subtrees() {
+        let mut tree: SparseMerkleTree<_, CountingStore> =
+            SparseMerkleTree::with_store(CountingStore::default());
+        let entries: Vec<_> = (0..200u16)
+            .map(|i| (key(i), i.to_be_bytes().to_vec()))
+            .collect();
+        tree.insert_batch(&entries);
+        let reference = setup_tree();
+
+        tree.store.reads.set(0);
+        assert!(tree.diff(&reference).is_empty());
+        assert!(tree.diff(&tree).is_empty());
+        // Only the roots are compared.
+        assert_eq!(tree.store.reads.get(), 3);
+
+        let mut changed = setup_tree();
+        changed.insert(&key(42), b"changed");
+        tree.store.reads.set(0);
+        assert_eq!(tree.diff(&changed).len(), 1);
+        // Two children per level on the changed path, plus the root.
+        assert_eq!(tree.store.reads.get(), 1 + 2 * 128);
+    }
+
+    #[test]
+    #[should_panic(expected = "different flavors")]
+    fn test_diff_rejects_other_flavor() {
+        SparseMerkleTree::new()
+            .diff(&SparseMerkleTree::new().with_flavor(SmtFlavor::PathCommitting));
+    }
+}
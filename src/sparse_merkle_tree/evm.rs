@@ -0,0 +1,239 @@
+use super::proof::{
+    bit, check_depth, check_key, ct_eq, leaf_hash, BitOrder, SmtError, SmtHasher, SmtProof,
+    DEFAULT_LEAF,
+};
+use alloc::vec::Vec;
+
+/// A proof shaped for a Solidity verifier taking
+/// `(bytes32[] siblings, uint256 bitmask, bytes32 leaf)`, see `SmtProof::to_evm_calldata`.
+///
+/// Bit `i` of `bitmask`, counted from the least significant bit, is set when the
+/// sibling at level `i + 1` (the root's children being level 1) isn't the default
+/// node there, and only those siblings are in `siblings`, from the root down. The
+/// bitmask is big-endian, as the EVM stores a `uint256`. `leaf` is the leaf hash,
+/// all zeros for an absent key. The contract recomputes the root from the leaf up:
+///
+/// ```text
+/// node = leaf; j = siblings.length
+/// for level in depth..=1:
+///     sibling = bitmask >> (level - 1) & 1 ? siblings[--j] : zeros[depth - level]
+///     node = key bit level - 1 ? hash_pair(sibling, node) : hash_pair(node, sibling)
+/// ```
+///
+/// with `zeros` the roots of empty subtrees by height, see `Smt::zero_hashes`, and
+/// the key's bits read MSB-first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvmProof {
+    pub siblings: Vec<[u8; 32]>,
+    pub bitmask: [u8; 32],
+    pub leaf: [u8; 32],
+}
+
+impl SmtProof {
+    /// The proof for `key` holding `value` (`None` for an absent key) laid out for
+    /// the EVM, with the default siblings of `H`'s tree left out. Panics for a
+    /// depth over 256, which the bitmask can't hold, or a `BitOrder::LsbFirst`
+    /// proof, as the contract reads keys MSB-first.
+    pub fn to_evm_calldata<const N: usize, H: SmtHasher>(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+    ) -> EvmProof {
+        assert!(
+            self.depth <= 256,
+            "depth {} doesn't fit a uint256",
+            self.depth
+        );
+        assert_eq!(
+            self.bit_order,
+            BitOrder::MsbFirst,
+            "EVM proofs read keys MSB-first"
+        );
+        let zero_hashes = H::zero_hashes(self.depth);
+        let mut bitmask = [0u8; 32];
+        let mut siblings = Vec::new();
+        // `self.siblings` runs from the leaf up, so sibling `j` is at level `depth - j`.
+        for (j, sibling) in self.siblings.iter().enumerate().rev() {
+            if *sibling != zero_hashes[j] {
+                let i = self.depth - j - 1;
+                bitmask[31 - i / 8] |= 1 << (i % 8);
+                siblings.push(*sibling);
+            }
+        }
+        EvmProof {
+            siblings,
+            bitmask,
+            leaf: value.map_or(DEFAULT_LEAF, |value| {
+                leaf_hash::<N, H>(self.flavor, key, value)
+            }),
+        }
+    }
+}
+
+impl EvmProof {
+    /// The three arguments ABI-encoded as calldata, without a function selector:
+    /// the offset of the array (`0x60`), `bitmask`, `leaf`, then the array's
+    /// length and elements, each 32 bytes.
+    pub fn abi_encode(&self) -> Vec<u8> {
+        let word = |n: usize| {
+            let mut word = [0u8; 32];
+            word[24..].copy_from_slice(&(n as u64).to_be_bytes());
+            word
+        };
+        let mut calldata = Vec::with_capacity(32 * (4 + self.siblings.len()));
+        calldata.extend_from_slice(&word(0x60));
+        calldata.extend_from_slice(&self.bitmask);
+        calldata.extend_from_slice(&self.leaf);
+        calldata.extend_from_slice(&word(self.siblings.len()));
+        for sibling in &self.siblings {
+            calldata.extend_from_slice(sibling);
+        }
+        calldata
+    }
+}
+
+/// Verifies an `EvmProof` for `key` against `root` in a tree of `depth` levels
+/// hashed with `H`, from that representation alone, the way the contract in the
+/// `EvmProof` docs does. Fails with `InvalidDepth` or `KeyLength` like
+/// `SmtVerifier::verify`, `InvalidBitmask` for bits set past the depth and
+/// `InvalidProofLength` if the bitmask doesn't match the number of siblings.
+pub fn verify_evm_encoding<const N: usize, H: SmtHasher>(
+    root: &[u8; 32],
+    key: &[u8; N],
+    depth: usize,
+    proof: &EvmProof,
+) -> Result<bool, SmtError> {
+    check_depth::<N>(depth)?;
+    check_key(key, depth)?;
+    let is_set = |i: usize| proof.bitmask[31 - i / 8] & (1 << (i % 8)) != 0;
+    if (depth..256).any(is_set) {
+        return Err(SmtError::InvalidBitmask);
+    }
+    let present = (0..depth).filter(|&i| is_set(i)).count();
+    if proof.siblings.len() != present {
+        return Err(SmtError::InvalidProofLength {
+            expected: present,
+            actual: proof.siblings.len(),
+        });
+    }
+
+    let zero_hashes = H::zero_hashes(depth);
+    let mut siblings = proof.siblings.iter().rev();
+    let mut node = proof.leaf;
+    for level in (1..=depth).rev() {
+        let sibling = if is_set(level - 1) {
+            *siblings.next().unwrap()
+        } else {
+            zero_hashes[depth - level]
+        };
+        node = if bit(key, level - 1) {
+            H::hash_pair(&sibling, &node)
+        } else {
+            H::hash_pair(&node, &sibling)
+        };
+    }
+    Ok(ct_eq(&node, root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sha256Hasher;
+    use alloc::{format, string::String};
+
+    /// Root and `SmtProof::to_hex` proof for 0101 = "a" in the depth-4 tree holding
+    /// 0101 = "a", 0110 = "c" and 1100 = "b", as in the `proof` tests.
+    const ROOT: &str = "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d";
+    const PROOF: &str = concat!(
+        "0x000400",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a",
+        "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+        "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+    );
+    /// Its calldata: levels 1 and 3 have non-default siblings, so the bitmask is 0b101.
+    const CALLDATA: &str = concat!(
+        "0000000000000000000000000000000000000000000000000000000000000060",
+        "0000000000000000000000000000000000000000000000000000000000000005",
+        "022a6979e6dab7aa5ae4c3e5e45f7e977112a7e63593820dbec1ec738a24f93c",
+        "0000000000000000000000000000000000000000000000000000000000000002",
+        "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+        "df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a",
+    );
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn root() -> [u8; 32] {
+        core::array::from_fn(|i| u8::from_str_radix(&ROOT[2 * i..2 * i + 2], 16).unwrap())
+    }
+
+    fn key(path: u8) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[0] = path;
+        key
+    }
+
+    #[test]
+    fn test_evm_calldata_fixture() {
+        let proof = SmtProof::from_hex(PROOF).unwrap();
+        let evm = proof.to_evm_calldata::<16, Sha256Hasher>(&key(0x50), Some(b"a"));
+        assert_eq!(hex(&evm.abi_encode()), CALLDATA);
+        let verify =
+            |evm: &EvmProof| verify_evm_encoding::<16, Sha256Hasher>(&root(), &key(0x50), 4, evm);
+        assert_eq!(verify(&evm), Ok(true));
+
+        let mut wrong_leaf = evm.clone();
+        wrong_leaf.leaf[0] ^= 1;
+        assert_eq!(verify(&wrong_leaf), Ok(false));
+        let mut swapped = evm.clone();
+        swapped.siblings.reverse();
+        assert_eq!(verify(&swapped), Ok(false));
+        let mut moved = evm.clone();
+        moved.bitmask[31] = 0b110;
+        assert_eq!(verify(&moved), Ok(false));
+        let mut past_depth = evm.clone();
+        past_depth.bitmask[31] |= 0b1_0000;
+        assert_eq!(verify(&past_depth), Err(SmtError::InvalidBitmask));
+        let mut missing = evm.clone();
+        missing.siblings.pop();
+        assert_eq!(
+            verify(&missing),
+            Err(SmtError::InvalidProofLength {
+                expected: 2,
+                actual: 1
+            })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_evm_encoding_of_tree_proofs() {
+        use crate::SparseMerkleTree256;
+
+        let mut tree = SparseMerkleTree256::new();
+        for i in 0..16u8 {
+            tree.insert(&[i.wrapping_mul(13); 32], &[i]);
+        }
+        for i in 0..20u8 {
+            let key = [i.wrapping_mul(13); 32];
+            let value = tree.get(&key);
+            let evm = tree
+                .generate_proof(&key)
+                .to_evm_calldata::<32, Sha256Hasher>(&key, value.as_deref());
+            assert_eq!(evm.abi_encode().len(), 32 * (4 + evm.siblings.len()));
+            assert_eq!(
+                verify_evm_encoding::<32, Sha256Hasher>(&tree.root(), &key, 256, &evm),
+                Ok(true)
+            );
+            if value.is_some() {
+                let other = [0xff; 32];
+                assert_eq!(
+                    verify_evm_encoding::<32, Sha256Hasher>(&tree.root(), &other, 256, &evm),
+                    Ok(false)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use super::store::hex;
+use super::{truncate, with_bit, NodeStore, Smt, SmtHasher, DEFAULT_LEAF};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// First inconsistency `Smt::verify_integrity` found in a tree's store. Nodes
+/// missing from the store count as default nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityError<const N: usize> {
+    /// The node at `level` and `path` isn't the hash of its children, or for a
+    /// leaf, of the value stored for its key.
+    NodeMismatch {
+        level: usize,
+        path: [u8; N],
+        stored: [u8; 32],
+        computed: [u8; 32],
+    },
+    /// A stored node that no path from the root reaches: its parent isn't stored,
+    /// its path has bits set past its level, or it's a default node, which the
+    /// tree never stores.
+    OrphanedNode { level: usize, path: [u8; N] },
+    /// A stored value whose key has bits set past the tree depth.
+    OrphanedValue { key: [u8; N] },
+    /// The stored nodes are consistent but their root isn't the tree's.
+    RootMismatch { stored: [u8; 32], root: [u8; 32] },
+}
+
+impl<const N: usize> fmt::Display for IntegrityError<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NodeMismatch {
+                level,
+                path,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "node {} at level {level} is {}, expected {}",
+                hex(path),
+                hex(stored),
+                hex(computed)
+            ),
+            Self::OrphanedNode { level, path } => {
+                write!(f, "node {} at level {level} is orphaned", hex(path))
+            }
+            Self::OrphanedValue { key } => {
+                write!(f, "value for key {} is past the tree depth", hex(key))
+            }
+            Self::RootMismatch { stored, root } => {
+                write!(f, "stored root is {}, expected {}", hex(stored), hex(root))
+            }
+        }
+    }
+}
+
+impl<const N: usize> std::error::Error for IntegrityError<N> {}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Audits the store: every stored node must be the hash of its children (leaves,
+    /// of their value), be reachable from the root, and the stored root must be
+    /// `root()`. Nodes are checked from the leaves up, so a corrupted node is
+    /// reported itself rather than through its ancestors. Reads the whole store and
+    /// rehashes every stored node, bypassing the node cache.
+    pub fn verify_integrity(&self) -> Result<(), IntegrityError<N>> {
+        let nodes: HashMap<_, _> = self
+            .store
+            .all_nodes()
+            .into_iter()
+            .map(|(level, path, hash)| ((level, path), hash))
+            .collect();
+        let values: HashMap<_, _> = self.store.all_values().into_iter().collect();
+        let stored = |level: usize, path: [u8; N]| {
+            nodes
+                .get(&(level, path))
+                .copied()
+                .unwrap_or(self.default_nodes[level])
+        };
+
+        let mut keys: Vec<_> = values.keys().copied().collect();
+        keys.sort_unstable();
+        if let Some(&key) = keys.iter().find(|key| truncate(key, self.depth) != **key) {
+            return Err(IntegrityError::OrphanedValue { key });
+        }
+        let values: HashMap<_, _> = values
+            .into_iter()
+            .map(|(key, value)| (self.bit_order.path(&key, self.depth), value))
+            .collect();
+
+        // Every stored node and every leaf that should be, deepest first.
+        let mut positions: Vec<_> = nodes
+            .keys()
+            .copied()
+            .chain(values.keys().map(|&path| (self.depth, path)))
+            .collect();
+        positions.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        positions.dedup();
+        for (level, path) in positions {
+            let orphaned = IntegrityError::OrphanedNode { level, path };
+            if level > self.depth || truncate(&path, level) != path {
+                return Err(orphaned);
+            }
+            let node = stored(level, path);
+            if nodes.contains_key(&(level, path)) && node == self.default_nodes[level] {
+                return Err(orphaned);
+            }
+            let computed = if level == self.depth && !self.store_values {
+                // Nothing to rehash a leaf from.
+                node
+            } else if level == self.depth {
+                values
+                    .get(&path)
+                    .map_or(DEFAULT_LEAF, |value| self.hash_value(&path, value))
+            } else {
+                self.hash_children(
+                    level,
+                    &stored(level + 1, path),
+                    &stored(level + 1, with_bit(&path, level, true)),
+                )
+            };
+            if node != computed {
+                return Err(IntegrityError::NodeMismatch {
+                    level,
+                    path,
+                    stored: node,
+                    computed,
+                });
+            }
+            if level > 0 && !nodes.contains_key(&(level - 1, truncate(&path, level - 1))) {
+                return Err(orphaned);
+            }
+        }
+
+        let stored_root = stored(0, [0; N]);
+        if stored_root != self.root {
+            return Err(IntegrityError::RootMismatch {
+                stored: stored_root,
+                root: self.root,
+            });
+        }
+        Ok(())
+    }
+
+    /// Deletes every stored node that a walk down from the root never reaches,
+    /// i.e. the `OrphanedNode`s of `verify_integrity`, and returns how many were
+    /// dropped. Such nodes are left behind by interrupted writes or by stores
+    /// shared with other trees; besides taking space, one under an empty subtree
+    /// would be picked up as a sibling once a key is inserted next to it.
+    pub fn prune(&mut self) -> usize {
+        let nodes: HashMap<_, _> = self
+            .store
+            .all_nodes()
+            .into_iter()
+            .map(|(level, path, hash)| ((level, path), hash))
+            .collect();
+        let mut reachable = HashSet::new();
+        let mut frontier = vec![(0, [0; N])];
+        while let Some((level, path)) = frontier.pop() {
+            match nodes.get(&(level, path)) {
+                Some(node) if *node != self.default_nodes[level] => {
+                    reachable.insert((level, path));
+                    if level < self.depth {
+                        frontier.push((level + 1, path));
+                        frontier.push((level + 1, with_bit(&path, level, true)));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut pruned = 0;
+        for position in nodes.keys() {
+            if !reachable.contains(position) {
+                self.store.delete(position.0, &position.1);
+                pruned += 1;
+            }
+        }
+        if pruned > 0 {
+            self.cache.clear();
+        }
+        pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SmtFlavor, SparseMerkleTree};
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::with_depth(16).with_flavor(SmtFlavor::PathCommitting);
+        for i in 0..50u16 {
+            let mut key = [0u8; 16];
+            key[..2].copy_from_slice(&i.wrapping_mul(0x9e37).to_be_bytes());
+            tree.insert(&key, &i.to_be_bytes());
+        }
+        tree
+    }
+
+    #[test]
+    fn test_integrity_of_consistent_tree() {
+        let mut tree = setup_tree();
+        assert_eq!(tree.verify_integrity(), Ok(()));
+        let mut key = [0u8; 16];
+        key[..2].copy_from_slice(&7u16.wrapping_mul(0x9e37).to_be_bytes());
+        tree.delete(&key);
+        assert_eq!(tree.verify_integrity(), Ok(()));
+        assert_eq!(SparseMerkleTree::new().verify_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn test_integrity_pinpoints_flipped_byte() {
+        let tree = setup_tree();
+        let mut key = [0u8; 16];
+        key[..2].copy_from_slice(&3u16.wrapping_mul(0x9e37).to_be_bytes());
+        for level in [16, 9, 1] {
+            let path = truncate(&key, level);
+            let mut corrupted = tree.snapshot();
+            let mut node = tree.node(level, path);
+            node[5] ^= 0x10;
+            corrupted.store.put(level, path, node);
+
+            assert_eq!(
+                corrupted.verify_integrity(),
+                Err(IntegrityError::NodeMismatch {
+                    level,
+                    path,
+                    stored: node,
+                    computed: tree.node(level, path),
+                })
+            );
+        }
+
+        // A flipped value shows up at its leaf.
+        let mut corrupted = tree.snapshot();
+        corrupted.store.put_value(key, vec![0xff, 0xff]);
+        assert!(matches!(
+            corrupted.verify_integrity(),
+            Err(IntegrityError::NodeMismatch { level: 16, path, .. }) if path == key
+        ));
+
+        // So does a corrupted root, once everything below it checks out.
+        let mut corrupted = tree.snapshot();
+        corrupted.root[0] ^= 1;
+        assert_eq!(
+            corrupted.verify_integrity(),
+            Err(IntegrityError::RootMismatch {
+                stored: tree.root,
+                root: corrupted.root,
+            })
+        );
+    }
+
+    #[test]
+    fn test_integrity_detects_orphans() {
+        let tree = setup_tree();
+
+        // A stray node under an empty subtree.
+        let mut stray = [0xff; 16];
+        stray[2..].fill(0);
+        stray[0] = (0..=255u8)
+            .find(|&byte| tree.node(8, truncate(&[byte; 16], 8)) == tree.default_nodes[8])
+            .unwrap();
+        // A consistent subtree cut off from the root, as left by a partial write.
+        let mut orphaned = tree.snapshot();
+        orphaned.insert(&stray, b"stray");
+        orphaned.store.delete(11, &truncate(&stray, 11));
+        assert_eq!(
+            orphaned.verify_integrity(),
+            Err(IntegrityError::OrphanedNode {
+                level: 12,
+                path: truncate(&stray, 12),
+            })
+        );
+
+        let mut stored_default = tree.snapshot();
+        stored_default
+            .store
+            .put(8, truncate(&stray, 8), tree.default_nodes[8]);
+        assert_eq!(
+            stored_default.verify_integrity(),
+            Err(IntegrityError::OrphanedNode {
+                level: 8,
+                path: truncate(&stray, 8),
+            })
+        );
+
+        let mut past_level = tree.snapshot();
+        past_level.store.put(4, stray, [7; 32]);
+        assert_eq!(
+            past_level.verify_integrity(),
+            Err(IntegrityError::OrphanedNode {
+                level: 4,
+                path: stray,
+            })
+        );
+
+        let mut past_depth = tree.snapshot();
+        let mut long_key = stray;
+        long_key[2] = 1;
+        past_depth.store.put_value(long_key, b"value".to_vec());
+        assert_eq!(
+            past_depth.verify_integrity(),
+            Err(IntegrityError::OrphanedValue { key: long_key })
+        );
+        assert_eq!(
+            past_depth.verify_integrity().unwrap_err().to_string(),
+            format!(
+                "value for key {:02x}ff0100000000000000000000000000 is past the tree depth",
+                stray[0]
+            )
+        );
+    }
+
+    #[test]
+    fn test_prune_drops_orphans() {
+        let mut tree = setup_tree();
+        let stored = tree.store.nodes.len();
+        assert_eq!(tree.prune(), 0);
+
+        // Leave a stale subtree under an empty one, as an interrupted delete would.
+        let mut stray = [0u8; 16];
+        stray[..2].copy_from_slice(&[0xff, 0xff]);
+        let mut stale = tree.snapshot();
+        stale.insert(&stray, b"stray");
+        for (&(level, path), &node) in stale.store.nodes.iter() {
+            if level >= 12 && truncate(&stray, level) == path {
+                tree.store.put(level, path, node);
+            }
+        }
+        tree.store.put(5, [0xaa; 16], tree.default_nodes[5]);
+        assert!(tree.verify_integrity().is_err());
+        assert_eq!(tree.prune(), 6);
+        assert_eq!(tree.store.nodes.len(), stored);
+        assert_eq!(tree.verify_integrity(), Ok(()));
+
+        // A key inserted next to the stale leaf no longer picks it up.
+        let mut neighbour = stray;
+        neighbour[1] = 0xfe;
+        let mut expected = setup_tree();
+        expected.insert(&neighbour, b"value");
+        tree.insert(&neighbour, b"value");
+        assert_eq!(tree.root, expected.root);
+        let proof = tree.generate_proof(&neighbour);
+        assert_eq!(
+            tree.verify_proof(&neighbour, Some(b"value"), &proof),
+            Ok(true)
+        );
+    }
+}
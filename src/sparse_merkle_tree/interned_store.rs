@@ -0,0 +1,152 @@
+use super::{NodePosition, NodeStore};
+use std::collections::HashMap;
+
+/// A store that keeps each distinct node hash once and refers to it by a 4-byte
+/// id, for trees with many identical subtrees, e.g. lots of keys holding the same
+/// value, where `MemoryStore` holds the same hash over and over. Every write
+/// looks the hash up and updates its reference count, so writes cost more than
+/// with `MemoryStore`; use it only when `Smt::stats` shows far fewer distinct
+/// hashes than nodes. Roots and proofs are the same with either store. Cloning
+/// copies everything.
+#[derive(Debug, Clone, Default)]
+pub struct InternedStore<const N: usize> {
+    pub(super) nodes: HashMap<NodePosition<N>, u32>,
+    /// Hash and number of nodes referring to it, by id.
+    pub(super) hashes: Vec<([u8; 32], u32)>,
+    pub(super) ids: HashMap<[u8; 32], u32>,
+    /// Ids no node refers to anymore, reused first.
+    pub(super) free: Vec<u32>,
+    pub(super) values: HashMap<[u8; N], Vec<u8>>,
+}
+
+impl<const N: usize> InternedStore<N> {
+    /// Id of `hash`, taking a reference to it.
+    fn intern(&mut self, hash: [u8; 32]) -> u32 {
+        if let Some(&id) = self.ids.get(&hash) {
+            self.hashes[id as usize].1 += 1;
+            return id;
+        }
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.hashes[id as usize] = (hash, 1);
+                id
+            }
+            None => {
+                let id = u32::try_from(self.hashes.len()).expect("too many distinct hashes");
+                self.hashes.push((hash, 1));
+                id
+            }
+        };
+        self.ids.insert(hash, id);
+        id
+    }
+
+    /// Drops a reference to `id`, freeing it with the last one.
+    fn release(&mut self, id: u32) {
+        let (hash, count) = &mut self.hashes[id as usize];
+        *count -= 1;
+        if *count == 0 {
+            self.ids.remove(hash);
+            self.free.push(id);
+        }
+    }
+}
+
+impl<const N: usize> NodeStore<N> for InternedStore<N> {
+    fn get(&self, level: usize, path: &[u8; N]) -> Option<[u8; 32]> {
+        let id = self.nodes.get(&(level, *path))?;
+        Some(self.hashes[*id as usize].0)
+    }
+
+    fn put(&mut self, level: usize, path: [u8; N], hash: [u8; 32]) {
+        let id = self.intern(hash);
+        if let Some(previous) = self.nodes.insert((level, path), id) {
+            self.release(previous);
+        }
+    }
+
+    fn delete(&mut self, level: usize, path: &[u8; N]) {
+        if let Some(id) = self.nodes.remove(&(level, *path)) {
+            self.release(id);
+        }
+    }
+
+    fn get_value(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.values.get(key).cloned()
+    }
+
+    fn put_value(&mut self, key: [u8; N], value: Vec<u8>) -> Option<Vec<u8>> {
+        self.values.insert(key, value)
+    }
+
+    fn delete_value(&mut self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.values.remove(key)
+    }
+
+    fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    fn all_nodes(&self) -> Vec<(usize, [u8; N], [u8; 32])> {
+        self.nodes
+            .iter()
+            .map(|(&(level, path), &id)| (level, path, self.hashes[id as usize].0))
+            .collect()
+    }
+
+    fn all_values(&self) -> Vec<([u8; N], Vec<u8>)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Sha256Hasher, SparseMerkleTree};
+
+    type InternedTree = SparseMerkleTree<Sha256Hasher, InternedStore<16>>;
+
+    #[test]
+    fn test_interned_store_matches_memory_store() {
+        let mut memory = SparseMerkleTree::new();
+        let mut interned = InternedTree::with_store(InternedStore::default());
+        for i in 0..100u8 {
+            memory.insert(&[i; 16], &[i % 3]);
+            interned.insert(&[i; 16], &[i % 3]);
+        }
+        for i in (0..100u8).step_by(4) {
+            memory.delete(&[i; 16]);
+            interned.delete(&[i; 16]);
+            memory.insert(&[i.wrapping_add(1); 16], b"moved");
+            interned.insert(&[i.wrapping_add(1); 16], b"moved");
+        }
+        assert!(interned.root_eq(&memory));
+        assert_eq!(interned.entries(), memory.entries());
+        assert_eq!(
+            interned.generate_proof(&[5; 16]),
+            memory.generate_proof(&[5; 16])
+        );
+        assert_eq!(interned.verify_integrity(), Ok(()));
+
+        // Every id still in use is referenced exactly as often as its count says.
+        let mut counts = vec![0; interned.store.hashes.len()];
+        for id in interned.store.nodes.values() {
+            counts[*id as usize] += 1;
+        }
+        for (id, (hash, count)) in interned.store.hashes.iter().enumerate() {
+            if interned.store.free.contains(&(id as u32)) {
+                assert_eq!(counts[id], 0);
+            } else {
+                assert_eq!((counts[id], interned.store.ids[hash]), (*count, id as u32));
+            }
+        }
+
+        for i in 0..=100u8 {
+            interned.delete(&[i; 16]);
+        }
+        assert!(interned.store.nodes.is_empty() && interned.store.ids.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use super::{NodeStore, Smt, SmtHasher};
+use std::fmt;
+
+/// Which value `Smt::merge` keeps when both trees hold the same key with different values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    PreferSelf,
+    PreferOther,
+    Error,
+}
+
+/// Returned by `Smt::merge` under `ConflictPolicy::Error`, naming the first
+/// conflicting key in key order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict<const N: usize> {
+    pub key: [u8; N],
+}
+
+impl<const N: usize> fmt::Display for MergeConflict<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key ")?;
+        for byte in &self.key {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, " has different values in both trees")
+    }
+}
+
+impl<const N: usize> std::error::Error for MergeConflict<N> {}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Copies every entry of `other` into this tree. The resulting root is the one a
+    /// fresh tree would get from the union of both trees' entries under `on_conflict`.
+    /// On a conflict under `ConflictPolicy::Error` the tree is left unchanged.
+    pub fn merge(
+        &mut self,
+        other: &Smt<N, H>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<(), MergeConflict<N>> {
+        assert_eq!(
+            self.depth, other.depth,
+            "cannot merge trees of different depths"
+        );
+        assert_eq!(
+            self.flavor, other.flavor,
+            "cannot merge trees of different flavors"
+        );
+        let mut entries = Vec::new();
+        for (key, value) in other.iter() {
+            match self.get(key) {
+                Some(existing) if existing == value => {}
+                Some(_) if on_conflict == ConflictPolicy::PreferSelf => {}
+                Some(_) if on_conflict == ConflictPolicy::Error => {
+                    return Err(MergeConflict { key: *key });
+                }
+                _ => entries.push((*key, value.to_vec())),
+            }
+        }
+        if !entries.is_empty() {
+            self.insert_batch(&entries);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    fn tree(entries: &[(u8, &[u8])]) -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for &(key, value) in entries {
+            tree.insert(&[key; 16], value);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_merge_overlapping_and_disjoint_keys() {
+        let left = tree(&[(1, b"a"), (2, b"left"), (3, b"same")]);
+        let right = tree(&[(2, b"right"), (3, b"same"), (4, b"d")]);
+
+        let mut merged = left.clone();
+        merged.merge(&right, ConflictPolicy::PreferSelf).unwrap();
+        let expected = tree(&[(1, b"a"), (2, b"left"), (3, b"same"), (4, b"d")]);
+        assert_eq!(merged.root(), expected.root());
+
+        let mut merged = left.clone();
+        merged.merge(&right, ConflictPolicy::PreferOther).unwrap();
+        let expected = tree(&[(1, b"a"), (2, b"right"), (3, b"same"), (4, b"d")]);
+        assert_eq!(merged.root(), expected.root());
+
+        for (key, value) in [(1u8, &b"a"[..]), (2, b"right"), (4, b"d")] {
+            let proof = merged.generate_proof(&[key; 16]);
+            assert_eq!(
+                merged.verify_proof(&[key; 16], Some(value), &proof),
+                Ok(true)
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_conflict_leaves_tree_unchanged() {
+        let mut left = tree(&[(1, b"a"), (2, b"left")]);
+        let right = tree(&[(2, b"right"), (5, b"e")]);
+        let root = left.root();
+
+        assert_eq!(
+            left.merge(&right, ConflictPolicy::Error),
+            Err(MergeConflict { key: [2; 16] })
+        );
+        assert_eq!(left.root(), root);
+        assert_eq!(left.get(&[5u8; 16]), None);
+
+        let disjoint = tree(&[(7, b"g")]);
+        assert_eq!(left.merge(&disjoint, ConflictPolicy::Error), Ok(()));
+        assert_eq!(
+            left.root(),
+            tree(&[(1, b"a"), (2, b"left"), (7, b"g")]).root()
+        );
+    }
+}
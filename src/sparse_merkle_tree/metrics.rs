@@ -0,0 +1,183 @@
+use super::{NodeStore, Smt, SmtHasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counts of the work trees do, for profiling. Install one with `Smt::set_metrics`;
+/// several trees can share it. Only writes and reads through a tree are counted,
+/// not the stateless verifiers.
+#[derive(Debug, Default)]
+pub struct SmtMetrics {
+    leaf_hashes: AtomicU64,
+    pair_hashes: AtomicU64,
+    store_reads: AtomicU64,
+    store_writes: AtomicU64,
+}
+
+impl SmtMetrics {
+    /// Values hashed into leaves.
+    pub fn leaf_hashes(&self) -> u64 {
+        self.leaf_hashes.load(Ordering::Relaxed)
+    }
+
+    /// Pairs of children hashed into their parent. Pairs of default children aren't
+    /// hashed, their parent being the default node, so they aren't counted.
+    pub fn pair_hashes(&self) -> u64 {
+        self.pair_hashes.load(Ordering::Relaxed)
+    }
+
+    /// Nodes and values read from the store. Nodes served by the cache aren't counted.
+    pub fn store_reads(&self) -> u64 {
+        self.store_reads.load(Ordering::Relaxed)
+    }
+
+    /// Nodes and values written to or deleted from the store.
+    pub fn store_writes(&self) -> u64 {
+        self.store_writes.load(Ordering::Relaxed)
+    }
+
+    /// Sets every counter back to zero.
+    pub fn reset(&self) {
+        for counter in [
+            &self.leaf_hashes,
+            &self.pair_hashes,
+            &self.store_reads,
+            &self.store_writes,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<const N: usize, H, S> Smt<N, H, S> {
+    /// Counts this tree's hashing and store accesses in `metrics` from now on.
+    /// Without metrics installed, the default, counting costs a check for `None`.
+    pub fn set_metrics(&mut self, metrics: Arc<SmtMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<SmtMetrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Stops counting.
+    pub fn clear_metrics(&mut self) {
+        self.metrics = None;
+    }
+
+    pub(super) fn count(&self, counter: fn(&SmtMetrics) -> &AtomicU64) {
+        if let Some(metrics) = &self.metrics {
+            counter(metrics).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// `leaf_hash` with this tree's flavor, counted.
+    pub(super) fn hash_value(&self, path: &[u8; N], value: &[u8]) -> [u8; 32] {
+        self.count(|metrics| &metrics.leaf_hashes);
+        Self::leaf_hash(self.flavor, path, value)
+    }
+
+    /// The node at `level` over `left` and `right`, counted. Two default children
+    /// give the level's precomputed default node without hashing, which saves most
+    /// of the work of emptying a sparse region.
+    pub(super) fn hash_children(
+        &self,
+        level: usize,
+        left: &[u8; 32],
+        right: &[u8; 32],
+    ) -> [u8; 32] {
+        let default = &self.default_nodes[level + 1];
+        if left == default && right == default {
+            return self.default_nodes[level];
+        }
+        self.count(|metrics| &metrics.pair_hashes);
+        Self::hash_pair(left, right)
+    }
+
+    pub(super) fn count_read(&self) {
+        self.count(|metrics| &metrics.store_reads);
+    }
+
+    pub(super) fn count_write(&self) {
+        self.count(|metrics| &metrics.store_writes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    #[test]
+    fn test_metrics_count_single_insert() {
+        let mut tree = SparseMerkleTree::new();
+        let metrics = Arc::new(SmtMetrics::default());
+        tree.set_metrics(Arc::clone(&metrics));
+
+        tree.insert(&[1; 16], b"value");
+        assert_eq!(metrics.leaf_hashes(), 1);
+        assert_eq!(metrics.pair_hashes(), 128);
+        // The old leaf and its 128 siblings, then the path and the value.
+        assert_eq!(metrics.store_reads(), 129);
+        assert_eq!(metrics.store_writes(), 130);
+
+        metrics.reset();
+        assert_eq!(tree.get(&[1; 16]), Some(b"value".to_vec()));
+        tree.insert(&[1; 16], b"value");
+        assert_eq!(metrics.leaf_hashes(), 1);
+        assert_eq!(metrics.pair_hashes(), 0);
+        assert_eq!(metrics.store_reads(), 2);
+
+        tree.clear_metrics();
+        tree.insert(&[2; 16], b"other");
+        assert_eq!(metrics.leaf_hashes(), 1);
+        assert!(tree.metrics().is_none());
+    }
+
+    #[test]
+    fn test_metrics_count_batches() {
+        let entries: Vec<_> = (0..4u8).map(|i| ([i << 6; 16], vec![i])).collect();
+        let mut tree = SparseMerkleTree::new();
+        let metrics = Arc::new(SmtMetrics::default());
+        tree.set_metrics(Arc::clone(&metrics));
+        tree.insert_batch(&entries);
+        // The four keys split in the top two levels: 3 pairs there and four
+        // paths of 126 below.
+        assert_eq!(metrics.leaf_hashes(), 4);
+        assert_eq!(metrics.pair_hashes(), 3 + 4 * 126);
+
+        metrics.reset();
+        let mut bulk = SparseMerkleTree::from_sorted_entries(entries).unwrap();
+        assert_eq!(bulk.metrics().map(|m| m.pair_hashes()), None);
+        bulk.set_metrics(Arc::clone(&metrics));
+        bulk.verify_integrity().unwrap();
+        assert_eq!(metrics.leaf_hashes(), 4);
+    }
+
+    #[test]
+    fn test_metrics_skip_default_pairs() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[0; 16], b"stays");
+        tree.insert(&[0x80; 16], b"goes");
+        let metrics = Arc::new(SmtMetrics::default());
+        tree.set_metrics(Arc::clone(&metrics));
+        // Only the root has a non-default child left, out of 128 levels.
+        tree.delete(&[0x80; 16]);
+        assert_eq!(metrics.pair_hashes(), 1);
+        let mut expected = SparseMerkleTree::new();
+        expected.insert(&[0; 16], b"stays");
+        assert_eq!(tree.root(), expected.root());
+
+        let entries: Vec<_> = (0..16u8).map(|i| ([i << 4; 16], vec![i])).collect();
+        let mut tree = SparseMerkleTree::new().with_default_value(b"");
+        tree.insert_batch(&entries);
+        metrics.reset();
+        tree.set_metrics(Arc::clone(&metrics));
+        let cleared: Vec<_> = entries.iter().map(|(key, _)| (*key, Vec::new())).collect();
+        tree.insert_batch(&cleared);
+        assert_eq!(metrics.pair_hashes(), 0);
+        assert_eq!(tree.root(), SparseMerkleTree::new().root());
+        assert_eq!(tree.verify_integrity(), Ok(()));
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    bit, ct_eq, truncate, BitOrder, NodeStore, Smt, SmtError, SmtFlavor, SmtHasher, SmtVerifier,
+    DEFAULT_LEAF,
+};
+
+/// Siblings needed to verify several keys at once. Nodes that can be computed
+/// from the proven keys themselves are left out, so keys sharing a path share
+/// its siblings. Siblings are ordered level by level from the leaves up, and by
+/// position within a level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtMultiProof {
+    pub depth: usize,
+    pub flavor: SmtFlavor,
+    pub bit_order: BitOrder,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Multiproof for `keys`. Duplicate keys are proven once.
+    pub fn generate_multiproof(&self, keys: &[[u8; N]]) -> SmtMultiProof {
+        let mut positions: Vec<[u8; N]> = keys.iter().map(|key| self.path(key)).collect();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let mut siblings = Vec::new();
+        for level in (1..=self.depth).rev() {
+            let mut i = 0;
+            while i < positions.len() {
+                let sibling = Self::sibling(&positions[i], level);
+                if positions.get(i + 1) == Some(&sibling) {
+                    i += 2;
+                } else {
+                    siblings.push(self.node(level, sibling));
+                    i += 1;
+                }
+            }
+            for position in positions.iter_mut() {
+                *position = truncate(position, level - 1);
+            }
+            positions.dedup();
+        }
+        SmtMultiProof {
+            depth: self.depth,
+            flavor: self.flavor,
+            bit_order: self.bit_order,
+            siblings,
+        }
+    }
+
+    /// Verifies that every `(key, value)` pair is committed to by the root of
+    /// `verifier`, with `None` values proving absence. Returns `false` if any pair
+    /// is wrong, if `entries` is empty, or if the same key is listed with two
+    /// different values. Fails like `SmtVerifier::verify` for a proof from another
+    /// kind of tree, an out-of-range depth or keys past it, and with
+    /// `InvalidProofLength` if the proof doesn't hold exactly the siblings these
+    /// keys need.
+    pub fn verify_multiproof(
+        verifier: &SmtVerifier<N, H>,
+        entries: &[([u8; N], Option<&[u8]>)],
+        proof: &SmtMultiProof,
+    ) -> Result<bool, SmtError> {
+        verifier.check_shape(proof.depth, proof.flavor, proof.bit_order)?;
+        let (depth, flavor) = (verifier.depth(), verifier.flavor());
+        Self::check_depth(depth)?;
+        for (key, _) in entries {
+            Self::check_key(key, depth)?;
+        }
+        if entries.is_empty() {
+            return Ok(false);
+        }
+
+        let mut entries: Vec<_> = entries
+            .iter()
+            .map(|&(key, value)| (verifier.bit_order().path(&key, depth), value))
+            .collect();
+        entries.sort_unstable_by_key(|(path, _)| *path);
+        if entries
+            .windows(2)
+            .any(|pair| pair[0].0 == pair[1].0 && pair[0].1 != pair[1].1)
+        {
+            return Ok(false);
+        }
+        entries.dedup();
+
+        let mut nodes: Vec<([u8; N], [u8; 32])> = entries
+            .iter()
+            .map(|(path, value)| {
+                let leaf = value.map_or(DEFAULT_LEAF, |value| Self::leaf_hash(flavor, path, value));
+                (*path, leaf)
+            })
+            .collect();
+        // Siblings used so far. Missing ones are stood in for by zeros to count how
+        // many the proof should have held.
+        let mut used = 0;
+
+        for level in (1..=depth).rev() {
+            let mut parents = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let (position, node) = &nodes[i];
+                let sibling_position = Self::sibling(position, level);
+                let parent = if nodes.get(i + 1).map(|(p, _)| p) == Some(&sibling_position) {
+                    i += 2;
+                    Self::hash_pair(node, &nodes[i - 1].1)
+                } else {
+                    let sibling = proof.siblings.get(used).copied().unwrap_or_default();
+                    used += 1;
+                    i += 1;
+                    if bit(position, level - 1) {
+                        Self::hash_pair(&sibling, node)
+                    } else {
+                        Self::hash_pair(node, &sibling)
+                    }
+                };
+                parents.push((truncate(position, level - 1), parent));
+            }
+            nodes = parents;
+        }
+        if used != proof.siblings.len() {
+            return Err(SmtError::InvalidProofLength {
+                expected: used,
+                actual: proof.siblings.len(),
+            });
+        }
+        Ok(ct_eq(&nodes[0].1, &verifier.root()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SmtError, SparseMerkleTree};
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..8u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_multiproof_verifies_all_entries() {
+        let tree = setup_tree();
+        let keys = [[1u8; 16], [5u8; 16], [9u8; 16]];
+        let proof = tree.generate_multiproof(&keys);
+
+        let entries: [([u8; 16], Option<&[u8]>); 3] = [
+            ([1u8; 16], Some(&[1])),
+            ([5u8; 16], Some(&[5])),
+            ([9u8; 16], None),
+        ];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &entries, &proof),
+            Ok(true)
+        );
+
+        // Order of the claimed entries doesn't matter.
+        let reversed: Vec<_> = entries.iter().rev().copied().collect();
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &reversed, &proof),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_multiproof_fails_if_any_entry_is_wrong() {
+        let tree = setup_tree();
+        let keys = [[1u8; 16], [5u8; 16]];
+        let proof = tree.generate_multiproof(&keys);
+
+        let wrong_value: [([u8; 16], Option<&[u8]>); 2] =
+            [([1u8; 16], Some(&[1])), ([5u8; 16], Some(&[6]))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &wrong_value, &proof),
+            Ok(false)
+        );
+
+        let wrong_absence: [([u8; 16], Option<&[u8]>); 2] =
+            [([1u8; 16], None), ([5u8; 16], Some(&[5]))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &wrong_absence, &proof),
+            Ok(false)
+        );
+
+        let missing_entry: [([u8; 16], Option<&[u8]>); 1] = [([1u8; 16], Some(&[1]))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &missing_entry, &proof),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: proof.siblings.len()
+            })
+        );
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &[], &proof),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_multiproof_for_adjacent_keys_is_smaller() {
+        let mut tree = setup_tree();
+        let key1 = [0xaau8; 16];
+        let mut key2 = key1;
+        key2[15] ^= 1;
+        tree.insert(&key1, b"left");
+        tree.insert(&key2, b"right");
+
+        let proof = tree.generate_multiproof(&[key1, key2]);
+        assert_eq!(proof.siblings.len(), 127);
+        let single = tree.generate_proof(&key1).siblings.len();
+        assert!(proof.siblings.len() < 2 * single);
+
+        let entries: [([u8; 16], Option<&[u8]>); 2] =
+            [(key1, Some(b"left")), (key2, Some(b"right"))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &entries, &proof),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_multiproof_duplicate_keys() {
+        let tree = setup_tree();
+        let proof = tree.generate_multiproof(&[[2u8; 16], [2u8; 16]]);
+        assert_eq!(proof, tree.generate_multiproof(&[[2u8; 16]]));
+
+        let duplicated: [([u8; 16], Option<&[u8]>); 2] =
+            [([2u8; 16], Some(&[2])), ([2u8; 16], Some(&[2]))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &duplicated, &proof),
+            Ok(true)
+        );
+
+        let conflicting: [([u8; 16], Option<&[u8]>); 2] =
+            [([2u8; 16], Some(&[2])), ([2u8; 16], None)];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &conflicting, &proof),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_multiproof_with_extra_siblings_fails() {
+        let tree = setup_tree();
+        let mut proof = tree.generate_multiproof(&[[3u8; 16]]);
+        proof.siblings.push([0; 32]);
+        let entries: [([u8; 16], Option<&[u8]>); 1] = [([3u8; 16], Some(&[3]))];
+        assert_eq!(
+            <SparseMerkleTree>::verify_multiproof(&tree.verifier(), &entries, &proof),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 129
+            })
+        );
+    }
+}
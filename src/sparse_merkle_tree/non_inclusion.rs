@@ -0,0 +1,305 @@
+use super::{
+    bit, ct_eq, truncate, with_bit, NodeStore, Smt, SmtError, SmtHasher, SmtVerifier, DEFAULT_LEAF,
+};
+
+/// Non-inclusion proof that stops where the key's path leaves the populated part of
+/// the tree, instead of carrying a sibling for every level. `witness` is the node at
+/// level `siblings.len()` on the key's path, and `siblings` lead from there to the
+/// root, ordered from that level up.
+///
+/// A leaf's position is its full key, so a `Leaf` witness commits to its key even
+/// under `SmtFlavor::Plain`: the verifier rebuilds the subtree around it and a wrong
+/// key puts the leaf somewhere else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtNonInclusionProof<const N: usize> {
+    pub depth: usize,
+    pub witness: NonInclusionWitness<N>,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// What sits on the absent key's path where a `SmtNonInclusionProof` stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonInclusionWitness<const N: usize> {
+    /// An empty subtree, i.e. the default node at that level.
+    Empty,
+    /// A subtree holding a single leaf, for another key sharing the path down to it.
+    Leaf { key: [u8; N], leaf: [u8; 32] },
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Compact proof that `key` is absent, or `None` if it is set. The proof has
+    /// one sibling per level above the shallowest empty or single-leaf subtree on
+    /// the key's path, about `log2(len())` for uniformly spread keys.
+    pub fn generate_non_inclusion_proof(&self, key: &[u8; N]) -> Option<SmtNonInclusionProof<N>> {
+        let path = self.path(key);
+        if self.node(self.depth, path) != self.default_nodes[self.depth] {
+            return None;
+        }
+        let empty_level = (0..=self.depth)
+            .find(|&level| self.node(level, truncate(&path, level)) == self.default_nodes[level])
+            .unwrap();
+
+        // The parent of the empty subtree is the deepest candidate for a single leaf,
+        // and each ancestor with an empty sibling on the way up holds the same leaf.
+        let mut level = empty_level;
+        let mut witness = NonInclusionWitness::Empty;
+        if let Some(parent) = empty_level.checked_sub(1) {
+            if let Some(leaf_path) = self.single_leaf(parent, truncate(&path, parent)) {
+                level = parent;
+                while level > 0
+                    && self.node(level, Self::sibling(&path, level)) == self.default_nodes[level]
+                {
+                    level -= 1;
+                }
+                witness = NonInclusionWitness::Leaf {
+                    key: self.bit_order.path(&leaf_path, self.depth),
+                    leaf: self.node(self.depth, leaf_path),
+                };
+            }
+        }
+        Some(SmtNonInclusionProof {
+            depth: self.depth,
+            witness,
+            siblings: self.proof_for_node(level, path, false).siblings,
+        })
+    }
+
+    /// Path of the only leaf under the node at `level` with `prefix`, or `None` if
+    /// the subtree holds more than one.
+    fn single_leaf(&self, level: usize, prefix: [u8; N]) -> Option<[u8; N]> {
+        let mut prefix = prefix;
+        for level in level..self.depth {
+            let default = self.default_nodes[level + 1];
+            let right = with_bit(&prefix, level, true);
+            match (
+                self.node(level + 1, prefix) != default,
+                self.node(level + 1, right) != default,
+            ) {
+                (true, false) => {}
+                (false, true) => prefix = right,
+                _ => return None,
+            }
+        }
+        Some(prefix)
+    }
+
+    /// Verifies that `key` is absent from the tree at the root of `verifier`, its
+    /// paths read in the verifier's bit order. Returns `false` if the witness leaf
+    /// is `key`'s own, if its key doesn't share `key`'s path down to where the
+    /// proof stops, or if the proof doesn't match the root. Fails with
+    /// `DepthMismatch` for a proof of another depth, `InvalidDepth` for an
+    /// out-of-range depth, `InvalidProofLength` for more siblings than levels, and
+    /// `KeyLength` for keys with bits past the depth.
+    pub fn verify_non_inclusion(
+        verifier: &SmtVerifier<N, H>,
+        key: &[u8; N],
+        proof: &SmtNonInclusionProof<N>,
+    ) -> Result<bool, SmtError> {
+        let depth = verifier.depth();
+        if proof.depth != depth {
+            return Err(SmtError::DepthMismatch {
+                expected: depth,
+                actual: proof.depth,
+            });
+        }
+        Self::check_depth(depth)?;
+        Self::check_key(key, depth)?;
+        let path = verifier.bit_order().path(key, depth);
+        let level = proof.siblings.len();
+        if level > depth {
+            return Err(SmtError::InvalidProofLength {
+                expected: depth,
+                actual: level,
+            });
+        }
+
+        let default_nodes = Self::default_nodes(depth);
+        let node = match &proof.witness {
+            NonInclusionWitness::Empty => default_nodes[level],
+            NonInclusionWitness::Leaf {
+                key: leaf_key,
+                leaf,
+            } => {
+                Self::check_key(leaf_key, depth)?;
+                let leaf_path = verifier.bit_order().path(leaf_key, depth);
+                if leaf_key == key
+                    || *leaf == DEFAULT_LEAF
+                    || truncate(&leaf_path, level) != truncate(&path, level)
+                {
+                    return Ok(false);
+                }
+                // Rebuild the subtree around the leaf, every other node in it being empty.
+                let mut node = *leaf;
+                for i in (level..depth).rev() {
+                    let sibling = &default_nodes[i + 1];
+                    node = if bit(&leaf_path, i) {
+                        Self::hash_pair(sibling, &node)
+                    } else {
+                        Self::hash_pair(&node, sibling)
+                    };
+                }
+                node
+            }
+        };
+        Ok(ct_eq(
+            &Self::fold_node(&path, node, &proof.siblings),
+            &verifier.root(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitOrder, SmtFlavor, SparseMerkleTree};
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..8u8 {
+            tree.insert(&[i * 32; 16], &[i]);
+        }
+        tree
+    }
+
+    fn setup_tree_without(key: u8) -> SparseMerkleTree {
+        let mut tree = setup_tree();
+        tree.delete(&[key; 16]);
+        tree
+    }
+
+    fn verify(tree: &SparseMerkleTree, key: &[u8; 16], proof: &SmtNonInclusionProof<16>) -> bool {
+        <SparseMerkleTree>::verify_non_inclusion(&tree.verifier(), key, proof).unwrap()
+    }
+
+    #[test]
+    fn test_non_inclusion_via_nearest_leaf() {
+        let tree = setup_tree();
+        // Shares its first 3 bits with [0x20; 16] only.
+        let mut key = [0x20; 16];
+        key[15] = 0x21;
+        let proof = tree.generate_non_inclusion_proof(&key).unwrap();
+        assert_eq!(
+            proof.witness,
+            NonInclusionWitness::Leaf {
+                key: [0x20; 16],
+                leaf: tree.node(128, [0x20; 16]),
+            }
+        );
+        assert_eq!(proof.siblings.len(), 3);
+        assert!(verify(&tree, &key, &proof));
+        assert!(!verify(&setup_tree_without(0x20), &key, &proof));
+        assert_eq!(tree.generate_non_inclusion_proof(&[0x20; 16]), None);
+    }
+
+    #[test]
+    fn test_non_inclusion_via_empty_subtree() {
+        let mut tree = setup_tree();
+        tree.insert(&[0x21; 16], b"neighbour");
+        // 0x22.. leaves the path of [0x20; 16] and [0x21; 16] at bit 6, into an empty
+        // subtree whose sibling holds both of them, so no single leaf is nearer.
+        let key = [0x22; 16];
+        let proof = tree.generate_non_inclusion_proof(&key).unwrap();
+        assert_eq!(proof.witness, NonInclusionWitness::Empty);
+        assert_eq!(proof.siblings.len(), 7);
+        assert!(verify(&tree, &key, &proof));
+
+        let empty = SparseMerkleTree::new();
+        let proof = empty.generate_non_inclusion_proof(&key).unwrap();
+        assert_eq!(proof.siblings.len(), 0);
+        assert!(verify(&empty, &key, &proof));
+        assert!(!verify(&tree, &key, &proof));
+    }
+
+    #[test]
+    fn test_non_inclusion_rejects_own_leaf() {
+        let tree = setup_tree();
+        let key = [0x40; 16];
+        let leaf = tree.node(128, key);
+        let mut absent = key;
+        absent[15] = 1;
+        let mut proof = tree.generate_non_inclusion_proof(&absent).unwrap();
+        assert!(verify(&tree, &absent, &proof));
+
+        // The same witness names the queried key itself, so it can't prove it absent.
+        assert!(!verify(&tree, &key, &proof));
+        proof.witness = NonInclusionWitness::Leaf { key: absent, leaf };
+        assert!(!verify(&tree, &absent, &proof));
+    }
+
+    #[test]
+    fn test_non_inclusion_rejects_tampering() {
+        let tree = setup_tree();
+        let key = [0x50; 16];
+        let proof = tree.generate_non_inclusion_proof(&key).unwrap();
+        assert!(verify(&tree, &key, &proof));
+
+        let mut wrong_sibling = proof.clone();
+        wrong_sibling.siblings[0] = [1; 32];
+        assert!(!verify(&tree, &key, &wrong_sibling));
+
+        // A leaf off the key's path.
+        let mut off_path = proof.clone();
+        off_path.witness = NonInclusionWitness::Leaf {
+            key: [0xe0; 16],
+            leaf: tree.node(128, [0xe0; 16]),
+        };
+        assert!(!verify(&tree, &key, &off_path));
+
+        let mut too_long = proof.clone();
+        too_long.siblings = vec![[0; 32]; 129];
+        assert_eq!(
+            <SparseMerkleTree>::verify_non_inclusion(&tree.verifier(), &key, &too_long),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 129
+            })
+        );
+        let mut no_depth = proof;
+        no_depth.depth = 0;
+        assert_eq!(
+            <SparseMerkleTree>::verify_non_inclusion(&tree.verifier(), &key, &no_depth),
+            Err(SmtError::DepthMismatch {
+                expected: 128,
+                actual: 0
+            })
+        );
+        let shallow = SmtVerifier::new(tree.root(), 0, SmtFlavor::Plain);
+        assert_eq!(
+            <SparseMerkleTree>::verify_non_inclusion(&shallow, &key, &no_depth),
+            Err(SmtError::InvalidDepth { depth: 0, max: 128 })
+        );
+    }
+
+    #[test]
+    fn test_non_inclusion_matches_full_proofs() {
+        let key_at = |i: u16| {
+            let mut key = [0u8; 16];
+            key[..2].copy_from_slice(&i.to_be_bytes());
+            key
+        };
+        for bit_order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut tree = SparseMerkleTree::with_depth(16)
+                .with_flavor(SmtFlavor::PathCommitting)
+                .with_bit_order(bit_order);
+            let present: Vec<_> = (0..40u16).map(|i| key_at(i.wrapping_mul(0x9e37))).collect();
+            for (i, key) in present.iter().enumerate() {
+                tree.insert(key, &i.to_be_bytes());
+            }
+            for i in 0..300u16 {
+                let key = key_at(i.wrapping_mul(0x3b9));
+                match tree.generate_non_inclusion_proof(&key) {
+                    Some(proof) => {
+                        assert!(tree.get(&key).is_none());
+                        assert!(proof.siblings.len() < 16);
+                        assert!(verify(&tree, &key, &proof));
+                        // Nor does it pass for a key that is set.
+                        for key in &present {
+                            assert!(!verify(&tree, key, &proof));
+                        }
+                    }
+                    None => assert!(tree.get(&key).is_some()),
+                }
+            }
+        }
+    }
+}
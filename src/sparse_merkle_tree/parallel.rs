@@ -0,0 +1,137 @@
+use super::{truncate, NodeStore, Smt, SmtHasher};
+use rayon::prelude::*;
+
+/// Bits below the keys' shared prefix used to partition a batch, giving up to
+/// 256 independent subtrees.
+const PARTITION_BITS: usize = 8;
+
+impl<const N: usize, H: SmtHasher + Sync, S: NodeStore<N> + Sync> Smt<N, H, S> {
+    /// Same result as `insert_batch`, with the subtrees under the keys' shared
+    /// prefix hashed in parallel before their roots are combined up to the root.
+    /// Partitioning starts below the prefix every key shares, so a batch that
+    /// falls entirely under one branch near the root is still split up.
+    pub fn insert_batch_parallel(&mut self, entries: &[([u8; N], Vec<u8>)]) -> [u8; 32] {
+        let leaves = self.batch_leaves(entries);
+        let (Some(first), Some(last)) = (leaves.first(), leaves.last()) else {
+            return self.root;
+        };
+        let split = (shared_prefix_len(&first.0, &last.0) + PARTITION_BITS).min(self.depth);
+
+        let partitions: Vec<_> = leaves
+            .chunk_by(|a, b| truncate(&a.0, split) == truncate(&b.0, split))
+            .collect();
+        let mut nodes: Vec<_> = partitions
+            .into_par_iter()
+            .map(|partition| self.rehash_batch(self.depth, partition.to_vec(), split))
+            .flatten()
+            .collect();
+
+        let mut subtree_roots: Vec<_> = nodes
+            .iter()
+            .filter(|((level, _), _)| *level == split)
+            .map(|&((_, prefix), node)| (prefix, node))
+            .collect();
+        subtree_roots.sort_unstable_by_key(|&(prefix, _)| prefix);
+        nodes.extend(self.rehash_batch(split, subtree_roots, 0));
+        self.apply_batch(entries, nodes);
+        self.root
+    }
+}
+
+/// Number of leading bits `a` and `b` have in common.
+fn shared_prefix_len<const N: usize>(a: &[u8; N], b: &[u8; N]) -> usize {
+    a.iter()
+        .zip(b)
+        .position(|(x, y)| x != y)
+        .map_or(N * 8, |i| i * 8 + (a[i] ^ b[i]).leading_zeros() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SparseMerkleTree, SparseMerkleTree256};
+
+    fn entries(count: u32, key: impl Fn(u32) -> [u8; 16]) -> Vec<([u8; 16], Vec<u8>)> {
+        (0..count)
+            .map(|i| (key(i), i.to_be_bytes().to_vec()))
+            .collect()
+    }
+
+    fn assert_same_as_sequential(tree: &SparseMerkleTree, entries: &[([u8; 16], Vec<u8>)]) {
+        let mut sequential = tree.clone();
+        sequential.insert_batch(entries);
+        let mut parallel = tree.clone();
+        parallel.insert_batch_parallel(entries);
+        assert_eq!(parallel.root(), sequential.root());
+        assert_eq!(parallel.store, sequential.store);
+    }
+
+    #[test]
+    fn test_shared_prefix_len() {
+        assert_eq!(shared_prefix_len(&[0u8; 2], &[0u8; 2]), 16);
+        assert_eq!(shared_prefix_len(&[0x80, 0], &[0, 0]), 0);
+        assert_eq!(shared_prefix_len(&[0xab, 0x01], &[0xab, 0x00]), 15);
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let spread = entries(2000, |i| {
+            let mut key = [0u8; 16];
+            key[..4].copy_from_slice(&i.wrapping_mul(0x9e37_79b9).to_be_bytes());
+            key
+        });
+        assert_same_as_sequential(&SparseMerkleTree::new(), &spread);
+
+        let mut existing = SparseMerkleTree::new();
+        existing.insert_batch(&spread[..500]);
+        let mut overlapping = entries(300, |i| [(i % 7) as u8; 16]);
+        overlapping.extend_from_slice(&spread[400..900]);
+        assert_same_as_sequential(&existing, &overlapping);
+        assert_same_as_sequential(&existing, &[]);
+
+        // Entries holding the default value delete their keys in both.
+        let mut zeroing = SparseMerkleTree::new().with_default_value(&0u32.to_be_bytes());
+        zeroing.insert_batch(&spread[1..500]);
+        let deletes: Vec<_> = spread[..900]
+            .iter()
+            .step_by(3)
+            .map(|(key, _)| (*key, 0u32.to_be_bytes().to_vec()))
+            .collect();
+        assert_same_as_sequential(&zeroing, &deletes);
+    }
+
+    #[test]
+    fn test_parallel_with_keys_under_one_branch() {
+        // Every key shares its first 15 bytes, so all of them fall in one partition of the top bits.
+        let clustered = entries(256, |i| {
+            let mut key = [0x5a; 16];
+            key[15] = i as u8;
+            key
+        });
+        assert_same_as_sequential(&SparseMerkleTree::new(), &clustered);
+        assert_same_as_sequential(&SparseMerkleTree::new(), &clustered[..1]);
+
+        let mut shallow = SparseMerkleTree::with_depth(8);
+        let keys = entries(40, |i| {
+            let mut key = [0u8; 16];
+            key[0] = (i * 5) as u8;
+            key
+        });
+        let mut sequential = shallow.clone();
+        sequential.insert_batch(&keys);
+        shallow.insert_batch_parallel(&keys);
+        assert_eq!(shallow.root(), sequential.root());
+    }
+
+    #[test]
+    fn test_parallel_256_bit_keys() {
+        let keys: Vec<([u8; 32], Vec<u8>)> = (0..300u32)
+            .map(|i| ([i as u8; 32], i.to_be_bytes().to_vec()))
+            .collect();
+        let mut sequential = SparseMerkleTree256::new();
+        sequential.insert_batch(&keys);
+        let mut parallel = SparseMerkleTree256::new();
+        parallel.insert_batch_parallel(&keys);
+        assert_eq!(parallel.root(), sequential.root());
+    }
+}
@@ -0,0 +1,200 @@
+use super::proof::{ct_eq, DEFAULT_LEAF};
+use super::{bit, truncate, NodeStore, Sha256Hasher, Smt, SmtError, SmtHasher, SmtProof};
+use super::{SmtFlavor, SmtVerifier};
+use std::collections::HashSet;
+
+/// The part of a tree covering a set of keys, rebuilt from proofs against a
+/// trusted root, see `Smt::from_proofs`. It holds the nodes on and beside those
+/// keys' paths, enough to read them and prove them again, and nothing else.
+#[derive(Debug, Clone)]
+pub struct PartialSmt<const N: usize, H = Sha256Hasher> {
+    tree: Smt<N, H>,
+    keys: HashSet<[u8; N]>,
+}
+
+/// A key, its value (`None` if absent) and the proof of that.
+type ProvenEntry<const N: usize> = ([u8; N], Option<Vec<u8>>, SmtProof);
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Checks each `(key, value, proof)` against the root of `verifier`, `None`
+    /// standing for an absent key, and keeps the nodes along those paths. The
+    /// depth, flavor and bit order are the verifier's.
+    ///
+    /// Fails with `RootMismatch` for a proof that doesn't lead to the root, and
+    /// like `SmtVerifier::verify` for a malformed one or one from another kind of
+    /// tree. Every proof leads to the same root, so two proofs disagreeing on a
+    /// node, e.g. one's sibling against the node the other hashes to at that
+    /// position, can't both pass.
+    pub fn from_proofs(
+        verifier: &SmtVerifier<N, H>,
+        items: &[ProvenEntry<N>],
+    ) -> Result<PartialSmt<N, H>, SmtError> {
+        let (root, depth) = (verifier.root(), verifier.depth());
+        let (flavor, bit_order) = (verifier.flavor(), verifier.bit_order());
+        Self::check_depth(depth)?;
+        let mut tree = Smt::<N, H>::with_depth_and_hasher(depth)
+            .with_flavor(flavor)
+            .with_bit_order(bit_order);
+        let mut keys = HashSet::new();
+        for (index, (key, value, proof)) in items.iter().enumerate() {
+            verifier.check_proof(proof)?;
+            Self::check_key(key, depth)?;
+            let path = bit_order.path(key, depth);
+            let mut node = value
+                .as_deref()
+                .map_or(DEFAULT_LEAF, |value| Self::leaf_hash(flavor, &path, value));
+            let mut nodes = vec![((depth, path), node)];
+            for level in (1..=depth).rev() {
+                let sibling = proof.siblings[depth - level];
+                nodes.push(((level, Self::sibling(&path, level)), sibling));
+                node = if bit(&path, level - 1) {
+                    Self::hash_pair(&sibling, &node)
+                } else {
+                    Self::hash_pair(&node, &sibling)
+                };
+                nodes.push(((level - 1, truncate(&path, level - 1)), node));
+            }
+            if proof.hashed_key || !ct_eq(&node, &root) {
+                return Err(SmtError::RootMismatch { index });
+            }
+
+            for ((level, prefix), node) in nodes {
+                if node != tree.default_nodes[level] {
+                    tree.store.put(level, prefix, node);
+                }
+            }
+            if let Some(value) = value {
+                tree.store.put_value(*key, value.clone());
+            }
+            keys.insert(*key);
+        }
+        tree.root = root;
+        Ok(PartialSmt { tree, keys })
+    }
+}
+
+impl<const N: usize, H: SmtHasher> PartialSmt<N, H> {
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn flavor(&self) -> SmtFlavor {
+        self.tree.flavor()
+    }
+
+    /// Number of keys covered, present or absent.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn covers(&self, key: &[u8; N]) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// The value of a covered key, `None` if it's absent. Fails with `NotCovered`
+    /// for any other key.
+    pub fn get(&self, key: &[u8; N]) -> Result<Option<Vec<u8>>, SmtError> {
+        self.check_covered(key)?;
+        Ok(self.tree.get(key))
+    }
+
+    /// The proof of a covered key, as the full tree would generate it. Fails with
+    /// `NotCovered` for any other key.
+    pub fn generate_proof(&self, key: &[u8; N]) -> Result<SmtProof, SmtError> {
+        self.check_covered(key)?;
+        Ok(self.tree.generate_proof(key))
+    }
+
+    fn check_covered(&self, key: &[u8; N]) -> Result<(), SmtError> {
+        if !self.covers(key) {
+            return Err(SmtError::NotCovered);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitOrder, SparseMerkleTree};
+
+    fn full_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..50u8 {
+            tree.insert(&[i.wrapping_mul(41); 16], &[i]);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_from_proofs_serves_imported_keys() {
+        let tree = full_tree();
+        // Two present keys, one absent one and a repeat.
+        let keys = [[0; 16], [41; 16], [7; 16], [41; 16]];
+        let items: Vec<_> = keys
+            .iter()
+            .map(|key| (*key, tree.get(key), tree.generate_proof(key)))
+            .collect();
+        let partial = <SparseMerkleTree>::from_proofs(&tree.verifier(), &items).unwrap();
+
+        assert_eq!(partial.root(), tree.root());
+        assert_eq!(partial.len(), 3);
+        for key in &keys {
+            assert_eq!(partial.get(key), Ok(tree.get(key)));
+            assert_eq!(partial.generate_proof(key), Ok(tree.generate_proof(key)));
+        }
+        assert_eq!(partial.get(&[82; 16]), Err(SmtError::NotCovered));
+        assert_eq!(partial.generate_proof(&[1; 16]), Err(SmtError::NotCovered));
+
+        let empty = <SparseMerkleTree>::from_proofs(&tree.verifier(), &[]).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.get(&[0; 16]), Err(SmtError::NotCovered));
+    }
+
+    #[test]
+    fn test_from_proofs_rejects_bad_and_conflicting_proofs() {
+        let tree = full_tree();
+        let item = |key: [u8; 16]| (key, tree.get(&key), tree.generate_proof(&key));
+
+        let mut wrong_value = item([41; 16]);
+        wrong_value.1 = Some(b"forged".to_vec());
+        assert_eq!(
+            <SparseMerkleTree>::from_proofs(&tree.verifier(), &[item([0; 16]), wrong_value]).err(),
+            Some(SmtError::RootMismatch { index: 1 })
+        );
+
+        // A sibling near the root that [41; 16] shares with [0; 16] changed, so the
+        // two proofs disagree on the node at that position.
+        let (first, second) = (item([0; 16]), item([41; 16]));
+        assert_eq!(first.2.siblings[127], second.2.siblings[127]);
+        let mut conflicting = second.clone();
+        conflicting.2.siblings[127][0] ^= 1;
+        assert_eq!(
+            <SparseMerkleTree>::from_proofs(&tree.verifier(), &[first.clone(), conflicting]).err(),
+            Some(SmtError::RootMismatch { index: 1 })
+        );
+
+        let mut other_flavor = second;
+        other_flavor.2.flavor = SmtFlavor::PathCommitting;
+        assert!(matches!(
+            <SparseMerkleTree>::from_proofs(&tree.verifier(), &[first.clone(), other_flavor]),
+            Err(SmtError::FlavorMismatch { .. })
+        ));
+
+        // The shape is the verifier's, even for the first proof.
+        let mut other_order = first;
+        other_order.2.bit_order = BitOrder::LsbFirst;
+        assert!(matches!(
+            <SparseMerkleTree>::from_proofs(&tree.verifier(), &[other_order]),
+            Err(SmtError::BitOrderMismatch { .. })
+        ));
+    }
+}
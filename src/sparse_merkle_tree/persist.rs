@@ -0,0 +1,267 @@
+use super::{BitOrder, Smt, SmtError, SmtFlavor, SmtHasher};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// First bytes of a saved tree.
+const MAGIC: &[u8; 4] = b"LSMT";
+const VERSION: u8 = 1;
+
+const PATH_COMMITTING_FLAG: u8 = 0x01;
+const DEFAULT_VALUE_FLAG: u8 = 0x02;
+const LENGTH_PREFIXED_FLAG: u8 = 0x04;
+const LSB_FIRST_FLAG: u8 = 0x08;
+
+impl<const N: usize, H: SmtHasher> Smt<N, H> {
+    /// Writes the tree to `path`, replacing the file. Integers are big-endian:
+    ///
+    /// ```text
+    /// "LSMT" | version: u8 | depth: u16 | key length: u16 | flags: u8
+    ///        | [default value length: u32 | default value]
+    ///        | root: [u8; 32] | entry count: u64
+    ///        | entry count * (key | value length: u32 | value)
+    /// ```
+    ///
+    /// Flags are 0x01 for `SmtFlavor::PathCommitting`, 0x04 for
+    /// `SmtFlavor::LengthPrefixed`, 0x08 for `BitOrder::LsbFirst` and 0x02 when a
+    /// default value follows. Entries are in ascending key order. Internal nodes aren't saved;
+    /// `load` rebuilds them and checks the result against the saved root.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        let mut flags = match self.flavor {
+            SmtFlavor::Plain => 0,
+            SmtFlavor::PathCommitting => PATH_COMMITTING_FLAG,
+            SmtFlavor::LengthPrefixed => LENGTH_PREFIXED_FLAG,
+        };
+        if self.default_value.is_some() {
+            flags |= DEFAULT_VALUE_FLAG;
+        }
+        if self.bit_order == BitOrder::LsbFirst {
+            flags |= LSB_FIRST_FLAG;
+        }
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.write_all(&(self.depth as u16).to_be_bytes())?;
+        file.write_all(&(N as u16).to_be_bytes())?;
+        file.write_all(&[flags])?;
+        if let Some(default_value) = &self.default_value {
+            write_bytes(&mut file, default_value)?;
+        }
+        file.write_all(&self.root)?;
+        file.write_all(&(self.len() as u64).to_be_bytes())?;
+        for (key, value) in self.iter() {
+            file.write_all(key)?;
+            write_bytes(&mut file, value)?;
+        }
+        file.flush()
+    }
+
+    /// Reads a tree written by `save`. Fails with `StorageError` if the file can't
+    /// be read, `UnsupportedVersion` for a format this build doesn't know, and
+    /// `InvalidEncoding` if the file is truncated, has trailing bytes, or its
+    /// entries don't hash to its root, e.g. because it was saved with another hasher.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SmtError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| {
+            SmtError::StorageError(format!("failed to read {}: {err}", path.display()))
+        })?;
+        let mut bytes = bytes.as_slice();
+        if take(&mut bytes, MAGIC.len())? != MAGIC {
+            return Err(SmtError::InvalidEncoding("not a saved tree".into()));
+        }
+        let version = take(&mut bytes, 1)?[0];
+        if version != VERSION {
+            return Err(SmtError::UnsupportedVersion(version));
+        }
+        let depth = read_u16(&mut bytes)? as usize;
+        Self::check_depth(depth)?;
+        let key_len = read_u16(&mut bytes)? as usize;
+        if key_len != N {
+            return Err(SmtError::InvalidEncoding(format!(
+                "keys are {key_len} bytes, expected {N}"
+            )));
+        }
+        let flags = take(&mut bytes, 1)?[0];
+        let flavor = match flags & !(DEFAULT_VALUE_FLAG | LSB_FIRST_FLAG) {
+            0 => SmtFlavor::Plain,
+            PATH_COMMITTING_FLAG => SmtFlavor::PathCommitting,
+            LENGTH_PREFIXED_FLAG => SmtFlavor::LengthPrefixed,
+            _ => return Err(SmtError::InvalidFlags(flags)),
+        };
+        let bit_order = if flags & LSB_FIRST_FLAG != 0 {
+            BitOrder::LsbFirst
+        } else {
+            BitOrder::MsbFirst
+        };
+        let mut tree = Self::with_depth_and_hasher(depth)
+            .with_flavor(flavor)
+            .with_bit_order(bit_order);
+        if flags & DEFAULT_VALUE_FLAG != 0 {
+            tree.default_value = Some(read_bytes(&mut bytes)?.to_vec());
+        }
+        let root = take(&mut bytes, 32)?;
+
+        let count = u64::from_be_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+        // Each entry takes at least N + 4 bytes, which bounds the allocation.
+        let mut entries = Vec::with_capacity(count.min((bytes.len() / (N + 4)) as u64) as usize);
+        for _ in 0..count {
+            let key: [u8; N] = take(&mut bytes, N)?.try_into().unwrap();
+            let value = read_bytes(&mut bytes)?;
+            if tree.is_default_value(value) {
+                return Err(SmtError::InvalidEncoding(
+                    "entry holds the default value".into(),
+                ));
+            }
+            entries.push((key, value));
+        }
+        if !bytes.is_empty() {
+            return Err(SmtError::InvalidEncoding(format!(
+                "{} trailing bytes",
+                bytes.len()
+            )));
+        }
+        if bit_order == BitOrder::MsbFirst {
+            tree.load_sorted(entries)?;
+        } else {
+            // Key order isn't path order, which `load_sorted` needs.
+            let entries: Vec<_> = entries
+                .into_iter()
+                .map(|(key, value)| (key, value.to_vec()))
+                .collect();
+            tree.insert_batch(&entries);
+        }
+        if tree.root[..] != *root {
+            return Err(SmtError::InvalidEncoding(
+                "entries don't match the saved root".into(),
+            ));
+        }
+        Ok(tree)
+    }
+}
+
+fn write_bytes(file: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    file.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    file.write_all(bytes)
+}
+
+/// Splits the next `len` bytes off `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], SmtError> {
+    if bytes.len() < len {
+        return Err(SmtError::InvalidEncoding("file is truncated".into()));
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn read_u16(bytes: &mut &[u8]) -> Result<u16, SmtError> {
+    Ok(u16::from_be_bytes(take(bytes, 2)?.try_into().unwrap()))
+}
+
+/// Reads a u32 length and that many bytes.
+fn read_bytes<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], SmtError> {
+    let len = u32::from_be_bytes(take(bytes, 4)?.try_into().unwrap());
+    take(bytes, len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Keccak256Hasher, SparseMerkleTree, SparseMerkleTree256};
+    use std::env;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("lil-merkle-{}-{name}.smt", std::process::id()))
+    }
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::with_depth(64).with_flavor(SmtFlavor::PathCommitting);
+        for i in 0..20u8 {
+            let mut key = [0u8; 16];
+            key[..8].copy_from_slice(&u64::from(i).wrapping_mul(0x9e37_79b9).to_be_bytes());
+            tree.insert(&key, &vec![i; usize::from(i)]);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let file = temp_file("round-trip");
+        let tree = setup_tree();
+        tree.save(&file).unwrap();
+        let loaded = <SparseMerkleTree>::load(&file).unwrap();
+        assert_eq!(loaded.root, tree.root);
+        assert_eq!(loaded.depth(), 64);
+        assert_eq!(loaded.len(), 20);
+        for (key, value) in tree.iter() {
+            let proof = loaded.generate_proof(key);
+            assert_eq!(proof, tree.generate_proof(key));
+            assert_eq!(loaded.verify_proof(key, Some(value), &proof), Ok(true));
+        }
+
+        let tree = SparseMerkleTree256::new().with_default_value(b"zero");
+        tree.save(&file).unwrap();
+        let loaded = <SparseMerkleTree256>::load(&file).unwrap();
+        assert_eq!(loaded.root, tree.root);
+        assert_eq!(loaded.default_value(), Some(&b"zero"[..]));
+
+        let mut tree = SparseMerkleTree::new().with_flavor(SmtFlavor::LengthPrefixed);
+        tree.insert(&[9; 16], b"framed");
+        tree.save(&file).unwrap();
+        let loaded = <SparseMerkleTree>::load(&file).unwrap();
+        assert_eq!(loaded.flavor(), SmtFlavor::LengthPrefixed);
+        assert_eq!(loaded.root, tree.root);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_load_rejects_bad_files() {
+        let file = temp_file("bad");
+        setup_tree().save(&file).unwrap();
+        let bytes = fs::read(&file).unwrap();
+        let load = |bytes: &[u8]| {
+            fs::write(&file, bytes).unwrap();
+            <SparseMerkleTree>::load(&file).unwrap_err()
+        };
+
+        for len in [0, 3, 10, 48, bytes.len() - 1] {
+            assert_eq!(
+                load(&bytes[..len]),
+                SmtError::InvalidEncoding("file is truncated".into())
+            );
+        }
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert_eq!(load(&future), SmtError::UnsupportedVersion(2));
+        assert_eq!(
+            load(&[&bytes[..], &[0]].concat()),
+            SmtError::InvalidEncoding("1 trailing bytes".into())
+        );
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            load(&flipped),
+            SmtError::InvalidEncoding("entries don't match the saved root".into())
+        );
+        assert_eq!(
+            load(b"not a tree"),
+            SmtError::InvalidEncoding("not a saved tree".into())
+        );
+
+        fs::write(&file, &bytes).unwrap();
+        assert_eq!(
+            <SparseMerkleTree256>::load(&file).unwrap_err(),
+            SmtError::InvalidEncoding("keys are 16 bytes, expected 32".into())
+        );
+        assert_eq!(
+            SparseMerkleTree::<Keccak256Hasher>::load(&file).unwrap_err(),
+            SmtError::InvalidEncoding("entries don't match the saved root".into())
+        );
+        fs::remove_file(&file).unwrap();
+        assert!(matches!(
+            <SparseMerkleTree>::load(&file),
+            Err(SmtError::StorageError(_))
+        ));
+    }
+}
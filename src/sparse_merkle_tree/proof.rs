@@ -0,0 +1,770 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt;
+use sha2::digest::Output;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+/// Hash of an empty leaf, at the bottom of every default subtree. No value hashes to
+/// it, not even an empty one, so only keys that were never set (or were deleted) have it.
+pub(super) const DEFAULT_LEAF: [u8; 32] = [0; 32];
+
+/// Hash function used to build a tree. Default nodes are derived from it, so
+/// roots from different hashers never match.
+///
+/// Leaves are hashed as `hash(0x00 || value)` and internal nodes as
+/// `hash(0x01 || left || right)`. Without the prefixes a 64-byte value equal to
+/// two child hashes would hash to their parent, letting a shortened proof pass
+/// that value off as a leaf. Roots built before the prefixes were added don't
+/// match; a hasher can override `hash_leaf` and `hash_pair` to reproduce them.
+pub trait SmtHasher {
+    /// Plain digest of `data`, also used to derive paths from hashed keys.
+    fn hash(data: &[u8]) -> [u8; 32];
+
+    /// `hash` of `parts` concatenated. Hashers with an incremental digest override
+    /// it to hash the parts in place, so hashing a leaf allocates nothing.
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        Self::hash(&parts.concat())
+    }
+
+    fn hash_leaf(value: &[u8]) -> [u8; 32] {
+        Self::hash_parts(&[&[0x00], value])
+    }
+
+    /// `hash_leaf` of `parts` concatenated, overridden like `hash_parts`.
+    fn hash_leaf_parts(parts: &[&[u8]]) -> [u8; 32] {
+        Self::hash_leaf(&parts.concat())
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut data = [0x01; 65];
+        data[1..33].copy_from_slice(left);
+        data[33..].copy_from_slice(right);
+        Self::hash(&data)
+    }
+
+    /// Roots of empty subtrees from the leaves up: `DEFAULT_LEAF`, then
+    /// `hash_pair` of the level below with itself, `height + 1` entries in all.
+    /// Hashers with a costly `hash_pair` can serve them from a precomputed table.
+    fn zero_hashes(height: usize) -> Vec<[u8; 32]> {
+        let mut zero_hashes = vec![DEFAULT_LEAF];
+        for _ in 0..height {
+            let below = zero_hashes[zero_hashes.len() - 1];
+            zero_hashes.push(Self::hash_pair(&below, &below));
+        }
+        zero_hashes
+    }
+}
+
+/// SHA-256 with the domain-separated leaf and node hashing of `SmtHasher`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl SmtHasher for Sha256Hasher {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Sha256>(&[], parts)
+    }
+
+    fn hash_leaf_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Sha256>(&[0x00], parts)
+    }
+}
+
+/// Same construction as `Sha256Hasher` with keccak256, matching e.g.
+/// `keccak256(abi.encodePacked(bytes1(0x01), left, right))` in Solidity.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Keccak256Hasher;
+
+impl SmtHasher for Keccak256Hasher {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        Keccak256::digest(data).into()
+    }
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Keccak256>(&[], parts)
+    }
+
+    fn hash_leaf_parts(parts: &[&[u8]]) -> [u8; 32] {
+        digest_parts::<Keccak256>(&[0x00], parts)
+    }
+}
+
+/// `D` of `prefix` followed by `parts`, fed in one at a time.
+fn digest_parts<D: Digest>(prefix: &[u8], parts: &[&[u8]]) -> [u8; 32]
+where
+    Output<D>: Into<[u8; 32]>,
+{
+    let mut digest = D::new_with_prefix(prefix);
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize().into()
+}
+
+/// How leaves are hashed. Internal nodes are the same in every flavor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SmtFlavor {
+    /// `H::hash_leaf(value)`, see `SmtHasher`.
+    #[default]
+    Plain,
+    /// `H::hash(0x00 || path || H::hash(value))`, with the path as the full
+    /// `N`-byte key. This commits each leaf to its position, so a proof only ever
+    /// verifies for its own key. With `Plain`, two sibling leaves holding the same value hash alike,
+    /// and the proof for either verifies for the other.
+    PathCommitting,
+    /// `H::hash_leaf(len || value)`, with `len` the value's length as a
+    /// little-endian `u64`. Framing the value this way means no value's leaf is
+    /// also the leaf of a differently split value, whatever the bytes happen to be.
+    LengthPrefixed,
+}
+
+/// Order in which a key's bits are read to form its path from the root down.
+/// Proofs record the order, and only verify under the order they were made with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BitOrder {
+    /// The root branch is the most significant bit of `key[0]`, so leaves lie in
+    /// ascending key order. See the example on `Smt::insert`.
+    #[default]
+    MsbFirst,
+    /// The first `depth` bits of the key reversed: the root branch is the last of
+    /// them and the leaf-level branch the most significant bit of `key[0]`, as in
+    /// trees indexed by little-endian integers. Keys with the same low bits share
+    /// their path near the root.
+    LsbFirst,
+}
+
+impl BitOrder {
+    /// Path of `key` in a tree of `depth` levels. Reading bits `LsbFirst` twice gives
+    /// back the key, so this also maps a path back to its key.
+    pub(super) fn path<const N: usize>(self, key: &[u8; N], depth: usize) -> [u8; N] {
+        match self {
+            Self::MsbFirst => *key,
+            Self::LsbFirst => {
+                let mut path = [0u8; N];
+                for i in (0..depth).filter(|&i| bit(key, depth - 1 - i)) {
+                    path[i / 8] |= 0x80 >> (i % 8);
+                }
+                path
+            }
+        }
+    }
+}
+
+/// Sibling hashes along a key's path, ordered from the leaf up to the root,
+/// together with the depth, flavor and bit order of the tree that produced them.
+/// `hashed_key` records whether the path was derived from a hashed key (see
+/// `insert_hashed`), so a proof for one kind of key never verifies as the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtProof {
+    pub depth: usize,
+    pub flavor: SmtFlavor,
+    pub hashed_key: bool,
+    pub bit_order: BitOrder,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// How much of a proof is real nodes rather than defaults, see `SmtProof::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofStats {
+    pub depth: usize,
+    /// Siblings that aren't the root of an empty subtree.
+    pub non_default_siblings: usize,
+    /// Length of `SmtProof::encode`, which leaves the default siblings out.
+    pub byte_len_compressed: usize,
+}
+
+/// Bits of the flags byte in the proof encoding. At most one flavor bit is set.
+const HASHED_KEY_FLAG: u8 = 0b0001;
+const PATH_COMMITTING_FLAG: u8 = 0b0010;
+const LENGTH_PREFIXED_FLAG: u8 = 0b0100;
+const LSB_FIRST_FLAG: u8 = 0b1000;
+
+/// Version byte of `SmtProof::encode`.
+const ENCODING_VERSION: u8 = 1;
+
+impl SmtProof {
+    /// Encodes the proof as the depth (2 bytes, big-endian), a flags byte (bit 0 set
+    /// for hashed keys, bit 1 for `SmtFlavor::PathCommitting`, bit 2 for
+    /// `SmtFlavor::LengthPrefixed` and bit 3 for `BitOrder::LsbFirst`) and each
+    /// 32-byte sibling.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flags = self.flags();
+        let mut bytes = Vec::with_capacity(3 + self.siblings.len() * 32);
+        bytes.extend_from_slice(&(self.depth as u16).to_be_bytes());
+        bytes.push(flags);
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// Decodes `to_bytes` output. Fails with `InvalidFlags` if unknown flags are set
+    /// and `InvalidProofLength` if the length doesn't match the encoded depth.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SmtError> {
+        let Some((header, siblings)) = bytes.split_first_chunk::<3>() else {
+            return Err(SmtError::InvalidProofLength {
+                expected: 3,
+                actual: bytes.len(),
+            });
+        };
+        let depth = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let (flavor, hashed_key, bit_order) = Self::parse_flags(header[2])?;
+        if siblings.len() != depth * 32 {
+            return Err(SmtError::InvalidProofLength {
+                expected: 3 + depth * 32,
+                actual: bytes.len(),
+            });
+        }
+        Ok(Self {
+            depth,
+            flavor,
+            hashed_key,
+            bit_order,
+            siblings: siblings
+                .chunks_exact(32)
+                .map(|sibling| sibling.try_into().unwrap())
+                .collect(),
+        })
+    }
+
+    /// `to_bytes` as lowercase hex with a `0x` prefix.
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::from("0x");
+        for byte in self.to_bytes() {
+            hex.push_str(&format!("{byte:02x}"));
+        }
+        hex
+    }
+
+    /// Decodes `to_hex` output. The `0x` prefix is optional and either case is accepted.
+    pub fn from_hex(hex: &str) -> Result<Self, ProofHexError> {
+        Self::from_bytes(&decode_hex(hex)?).map_err(ProofHexError::InvalidProof)
+    }
+
+    /// Compact encoding that leaves out default siblings, which depend on the
+    /// hasher `H` of the tree the proof came from:
+    ///
+    /// ```text
+    /// version: u8 = 1 | flags: u8 | depth - 1: u8 | bitmap: ceil(depth / 8) bytes | siblings
+    /// ```
+    ///
+    /// `flags` are those of `to_bytes`. Bit `j` of the bitmap, read MSB-first, is set
+    /// when sibling `j` (counted from the leaf) isn't the default node at its level,
+    /// in which case it follows in `siblings`, 32 bytes each, leaf first. Bits past
+    /// the depth are zero. Panics for a depth outside `1..=256`.
+    pub fn encode<H: SmtHasher>(&self) -> Vec<u8> {
+        assert!(
+            (1..=256).contains(&self.depth),
+            "depth {} doesn't fit the encoding",
+            self.depth
+        );
+        let zero_hashes = H::zero_hashes(self.depth);
+        let mut bitmap = vec![0u8; self.depth.div_ceil(8)];
+        let mut siblings = Vec::new();
+        for (j, sibling) in self.siblings.iter().enumerate() {
+            if *sibling != zero_hashes[j] {
+                bitmap[j / 8] |= 0x80 >> (j % 8);
+                siblings.extend_from_slice(sibling);
+            }
+        }
+        [
+            &[ENCODING_VERSION, self.flags(), (self.depth - 1) as u8][..],
+            &bitmap,
+            &siblings,
+        ]
+        .concat()
+    }
+
+    /// Decodes `encode` output for the same hasher. Fails with `UnsupportedVersion`
+    /// for another version, `InvalidFlags` for unknown flags, `InvalidBitmask` for
+    /// bitmap bits past the depth, and `InvalidProofLength` if the bytes are
+    /// shorter or longer than the bitmap says.
+    pub fn decode<H: SmtHasher>(bytes: &[u8]) -> Result<Self, SmtError> {
+        let Some(([version, flags, depth], rest)) = bytes.split_first_chunk::<3>() else {
+            return Err(SmtError::InvalidProofLength {
+                expected: 3,
+                actual: bytes.len(),
+            });
+        };
+        if *version != ENCODING_VERSION {
+            return Err(SmtError::UnsupportedVersion(*version));
+        }
+        let (flavor, hashed_key, bit_order) = Self::parse_flags(*flags)?;
+        let depth = usize::from(*depth) + 1;
+        let header_len = 3 + depth.div_ceil(8);
+        if bytes.len() < header_len {
+            return Err(SmtError::InvalidProofLength {
+                expected: header_len,
+                actual: bytes.len(),
+            });
+        }
+        let (bitmap, mut siblings) = rest.split_at(depth.div_ceil(8));
+        let is_set = |j: usize| bitmap[j / 8] & (0x80 >> (j % 8)) != 0;
+        if (depth..bitmap.len() * 8).any(is_set) {
+            return Err(SmtError::InvalidBitmask);
+        }
+        let present = (0..depth).filter(|&j| is_set(j)).count();
+        if siblings.len() != present * 32 {
+            return Err(SmtError::InvalidProofLength {
+                expected: header_len + present * 32,
+                actual: bytes.len(),
+            });
+        }
+
+        let zero_hashes = H::zero_hashes(depth);
+        let siblings = (0..depth)
+            .map(|j| {
+                if !is_set(j) {
+                    return zero_hashes[j];
+                }
+                let (sibling, rest) = siblings.split_first_chunk::<32>().unwrap();
+                siblings = rest;
+                *sibling
+            })
+            .collect();
+        Ok(Self {
+            depth,
+            flavor,
+            hashed_key,
+            bit_order,
+            siblings,
+        })
+    }
+
+    /// Counts the siblings that differ from `H`'s empty subtree at their level.
+    /// Only those cost 32 bytes in `encode`, whose length this is without encoding.
+    pub fn stats<H: SmtHasher>(&self) -> ProofStats {
+        let zero_hashes = H::zero_hashes(self.depth);
+        let non_default_siblings = self
+            .siblings
+            .iter()
+            .zip(&zero_hashes)
+            .filter(|(sibling, zero_hash)| sibling != zero_hash)
+            .count();
+        ProofStats {
+            depth: self.depth,
+            non_default_siblings,
+            byte_len_compressed: 3 + self.depth.div_ceil(8) + non_default_siblings * 32,
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        let flavor = match self.flavor {
+            SmtFlavor::Plain => 0,
+            SmtFlavor::PathCommitting => PATH_COMMITTING_FLAG,
+            SmtFlavor::LengthPrefixed => LENGTH_PREFIXED_FLAG,
+        };
+        let hashed_key = if self.hashed_key { HASHED_KEY_FLAG } else { 0 };
+        let bit_order = match self.bit_order {
+            BitOrder::MsbFirst => 0,
+            BitOrder::LsbFirst => LSB_FIRST_FLAG,
+        };
+        flavor | hashed_key | bit_order
+    }
+
+    /// The flavor, `hashed_key` and bit order of a flags byte. Fails with
+    /// `InvalidFlags` for unknown bits or more than one flavor.
+    fn parse_flags(flags: u8) -> Result<(SmtFlavor, bool, BitOrder), SmtError> {
+        let bit_order = if flags & LSB_FIRST_FLAG != 0 {
+            BitOrder::LsbFirst
+        } else {
+            BitOrder::MsbFirst
+        };
+        let flavor = match flags & !(HASHED_KEY_FLAG | LSB_FIRST_FLAG) {
+            0 => SmtFlavor::Plain,
+            PATH_COMMITTING_FLAG => SmtFlavor::PathCommitting,
+            LENGTH_PREFIXED_FLAG => SmtFlavor::LengthPrefixed,
+            _ => return Err(SmtError::InvalidFlags(flags)),
+        };
+        Ok((flavor, flags & HASHED_KEY_FLAG != 0, bit_order))
+    }
+}
+
+/// Bytes of a hex string with an optional `0x` prefix, in either case.
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>, ProofHexError> {
+    let digits = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    if let Some((index, character)) = digits
+        .char_indices()
+        .find(|(_, character)| !character.is_ascii_hexdigit())
+    {
+        return Err(ProofHexError::InvalidCharacter { index, character });
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(ProofHexError::OddLength);
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// Why `SmtProof::from_hex` or `SmtVerifier::from_hex_root` rejected its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofHexError {
+    /// A character that isn't a hex digit, at a byte offset past any `0x` prefix.
+    InvalidCharacter {
+        index: usize,
+        character: char,
+    },
+    OddLength,
+    /// Hex of `actual` bytes for a value of `expected` bytes, such as a root.
+    WrongLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// Valid hex that doesn't decode as a proof, see `SmtProof::from_bytes`.
+    InvalidProof(SmtError),
+}
+
+impl fmt::Display for ProofHexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCharacter { index, character } => {
+                write!(f, "invalid hex character {character:?} at index {index}")
+            }
+            Self::OddLength => write!(f, "hex string has an odd number of digits"),
+            Self::WrongLength { expected, actual } => {
+                write!(f, "hex string holds {actual} bytes, expected {expected}")
+            }
+            Self::InvalidProof(err) => write!(f, "bytes don't encode a proof: {err}"),
+        }
+    }
+}
+
+impl core::error::Error for ProofHexError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::InvalidProof(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Why an operation on a tree or proof failed. Verification returns `Ok(false)` for a
+/// well-formed proof that doesn't match, and one of these for a proof that can't be
+/// checked at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmtError {
+    /// A proof with the wrong number of siblings, or an encoding of the wrong size.
+    InvalidProofLength { expected: usize, actual: usize },
+    /// A proof generated for a tree of another depth.
+    DepthMismatch { expected: usize, actual: usize },
+    /// A depth outside `1..=max`, the number of bits in a key.
+    InvalidDepth { depth: usize, max: usize },
+    /// A proof generated for a tree of another flavor.
+    FlavorMismatch {
+        expected: SmtFlavor,
+        actual: SmtFlavor,
+    },
+    /// A proof generated for a tree reading key bits in another order.
+    BitOrderMismatch {
+        expected: BitOrder,
+        actual: BitOrder,
+    },
+    /// A key with bits set past the first `depth`, i.e. longer than the tree's paths.
+    KeyLength { depth: usize },
+    /// A prefix of `len` bytes, too short to hold `bits` bits.
+    PrefixLength { bits: usize, len: usize },
+    /// A compressed proof whose bitmask has bits set past the tree depth.
+    InvalidBitmask,
+    /// Unknown bits in the flags byte of an encoded proof.
+    InvalidFlags(u8),
+    /// The backing store failed, with the underlying error's message.
+    StorageError(String),
+    /// Entry `index` of a sorted input doesn't come strictly after the one before it.
+    UnsortedKey { index: usize },
+    /// Entry `index` of an input repeats an earlier key with a different value.
+    ConflictingKey { index: usize },
+    /// Entry `index` of an input is a proof that doesn't lead to the expected root.
+    RootMismatch { index: usize },
+    /// A key outside those a partial tree was built for, see `Smt::from_proofs`.
+    NotCovered,
+    /// A checkpoint that was rolled back, discarded, or dropped with an outer one.
+    UnknownCheckpoint,
+    /// A key that is set in a tree that keeps only leaf hashes, not values.
+    ValueNotRetained,
+    /// A saved tree in a format version this build can't read.
+    UnsupportedVersion(u8),
+    /// A saved tree that is truncated or otherwise malformed, with what is wrong.
+    InvalidEncoding(String),
+}
+
+impl fmt::Display for SmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidProofLength { expected, actual } => {
+                write!(f, "proof has length {actual}, expected {expected}")
+            }
+            Self::DepthMismatch { expected, actual } => {
+                write!(f, "proof is for depth {actual}, expected {expected}")
+            }
+            Self::InvalidDepth { depth, max } => {
+                write!(f, "depth {depth} is not between 1 and {max}")
+            }
+            Self::FlavorMismatch { expected, actual } => {
+                write!(f, "proof is for flavor {actual:?}, expected {expected:?}")
+            }
+            Self::BitOrderMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "proof is for bit order {actual:?}, expected {expected:?}"
+                )
+            }
+            Self::KeyLength { depth } => {
+                write!(f, "key has bits set past the tree depth of {depth}")
+            }
+            Self::PrefixLength { bits, len } => {
+                write!(f, "prefix of {len} bytes is too short for {bits} bits")
+            }
+            Self::InvalidBitmask => write!(f, "bitmask has bits set past the tree depth"),
+            Self::InvalidFlags(flags) => write!(f, "unknown proof flags {flags:#04x}"),
+            Self::StorageError(message) => write!(f, "storage error: {message}"),
+            Self::UnsortedKey { index } => {
+                write!(f, "key {index} is not after the previous key")
+            }
+            Self::ConflictingKey { index } => {
+                write!(
+                    f,
+                    "key {index} repeats an earlier key with a different value"
+                )
+            }
+            Self::RootMismatch { index } => {
+                write!(f, "proof {index} does not lead to the expected root")
+            }
+            Self::NotCovered => write!(f, "key is not covered by the partial tree"),
+            Self::UnknownCheckpoint => write!(f, "unknown checkpoint"),
+            Self::ValueNotRetained => write!(f, "value is not retained by this tree"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {version}")
+            }
+            Self::InvalidEncoding(message) => write!(f, "invalid encoding: {message}"),
+        }
+    }
+}
+
+impl core::error::Error for SmtError {}
+
+/// Bit `i` of `bits`, counting from the most significant bit of `bits[0]`.
+pub(super) fn bit<const N: usize>(bits: &[u8; N], i: usize) -> bool {
+    bits[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+/// Whether `a == b`, looking at every byte whatever the first difference, so the
+/// time a verifier takes doesn't tell how much of a forged root matched.
+pub(super) fn ct_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter()
+        .zip(b)
+        .fold(0u8, |diff, (x, y)| core::hint::black_box(diff | (x ^ y)))
+        == 0
+}
+
+/// Keeps the first `len` bits and clears the rest.
+pub(super) fn truncate<const N: usize>(bits: &[u8; N], len: usize) -> [u8; N] {
+    let mut out = [0u8; N];
+    out[..len / 8].copy_from_slice(&bits[..len / 8]);
+    if !len.is_multiple_of(8) {
+        out[len / 8] = bits[len / 8] & !(0xff >> (len % 8));
+    }
+    out
+}
+
+/// Checks that a proof's depth is in range and matches its sibling count.
+pub(super) fn check_proof<const N: usize>(proof: &SmtProof) -> Result<(), SmtError> {
+    check_depth::<N>(proof.depth)?;
+    if proof.siblings.len() != proof.depth {
+        return Err(SmtError::InvalidProofLength {
+            expected: proof.depth,
+            actual: proof.siblings.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidDepth` unless `depth` is between 1 and the `8 * N` bits of a key.
+pub(super) fn check_depth<const N: usize>(depth: usize) -> Result<(), SmtError> {
+    if !(1..=N * 8).contains(&depth) {
+        return Err(SmtError::InvalidDepth { depth, max: N * 8 });
+    }
+    Ok(())
+}
+
+/// Fails with `KeyLength` if `key` has bits set past `depth`.
+pub(super) fn check_key<const N: usize>(key: &[u8; N], depth: usize) -> Result<(), SmtError> {
+    if truncate(key, depth) != *key {
+        return Err(SmtError::KeyLength { depth });
+    }
+    Ok(())
+}
+
+/// Leaf for `value` at `path` (the key truncated to the depth).
+pub(super) fn leaf_hash<const N: usize, H: SmtHasher>(
+    flavor: SmtFlavor,
+    path: &[u8; N],
+    value: &[u8],
+) -> [u8; 32] {
+    match flavor {
+        SmtFlavor::Plain => H::hash_leaf(value),
+        SmtFlavor::PathCommitting => H::hash_parts(&[&[0x00], path, &H::hash(value)]),
+        SmtFlavor::LengthPrefixed => {
+            H::hash_leaf_parts(&[&(value.len() as u64).to_le_bytes(), value])
+        }
+    }
+}
+
+/// Root obtained by hashing `value`'s leaf up through the siblings in `proof`.
+/// The sibling count must match the proof's depth.
+pub(super) fn fold_path<const N: usize, H: SmtHasher>(
+    path: &[u8; N],
+    value: Option<&[u8]>,
+    proof: &SmtProof,
+) -> [u8; 32] {
+    let leaf = value.map_or(DEFAULT_LEAF, |value| {
+        leaf_hash::<N, H>(proof.flavor, path, value)
+    });
+    fold_node::<N, H>(path, leaf, &proof.siblings)
+}
+
+/// Root obtained by hashing `node`, at level `siblings.len()` on `path`, up
+/// through `siblings`, ordered from that level up.
+pub(super) fn fold_node<const N: usize, H: SmtHasher>(
+    path: &[u8; N],
+    node: [u8; 32],
+    siblings: &[[u8; 32]],
+) -> [u8; 32] {
+    fold_siblings::<N, H, _>(path, siblings.len(), node, &mut siblings.iter())
+        .expect("one sibling per level")
+}
+
+/// Root obtained by hashing `node`, at level `level` on `path`, up through the
+/// next `level` siblings, ordered from that level up, or `None` if they run out.
+pub(super) fn fold_siblings<'a, const N: usize, H: SmtHasher, I>(
+    path: &[u8; N],
+    level: usize,
+    mut current_node: [u8; 32],
+    siblings: &mut I,
+) -> Option<[u8; 32]>
+where
+    I: Iterator<Item = &'a [u8; 32]>,
+{
+    for i in (0..level).rev() {
+        let sibling = siblings.next()?;
+        current_node = if !bit(path, i) {
+            H::hash_pair(&current_node, sibling)
+        } else {
+            H::hash_pair(sibling, &current_node)
+        };
+    }
+    Some(current_node)
+}
+
+/// These run without `std` too: `cargo test --no-default-features`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SmtVerifier;
+
+    /// Root of the depth-4 tree holding 0101 = "a", 0110 = "c" and 1100 = "b", and the
+    /// proof for 0101 in `SmtProof::to_hex` form, siblings from the leaf up.
+    const ROOT: &str = "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d";
+    const PROOF: &str = concat!(
+        "0x000400",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a",
+        "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+        "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+    );
+
+    fn root() -> [u8; 32] {
+        let mut root = [0; 32];
+        for (i, byte) in root.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&ROOT[2 * i..2 * i + 2], 16).unwrap();
+        }
+        root
+    }
+
+    fn key(path: u8) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[0] = path;
+        key
+    }
+
+    /// Verifies against `root` as a depth-4 `Plain` tree hashed with `H`.
+    fn verify<H: SmtHasher>(
+        root: &[u8; 32],
+        key: &[u8; 16],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        SmtVerifier::<16, H>::new(*root, 4, SmtFlavor::Plain).verify(key, value, proof)
+    }
+
+    #[test]
+    fn test_verify_fixed_vector() {
+        let proof = SmtProof::from_hex(PROOF).unwrap();
+        let root = root();
+        assert_eq!(
+            verify::<Sha256Hasher>(&root, &key(0x50), Some(b"a"), &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            verify::<Sha256Hasher>(&root, &key(0x50), Some(b"b"), &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verify::<Sha256Hasher>(&root, &key(0x50), None, &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verify::<Keccak256Hasher>(&root, &key(0x50), Some(b"a"), &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verify::<Sha256Hasher>(&root, &key(0x58), Some(b"a"), &proof),
+            Err(SmtError::KeyLength { depth: 4 })
+        );
+    }
+
+    #[test]
+    fn test_ct_eq_matches_eq() {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+        for _ in 0..64 {
+            let a: [u8; 32] = core::array::from_fn(|_| next());
+            let b: [u8; 32] = core::array::from_fn(|_| next());
+            assert_eq!(ct_eq(&a, &b), a == b);
+            assert!(ct_eq(&a, &a));
+            // A difference in any single bit, first byte to last.
+            for i in 0..256 {
+                let mut c = a;
+                c[i / 8] ^= 0x80 >> (i % 8);
+                assert!(!ct_eq(&a, &c));
+            }
+        }
+        assert!(ct_eq(&[0; 32], &[0; 32]));
+    }
+
+    #[test]
+    fn test_verify_compact_encoding() {
+        let proof = SmtProof::from_hex(PROOF).unwrap();
+        let encoded = proof.encode::<Sha256Hasher>();
+        assert_eq!(encoded.len(), 4 + 2 * 32);
+        let decoded = SmtProof::decode::<Sha256Hasher>(&encoded).unwrap();
+        assert_eq!(
+            verify::<Sha256Hasher>(&root(), &key(0x50), Some(b"a"), &decoded),
+            Ok(true)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use super::{bit, with_bit, Sha256Hasher, Smt, SmtHasher, SmtProof};
+use std::sync::{Mutex, MutexGuard};
+
+/// A full-depth tree split by the first `shard_bits` bits of the key into
+/// `2^shard_bits` trees, each behind its own lock, so writers to different
+/// shards don't wait for each other. Shard `i` holds the keys starting with the
+/// bits of `i`, so its subtree at level `shard_bits` is exactly the global
+/// tree's, and the global root is those subtree roots hashed up `shard_bits`
+/// levels. Roots and proofs are the same as for a single `Smt` holding every key.
+#[derive(Debug)]
+pub struct ShardedSmt<const N: usize, H = Sha256Hasher> {
+    shard_bits: usize,
+    shards: Vec<Mutex<Smt<N, H>>>,
+}
+
+pub type ShardedSparseMerkleTree<H = Sha256Hasher> = ShardedSmt<16, H>;
+
+impl<const N: usize> ShardedSmt<N> {
+    /// Creates an empty tree with `2^shard_bits` shards. Panics if `shard_bits` is
+    /// more than 16 or than a key holds.
+    pub fn new(shard_bits: usize) -> Self {
+        Self::with_hasher(shard_bits)
+    }
+}
+
+impl<const N: usize, H: SmtHasher> ShardedSmt<N, H> {
+    /// Like `new`, hashed with `H`.
+    pub fn with_hasher(shard_bits: usize) -> Self {
+        assert!(
+            shard_bits <= 16 && shard_bits <= N * 8,
+            "{shard_bits} shard bits is too many"
+        );
+        let shards = (0..1 << shard_bits)
+            .map(|_| Mutex::new(Smt::with_hasher()))
+            .collect();
+        Self { shard_bits, shards }
+    }
+
+    pub fn shard_bits(&self) -> usize {
+        self.shard_bits
+    }
+
+    /// Inserts or overwrites `key`, locking only its shard. Unlike `Smt::insert`
+    /// this doesn't return the root, which would take every shard's lock.
+    pub fn insert(&self, key: &[u8; N], value: &[u8]) {
+        self.shard(key).insert(key, value);
+    }
+
+    pub fn delete(&self, key: &[u8; N]) {
+        self.shard(key).delete(key);
+    }
+
+    pub fn get(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.shard(key).get(key)
+    }
+
+    /// The root of the whole tree, hashing the shards' subtree roots up to it.
+    /// Every shard is locked meanwhile, so the root is of one consistent state.
+    pub fn root(&self) -> [u8; 32] {
+        let shards = self.lock_all();
+        self.top_levels(&shards)[0][0]
+    }
+
+    /// A proof with a sibling per level, as from `Smt::generate_proof`, verified
+    /// against `root`. The siblings above level `shard_bits` come from the other
+    /// shards, so every shard is locked meanwhile.
+    pub fn generate_proof(&self, key: &[u8; N]) -> SmtProof {
+        let shards = self.lock_all();
+        let index = self.shard_index(key);
+        let mut proof = shards[index].generate_proof(key);
+        let depth = proof.depth;
+        for (level, nodes) in self.top_levels(&shards).iter().enumerate().skip(1) {
+            let prefix = index >> (self.shard_bits - level);
+            proof.siblings[depth - level] = nodes[prefix ^ 1];
+        }
+        proof
+    }
+
+    /// Shard of `key`: its first `shard_bits` bits as an integer.
+    fn shard_index(&self, key: &[u8; N]) -> usize {
+        (0..self.shard_bits).fold(0, |index, i| index << 1 | usize::from(bit(key, i)))
+    }
+
+    fn shard(&self, key: &[u8; N]) -> MutexGuard<'_, Smt<N, H>> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    /// Locks the shards in index order, so callers locking them all never deadlock.
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Smt<N, H>>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect()
+    }
+
+    /// Nodes of the top `shard_bits` levels, indexed by level and then by prefix
+    /// as an integer, from each shard's subtree root upwards.
+    fn top_levels(&self, shards: &[MutexGuard<'_, Smt<N, H>>]) -> Vec<Vec<[u8; 32]>> {
+        let subtree_roots: Vec<_> = shards
+            .iter()
+            .enumerate()
+            .map(|(index, shard)| {
+                let prefix = (0..self.shard_bits).fold([0; N], |prefix, i| {
+                    with_bit(&prefix, i, index >> (self.shard_bits - 1 - i) & 1 == 1)
+                });
+                shard.node(self.shard_bits, prefix)
+            })
+            .collect();
+        let mut levels = vec![subtree_roots];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|pair| H::hash_pair(&pair[0], &pair[1]))
+                .collect();
+            levels.insert(0, parents);
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+    use std::thread;
+
+    fn key(i: u32) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..4].copy_from_slice(&i.wrapping_mul(0x9e37_79b9).to_be_bytes());
+        key
+    }
+
+    #[test]
+    fn test_concurrent_writes_match_single_tree() {
+        let sharded = ShardedSparseMerkleTree::new(4);
+        thread::scope(|scope| {
+            for writer in 0..8u32 {
+                let sharded = &sharded;
+                scope.spawn(move || {
+                    for i in (writer..2000).step_by(8) {
+                        sharded.insert(&key(i), &i.to_be_bytes());
+                    }
+                    for i in (writer..2000).step_by(80) {
+                        sharded.delete(&key(i));
+                    }
+                });
+            }
+        });
+
+        let mut reference = SparseMerkleTree::new();
+        for i in 0..2000u32 {
+            reference.insert(&key(i), &i.to_be_bytes());
+        }
+        for i in (0..2000u32).filter(|i| i % 80 < 8) {
+            reference.delete(&key(i));
+        }
+        let root = sharded.root();
+        assert_eq!(root, reference.root());
+
+        for i in [0, 9, 1234, 5000] {
+            let proof = sharded.generate_proof(&key(i));
+            assert_eq!(proof.siblings.len(), 128);
+            assert_eq!(proof, reference.generate_proof(&key(i)));
+            let value = sharded.get(&key(i));
+            assert_eq!(value, reference.get(&key(i)));
+            assert_eq!(
+                reference
+                    .verifier()
+                    .verify(&key(i), value.as_deref(), &proof),
+                Ok(true)
+            );
+        }
+    }
+
+    #[test]
+    fn test_shard_counts() {
+        let key = [0xa5; 16];
+        for shard_bits in [0, 1, 3, 8] {
+            let sharded = ShardedSparseMerkleTree::new(shard_bits);
+            assert_eq!(sharded.root(), SparseMerkleTree::new().root());
+            sharded.insert(&key, b"value");
+            let mut reference = SparseMerkleTree::new();
+            assert_eq!(sharded.root(), reference.insert(&key, b"value"));
+            assert_eq!(sharded.generate_proof(&key), reference.generate_proof(&key));
+        }
+    }
+}
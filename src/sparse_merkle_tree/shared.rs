@@ -0,0 +1,96 @@
+use super::{Sha256Hasher, Smt, SmtHasher};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// A tree shared between threads: any number of readers work on immutable
+/// snapshots while one writer at a time applies changes to its own copy and then
+/// publishes it. Readers only lock to clone the current snapshot's `Arc`, so a
+/// slow proof never holds up the writer, and a proof generated from a snapshot
+/// always verifies against that snapshot's root.
+///
+/// Publishing is an O(1) `Smt::snapshot`, but the writer's next change then copies
+/// the node and value maps if a reader still holds the previous snapshot, so
+/// batching writes into few `write` calls keeps the copies down.
+#[derive(Debug)]
+pub struct SharedSmt<const N: usize, H = Sha256Hasher> {
+    current: RwLock<Arc<Smt<N, H>>>,
+    writer: Mutex<Smt<N, H>>,
+}
+
+pub type SharedSparseMerkleTree<H = Sha256Hasher> = SharedSmt<16, H>;
+
+impl<const N: usize, H: SmtHasher> SharedSmt<N, H> {
+    pub fn new(tree: Smt<N, H>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(tree.snapshot())),
+            writer: Mutex::new(tree),
+        }
+    }
+
+    /// The latest published state. It never changes, so everything read from it,
+    /// e.g. a root and proofs against it, is consistent.
+    pub fn snapshot(&self) -> Arc<Smt<N, H>> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Applies `f` to the tree and publishes the result, returning what `f` returns.
+    /// Concurrent writers wait for each other; readers keep seeing the previous state
+    /// until `f` has returned.
+    pub fn write<R>(&self, f: impl FnOnce(&mut Smt<N, H>) -> R) -> R {
+        let mut tree = self.writer.lock().unwrap();
+        let result = f(&mut tree);
+        *self.current.write().unwrap() = Arc::new(tree.snapshot());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileStore, Keccak256Hasher, SparseMerkleTree};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_trees_are_send_and_sync() {
+        assert_send_sync::<SparseMerkleTree>();
+        assert_send_sync::<SparseMerkleTree<Keccak256Hasher>>();
+        assert_send_sync::<SparseMerkleTree<Sha256Hasher, FileStore>>();
+        assert_send_sync::<SharedSparseMerkleTree>();
+    }
+
+    #[test]
+    fn test_proofs_during_writes() {
+        let shared = SharedSparseMerkleTree::new(SparseMerkleTree::new());
+        thread::scope(|scope| {
+            for reader in 0..4u8 {
+                let shared = &shared;
+                scope.spawn(move || {
+                    for i in 0..200u8 {
+                        let tree = shared.snapshot();
+                        let key = [i % 32; 16];
+                        let value = tree.get(&key);
+                        let proof = tree.generate_proof(&key);
+                        assert_eq!(
+                            tree.verifier().verify(&key, value.as_deref(), &proof),
+                            Ok(true),
+                            "reader {reader}, key {i}"
+                        );
+                    }
+                });
+            }
+            for round in 0..50u8 {
+                let entries: Vec<_> = (0..32u8).map(|i| ([i; 16], vec![round, i])).collect();
+                shared.write(|tree| {
+                    tree.insert_batch(&entries);
+                    tree.delete(&[round % 32; 16]);
+                });
+            }
+        });
+
+        let tree = shared.snapshot();
+        assert_eq!(tree.get(&[3u8; 16]), Some(vec![49, 3]));
+        assert_eq!(tree.get(&[49 % 32; 16]), None);
+        assert_eq!(shared.write(|tree| tree.len()), 31);
+    }
+}
@@ -0,0 +1,150 @@
+use super::{InternedStore, MemoryStore, Smt, SmtHasher};
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+/// Size of a tree's storage, see `Smt::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeStats {
+    pub depth: usize,
+    /// Keys set, i.e. non-default leaves.
+    pub leaves: usize,
+    /// Non-default nodes stored above the leaves, the root included.
+    pub internal_nodes: usize,
+    /// Distinct hashes among the stored nodes. Far below `leaves + internal_nodes`,
+    /// the tree has many identical subtrees and `InternedStore` would save memory.
+    pub distinct_hashes: usize,
+    /// Estimate of the heap bytes held by the node and value maps and the values.
+    pub heap_bytes: usize,
+}
+
+impl<const N: usize, H: SmtHasher> Smt<N, H, MemoryStore<N>> {
+    /// Counts of stored leaves and internal nodes, with a rough heap estimate. The
+    /// maps are counted at their capacity, with a control byte per slot as in
+    /// std's hash maps, so the estimate stays near even after deletions shrink them.
+    pub fn stats(&self) -> TreeStats {
+        let leaves = self
+            .store
+            .nodes
+            .keys()
+            .filter(|(level, _)| *level == self.depth)
+            .count();
+        let values: usize = self.store.values.values().map(Vec::capacity).sum();
+        TreeStats {
+            depth: self.depth,
+            leaves,
+            internal_nodes: self.store.nodes.len() - leaves,
+            distinct_hashes: self.store.nodes.values().collect::<HashSet<_>>().len(),
+            heap_bytes: map_bytes(&self.store.nodes) + map_bytes(&self.store.values) + values,
+        }
+    }
+}
+
+impl<const N: usize, H: SmtHasher> Smt<N, H, InternedStore<N>> {
+    /// As for a `MemoryStore` tree, with each distinct hash counted once in the
+    /// heap estimate, plus the ids referring to it.
+    pub fn stats(&self) -> TreeStats {
+        let store = &self.store;
+        let leaves = store
+            .nodes
+            .keys()
+            .filter(|(level, _)| *level == self.depth)
+            .count();
+        let values: usize = store.values.values().map(Vec::capacity).sum();
+        TreeStats {
+            depth: self.depth,
+            leaves,
+            internal_nodes: store.nodes.len() - leaves,
+            distinct_hashes: store.ids.len(),
+            heap_bytes: map_bytes(&store.nodes)
+                + map_bytes(&store.ids)
+                + store.hashes.capacity() * size_of::<([u8; 32], u32)>()
+                + store.free.capacity() * size_of::<u32>()
+                + map_bytes(&store.values)
+                + values,
+        }
+    }
+}
+
+fn map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InternedStore, Sha256Hasher, SparseMerkleTree};
+
+    /// Key with `path` as its first 4 bits, for a depth-4 tree.
+    fn key(path: u8) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[0] = path << 4;
+        key
+    }
+
+    #[test]
+    fn test_stats_count_nodes() {
+        let mut tree = SparseMerkleTree::with_depth(4);
+        let empty = tree.stats();
+        assert_eq!((empty.leaves, empty.internal_nodes, empty.depth), (0, 0, 4));
+
+        tree.insert(&key(0b0101), b"a");
+        tree.insert(&key(0b1100), b"b");
+        tree.insert(&key(0b0110), b"c");
+        // The root, 0 and 1, 01 and 11, then 010, 011 and 110.
+        let stats = tree.stats();
+        assert_eq!((stats.leaves, stats.internal_nodes), (3, 8));
+        assert!(stats.heap_bytes > empty.heap_bytes);
+
+        // Only the nodes on b's own branch go.
+        tree.delete(&key(0b1100));
+        let stats = tree.stats();
+        assert_eq!((stats.leaves, stats.internal_nodes), (2, 5));
+        // Overwriting adds nothing.
+        tree.insert(&key(0b0101), b"A");
+        assert_eq!(tree.stats().internal_nodes, 5);
+        tree.delete(&key(0b0101));
+        tree.delete(&key(0b0110));
+        let stats = tree.stats();
+        assert_eq!((stats.leaves, stats.internal_nodes), (0, 0));
+
+        let mut full = SparseMerkleTree::new();
+        full.insert(&[7; 16], b"value");
+        let stats = full.stats();
+        assert_eq!((stats.leaves, stats.internal_nodes), (1, 128));
+        assert!(stats.heap_bytes >= 129 * (32 + 32 + 8) + 5);
+    }
+
+    #[test]
+    fn test_interning_identical_subtrees() {
+        let mut plain = SparseMerkleTree::new();
+        let mut interned =
+            SparseMerkleTree::<Sha256Hasher, _>::with_store(InternedStore::default());
+        // Each key is alone in its subtree under its first byte, with the same value,
+        // so the 256 subtrees 120 levels tall below byte 1 are all identical.
+        for i in 0..=255u8 {
+            let mut key = [0; 16];
+            key[0] = i;
+            plain.insert(&key, b"same");
+            interned.insert(&key, b"same");
+        }
+        assert_eq!(interned.root(), plain.root());
+        let key = [7; 16];
+        assert_eq!(interned.generate_proof(&key), plain.generate_proof(&key));
+
+        let (plain, interned) = (plain.stats(), interned.stats());
+        let nodes = 256 * 121 + 255;
+        assert_eq!(plain.leaves + plain.internal_nodes, nodes);
+        // Above the subtrees, both children of each node are equal too, so every level
+        // holds a single hash.
+        assert_eq!(plain.distinct_hashes, 129);
+        assert_eq!(
+            (
+                interned.leaves,
+                interned.internal_nodes,
+                interned.distinct_hashes
+            ),
+            (plain.leaves, plain.internal_nodes, plain.distinct_hashes)
+        );
+        // The node map holds 4-byte ids instead of 32-byte hashes.
+        assert!(interned.heap_bytes < plain.heap_bytes * 2 / 3);
+    }
+}
@@ -0,0 +1,378 @@
+use super::{NodePosition, SmtError};
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Backing storage for a tree's non-default nodes and leaf values. Nodes are
+/// addressed by level (0 is the root) and path prefix, with every bit from
+/// `level` onwards cleared. Anything not in the store is a default node.
+pub trait NodeStore<const N: usize> {
+    fn get(&self, level: usize, path: &[u8; N]) -> Option<[u8; 32]>;
+    fn put(&mut self, level: usize, path: [u8; N], hash: [u8; 32]);
+    fn delete(&mut self, level: usize, path: &[u8; N]);
+
+    fn get_value(&self, key: &[u8; N]) -> Option<Vec<u8>>;
+    /// Stores a leaf value, returning the one it replaced.
+    fn put_value(&mut self, key: [u8; N], value: Vec<u8>) -> Option<Vec<u8>>;
+    /// Removes a leaf value, returning it if it was present.
+    fn delete_value(&mut self, key: &[u8; N]) -> Option<Vec<u8>>;
+    /// Number of leaf values stored.
+    fn value_count(&self) -> usize;
+    /// Every stored node as `(level, path, hash)`, in no particular order.
+    fn all_nodes(&self) -> Vec<(usize, [u8; N], [u8; 32])>;
+    /// Every stored leaf value with its key, in no particular order.
+    fn all_values(&self) -> Vec<([u8; N], Vec<u8>)>;
+}
+
+/// The default store, holding everything in hash maps. The maps are shared
+/// between clones until one of them writes, so cloning is O(1).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryStore<const N: usize> {
+    pub(super) nodes: Arc<HashMap<NodePosition<N>, [u8; 32]>>,
+    pub(super) values: Arc<HashMap<[u8; N], Vec<u8>>>,
+}
+
+impl<const N: usize> NodeStore<N> for MemoryStore<N> {
+    fn get(&self, level: usize, path: &[u8; N]) -> Option<[u8; 32]> {
+        self.nodes.get(&(level, *path)).copied()
+    }
+
+    fn put(&mut self, level: usize, path: [u8; N], hash: [u8; 32]) {
+        Arc::make_mut(&mut self.nodes).insert((level, path), hash);
+    }
+
+    fn delete(&mut self, level: usize, path: &[u8; N]) {
+        if self.nodes.contains_key(&(level, *path)) {
+            Arc::make_mut(&mut self.nodes).remove(&(level, *path));
+        }
+    }
+
+    fn get_value(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        self.values.get(key).cloned()
+    }
+
+    fn put_value(&mut self, key: [u8; N], value: Vec<u8>) -> Option<Vec<u8>> {
+        Arc::make_mut(&mut self.values).insert(key, value)
+    }
+
+    fn delete_value(&mut self, key: &[u8; N]) -> Option<Vec<u8>> {
+        if !self.values.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(&mut self.values).remove(key)
+    }
+
+    fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    fn all_nodes(&self) -> Vec<(usize, [u8; N], [u8; 32])> {
+        self.nodes
+            .iter()
+            .map(|(&(level, path), &hash)| (level, path, hash))
+            .collect()
+    }
+
+    fn all_values(&self) -> Vec<([u8; N], Vec<u8>)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect()
+    }
+}
+
+/// A naive store writing one file per node and per value into a directory.
+/// It is meant as a reference for persistent backends. Opening the directory
+/// reports errors, while reads and writes after that panic on I/O errors.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Opens the store in `dir`, creating the directory if needed. Reopening a
+    /// directory picks up every node and value written to it before. Fails with
+    /// `StorageError` if the directory can't be created.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, SmtError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|err| {
+            SmtError::StorageError(format!("failed to create {}: {err}", dir.display()))
+        })?;
+        Ok(Self { dir })
+    }
+
+    fn node_file(&self, level: usize, path: &[u8]) -> PathBuf {
+        self.dir.join(format!("node-{level}-{}", hex(path)))
+    }
+
+    fn value_file(&self, key: &[u8]) -> PathBuf {
+        self.dir.join(format!("value-{}", hex(key)))
+    }
+
+    fn read(file: PathBuf) -> Option<Vec<u8>> {
+        match fs::read(file) {
+            Ok(bytes) => Some(bytes),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => panic!("failed to read from store: {err}"),
+        }
+    }
+
+    /// Names of the files in the store directory.
+    fn file_names(&self) -> Vec<String> {
+        let entries = fs::read_dir(&self.dir).expect("failed to list store directory");
+        entries
+            .map(|entry| entry.expect("failed to list store directory"))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn remove(file: PathBuf) {
+        match fs::remove_file(file) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                panic!("failed to delete from store: {err}")
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<const N: usize> NodeStore<N> for FileStore {
+    fn get(&self, level: usize, path: &[u8; N]) -> Option<[u8; 32]> {
+        Self::read(self.node_file(level, path))
+            .map(|hash| hash.try_into().expect("stored node hashes are 32 bytes"))
+    }
+
+    fn put(&mut self, level: usize, path: [u8; N], hash: [u8; 32]) {
+        fs::write(self.node_file(level, &path), hash).expect("failed to write to store");
+    }
+
+    fn delete(&mut self, level: usize, path: &[u8; N]) {
+        Self::remove(self.node_file(level, path));
+    }
+
+    fn get_value(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        Self::read(self.value_file(key))
+    }
+
+    fn put_value(&mut self, key: [u8; N], value: Vec<u8>) -> Option<Vec<u8>> {
+        let previous = NodeStore::<N>::get_value(self, &key);
+        fs::write(self.value_file(&key), value).expect("failed to write to store");
+        previous
+    }
+
+    fn delete_value(&mut self, key: &[u8; N]) -> Option<Vec<u8>> {
+        let previous = NodeStore::<N>::get_value(self, key);
+        Self::remove(self.value_file(key));
+        previous
+    }
+
+    fn value_count(&self) -> usize {
+        self.file_names()
+            .iter()
+            .filter(|name| name.starts_with("value-"))
+            .count()
+    }
+
+    fn all_nodes(&self) -> Vec<(usize, [u8; N], [u8; 32])> {
+        self.file_names()
+            .iter()
+            .filter_map(|name| {
+                let (level, path) = name.strip_prefix("node-")?.split_once('-')?;
+                Some((level.parse().ok()?, unhex(path)?))
+            })
+            .map(|(level, path)| {
+                (
+                    level,
+                    path,
+                    NodeStore::<N>::get(self, level, &path).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn all_values(&self) -> Vec<([u8; N], Vec<u8>)> {
+        self.file_names()
+            .iter()
+            .filter_map(|name| unhex(name.strip_prefix("value-")?))
+            .map(|key| (key, NodeStore::<N>::get_value(self, &key).unwrap()))
+            .collect()
+    }
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Inverse of `hex` for `N` bytes, `None` for anything else.
+fn unhex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// `MemoryStore` that counts node reads, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(super) struct CountingStore {
+    pub(super) inner: MemoryStore<16>,
+    pub(super) reads: Cell<usize>,
+}
+
+#[cfg(test)]
+impl NodeStore<16> for CountingStore {
+    fn get(&self, level: usize, path: &[u8; 16]) -> Option<[u8; 32]> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.get(level, path)
+    }
+
+    fn put(&mut self, level: usize, path: [u8; 16], hash: [u8; 32]) {
+        self.inner.put(level, path, hash)
+    }
+
+    fn delete(&mut self, level: usize, path: &[u8; 16]) {
+        self.inner.delete(level, path)
+    }
+
+    fn get_value(&self, key: &[u8; 16]) -> Option<Vec<u8>> {
+        self.inner.get_value(key)
+    }
+
+    fn put_value(&mut self, key: [u8; 16], value: Vec<u8>) -> Option<Vec<u8>> {
+        self.inner.put_value(key, value)
+    }
+
+    fn delete_value(&mut self, key: &[u8; 16]) -> Option<Vec<u8>> {
+        self.inner.delete_value(key)
+    }
+
+    fn value_count(&self) -> usize {
+        self.inner.value_count()
+    }
+
+    fn all_nodes(&self) -> Vec<(usize, [u8; 16], [u8; 32])> {
+        self.inner.all_nodes()
+    }
+
+    fn all_values(&self) -> Vec<([u8; 16], Vec<u8>)> {
+        self.inner.all_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntegrityError, SparseMerkleTree};
+    use std::env;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("lil-merkle-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_file_store_matches_memory_store() {
+        let dir = temp_dir("matches-memory");
+        let mut on_disk = SparseMerkleTree::with_store(FileStore::open(&dir).unwrap());
+        let mut in_memory = SparseMerkleTree::new();
+        for i in 0..10u8 {
+            on_disk.insert(&[i; 16], &[i]);
+            in_memory.insert(&[i; 16], &[i]);
+        }
+        on_disk.delete(&[4u8; 16]);
+        in_memory.delete(&[4u8; 16]);
+
+        assert_eq!(on_disk.root(), in_memory.root());
+        assert!(on_disk == in_memory);
+        assert_eq!(on_disk.entries(), in_memory.entries());
+        assert_eq!(
+            on_disk.generate_proof(&[7u8; 16]),
+            in_memory.generate_proof(&[7u8; 16])
+        );
+        assert_eq!(on_disk.get(&[7u8; 16]), Some(vec![7]));
+        assert_eq!(on_disk.get(&[4u8; 16]), None);
+        assert_eq!(on_disk.len(), 9);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_reopen_and_continue() {
+        let dir = temp_dir("reopen");
+        let mut expected = SparseMerkleTree::new();
+        {
+            let mut tree = SparseMerkleTree::with_store(FileStore::open(&dir).unwrap());
+            for i in 0..5u8 {
+                tree.insert(&[i; 16], &[i]);
+                expected.insert(&[i; 16], &[i]);
+            }
+        }
+
+        let mut tree = SparseMerkleTree::with_store(FileStore::open(&dir).unwrap());
+        assert_eq!(tree.root(), expected.root());
+        assert_eq!(tree.get(&[3u8; 16]), Some(vec![3]));
+
+        tree.insert(&[9u8; 16], b"more");
+        expected.insert(&[9u8; 16], b"more");
+        assert_eq!(tree.update(&[1u8; 16], b"changed"), Some(vec![1]));
+        expected.insert(&[1u8; 16], b"changed");
+        tree.delete(&[2u8; 16]);
+        expected.delete(&[2u8; 16]);
+
+        assert_eq!(tree.root(), expected.root());
+        let proof = tree.generate_proof(&[9u8; 16]);
+        assert_eq!(
+            tree.verify_proof(&[9u8; 16], Some(b"more"), &proof),
+            Ok(true)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_integrity() {
+        let dir = temp_dir("integrity");
+        let mut tree = SparseMerkleTree::with_store(FileStore::open(&dir).unwrap());
+        for i in 0..5u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+        assert_eq!(tree.verify_integrity(), Ok(()));
+
+        let file = dir.join(format!("node-1-{}", hex(&[0; 16])));
+        let mut node = fs::read(&file).unwrap();
+        let expected: [u8; 32] = node.clone().try_into().unwrap();
+        node[17] ^= 1;
+        fs::write(&file, &node).unwrap();
+        let tree = SparseMerkleTree::with_store(FileStore::open(&dir).unwrap());
+        assert_eq!(
+            tree.verify_integrity(),
+            Err(IntegrityError::NodeMismatch {
+                level: 1,
+                path: [0; 16],
+                stored: node.try_into().unwrap(),
+                computed: expected,
+            })
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_open_reports_errors() {
+        let file = temp_dir("not-a-dir");
+        fs::write(&file, b"").unwrap();
+
+        let err = FileStore::open(file.join("store")).unwrap_err();
+        assert!(matches!(err, SmtError::StorageError(_)));
+        assert!(err
+            .to_string()
+            .starts_with("storage error: failed to create"));
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use super::{NodeStore, Smt, SmtError, SmtHasher, SmtMultiProof, SmtVerifier};
+use std::collections::HashMap;
+
+/// Proof that a batch of writes takes one root to another: a multiproof for the
+/// keys the batch touches. Their siblings are the same before and after, since
+/// they are exactly the nodes the batch doesn't change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtTransitionProof {
+    pub multiproof: SmtMultiProof,
+}
+
+/// One write in a transition: `(key, old_value, new_value)`, with `None` for an
+/// absent key, so inserts, updates and deletes all look alike.
+pub type SmtOperation<'a, const N: usize> = ([u8; N], Option<&'a [u8]>, Option<&'a [u8]>);
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Transition proof for applying `ops` to the tree as it is now, so call it
+    /// before applying them. Only the keys are read; the values are checked by
+    /// `verify_transition`.
+    pub fn generate_transition_proof(&self, ops: &[SmtOperation<N>]) -> SmtTransitionProof {
+        let keys: Vec<[u8; N]> = ops.iter().map(|(key, _, _)| *key).collect();
+        SmtTransitionProof {
+            multiproof: self.generate_multiproof(&keys),
+        }
+    }
+
+    /// Verifies that applying `ops` in order to the tree at the root of `verifier`
+    /// yields `root_b`, a root of the same kind of tree. Each op's old value must be the key's value at that point in the
+    /// batch, so reordering ops on the same key fails. Returns `false` for an empty
+    /// batch, and fails like `verify_multiproof` for malformed proofs.
+    pub fn verify_transition(
+        verifier: &SmtVerifier<N, H>,
+        root_b: &[u8; 32],
+        ops: &[SmtOperation<N>],
+        proof: &SmtTransitionProof,
+    ) -> Result<bool, SmtError> {
+        let mut before: HashMap<[u8; N], Option<&[u8]>> = HashMap::new();
+        let mut after: HashMap<[u8; N], Option<&[u8]>> = HashMap::new();
+        for &(key, old_value, new_value) in ops {
+            let current = *after.entry(key).or_insert_with(|| {
+                before.insert(key, old_value);
+                old_value
+            });
+            if current != old_value {
+                return Ok(false);
+            }
+            after.insert(key, new_value);
+        }
+
+        let before: Vec<_> = before.into_iter().collect();
+        let after: Vec<_> = after.into_iter().collect();
+        Ok(
+            Self::verify_multiproof(verifier, &before, &proof.multiproof)?
+                && Self::verify_multiproof(
+                    &verifier.with_root(*root_b),
+                    &after,
+                    &proof.multiproof,
+                )?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..8u8 {
+            tree.insert(&[i; 16], &[i]);
+        }
+        tree
+    }
+
+    /// Applies `ops` to `tree`, returning the new root.
+    fn apply(tree: &mut SparseMerkleTree, ops: &[SmtOperation<16>]) -> [u8; 32] {
+        let mut root = tree.root();
+        for (key, _, new_value) in ops {
+            root = match new_value {
+                Some(value) => tree.insert(key, value),
+                None => tree.delete(key),
+            };
+        }
+        root
+    }
+
+    #[test]
+    fn test_transition_with_mixed_ops() {
+        let mut tree = setup_tree();
+        let before = tree.verifier();
+        let ops: [SmtOperation<16>; 5] = [
+            ([9; 16], None, Some(b"inserted")),
+            ([2; 16], Some(&[2]), Some(b"updated")),
+            ([5; 16], Some(&[5]), None),
+            ([9; 16], Some(b"inserted"), Some(b"again")),
+            ([12; 16], None, None),
+        ];
+        let proof = tree.generate_transition_proof(&ops);
+        let root_b = apply(&mut tree, &ops);
+
+        assert_eq!(
+            <SparseMerkleTree>::verify_transition(&before, &root_b, &ops, &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            <SparseMerkleTree>::verify_transition(
+                &before.with_root(root_b),
+                &before.root(),
+                &ops,
+                &proof
+            ),
+            Ok(false)
+        );
+
+        // Ops on different keys commute, but not those on the same key.
+        let mut commuted = ops;
+        commuted.swap(1, 2);
+        assert_eq!(
+            <SparseMerkleTree>::verify_transition(&before, &root_b, &commuted, &proof),
+            Ok(true)
+        );
+        let mut reordered = ops;
+        reordered.swap(0, 3);
+        assert_eq!(
+            <SparseMerkleTree>::verify_transition(&before, &root_b, &reordered, &proof),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_transition_rejects_wrong_claims() {
+        let mut tree = setup_tree();
+        let before = tree.verifier();
+        let ops: [SmtOperation<16>; 2] = [
+            ([1; 16], Some(&[1]), Some(b"one")),
+            ([3; 16], Some(&[3]), None),
+        ];
+        let proof = tree.generate_transition_proof(&ops);
+        let root_b = apply(&mut tree, &ops);
+        let verify = |ops: &[SmtOperation<16>]| {
+            <SparseMerkleTree>::verify_transition(&before, &root_b, ops, &proof)
+        };
+        assert_eq!(verify(&ops), Ok(true));
+
+        // A wrong old value, a wrong new value, or a missing op.
+        assert_eq!(verify(&[ops[0], ([3; 16], Some(&[4]), None)]), Ok(false));
+        assert_eq!(
+            verify(&[ops[0], ([3; 16], Some(&[3]), Some(&[3]))]),
+            Ok(false)
+        );
+        assert_eq!(
+            verify(&ops[..1]),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 248
+            })
+        );
+        assert_eq!(verify(&[]), Ok(false));
+    }
+}
@@ -0,0 +1,278 @@
+use super::{bit, with_bit, NodeStore, Smt, SmtHasher};
+use std::sync::Arc;
+
+/// A store holding nodes and values in binary tries of `Arc`ed nodes, one trie
+/// level per path bit. A write copies only the trie nodes on the way down to the
+/// entry, so clones share everything neither of them has written since, and
+/// cloning is O(1). This is what `Smt::inserted` builds on: a chain of trees
+/// each one insert apart costs memory in proportion to the inserts, not to the
+/// trees' sizes. Reads walk the trie, so they're slower than with `MemoryStore`.
+#[derive(Debug, Clone, Default)]
+pub struct TrieStore<const N: usize> {
+    nodes: Option<Arc<TrieNode<[u8; 32]>>>,
+    values: Option<Arc<TrieNode<Vec<u8>>>>,
+    value_count: usize,
+}
+
+#[derive(Debug, Clone)]
+struct TrieNode<T> {
+    entry: Option<T>,
+    children: [Option<Arc<TrieNode<T>>>; 2],
+}
+
+impl<const N: usize> NodeStore<N> for TrieStore<N> {
+    fn get(&self, level: usize, path: &[u8; N]) -> Option<[u8; 32]> {
+        get(&self.nodes, path, level).copied()
+    }
+
+    fn put(&mut self, level: usize, path: [u8; N], hash: [u8; 32]) {
+        insert(&mut self.nodes, &path, level, hash);
+    }
+
+    fn delete(&mut self, level: usize, path: &[u8; N]) {
+        remove(&mut self.nodes, path, level);
+    }
+
+    fn get_value(&self, key: &[u8; N]) -> Option<Vec<u8>> {
+        get(&self.values, key, N * 8).cloned()
+    }
+
+    fn put_value(&mut self, key: [u8; N], value: Vec<u8>) -> Option<Vec<u8>> {
+        let previous = insert(&mut self.values, &key, N * 8, value);
+        if previous.is_none() {
+            self.value_count += 1;
+        }
+        previous
+    }
+
+    fn delete_value(&mut self, key: &[u8; N]) -> Option<Vec<u8>> {
+        let previous = remove(&mut self.values, key, N * 8);
+        if previous.is_some() {
+            self.value_count -= 1;
+        }
+        previous
+    }
+
+    fn value_count(&self) -> usize {
+        self.value_count
+    }
+
+    fn all_nodes(&self) -> Vec<(usize, [u8; N], [u8; 32])> {
+        let mut nodes = Vec::new();
+        walk(&self.nodes, 0, [0; N], &mut |level, path, hash| {
+            nodes.push((level, path, *hash))
+        });
+        nodes
+    }
+
+    fn all_values(&self) -> Vec<([u8; N], Vec<u8>)> {
+        let mut values = Vec::new();
+        walk(&self.values, 0, [0; N], &mut |_, key, value: &Vec<u8>| {
+            values.push((key, value.clone()))
+        });
+        values
+    }
+}
+
+/// Entry at the end of the first `len` bits of `path`.
+fn get<'a, const N: usize, T>(
+    root: &'a Option<Arc<TrieNode<T>>>,
+    path: &[u8; N],
+    len: usize,
+) -> Option<&'a T> {
+    let mut node = root.as_deref()?;
+    for i in 0..len {
+        node = node.children[usize::from(bit(path, i))].as_deref()?;
+    }
+    node.entry.as_ref()
+}
+
+/// Sets the entry at the end of the first `len` bits of `path`, copying every
+/// shared trie node on the way. Returns the entry it replaced.
+fn insert<const N: usize, T: Clone>(
+    root: &mut Option<Arc<TrieNode<T>>>,
+    path: &[u8; N],
+    len: usize,
+    entry: T,
+) -> Option<T> {
+    let mut node = root;
+    for i in 0..len {
+        node = &mut unshared(node).children[usize::from(bit(path, i))];
+    }
+    unshared(node).entry.replace(entry)
+}
+
+/// The trie node in `node`, created if missing and copied if shared.
+fn unshared<T: Clone>(node: &mut Option<Arc<TrieNode<T>>>) -> &mut TrieNode<T> {
+    Arc::make_mut(node.get_or_insert_with(|| {
+        Arc::new(TrieNode {
+            entry: None,
+            children: [None, None],
+        })
+    }))
+}
+
+/// Clears the entry at the end of the first `len` bits of `path`, dropping trie
+/// nodes left empty. Returns the entry it removed.
+fn remove<const N: usize, T: Clone>(
+    root: &mut Option<Arc<TrieNode<T>>>,
+    path: &[u8; N],
+    len: usize,
+) -> Option<T> {
+    // Checked first so a miss doesn't copy the path.
+    get(root, path, len)?;
+    take(root, path, len, 0)
+}
+
+/// `remove` below the trie node at bit `i`.
+fn take<const N: usize, T: Clone>(
+    node: &mut Option<Arc<TrieNode<T>>>,
+    path: &[u8; N],
+    len: usize,
+    i: usize,
+) -> Option<T> {
+    let trie_node = Arc::make_mut(node.as_mut()?);
+    let previous = if i == len {
+        trie_node.entry.take()
+    } else {
+        take(
+            &mut trie_node.children[usize::from(bit(path, i))],
+            path,
+            len,
+            i + 1,
+        )
+    };
+    if trie_node.entry.is_none() && trie_node.children.iter().all(Option::is_none) {
+        *node = None;
+    }
+    previous
+}
+
+/// Calls `f` with the level, path and entry of every entry below `node`, which
+/// sits at `level` on `path`.
+fn walk<const N: usize, T>(
+    node: &Option<Arc<TrieNode<T>>>,
+    level: usize,
+    path: [u8; N],
+    f: &mut impl FnMut(usize, [u8; N], &T),
+) {
+    let Some(node) = node else {
+        return;
+    };
+    if let Some(entry) = &node.entry {
+        f(level, path, entry);
+    }
+    for (side, child) in node.children.iter().enumerate() {
+        if child.is_some() {
+            walk(child, level + 1, with_bit(&path, level, side == 1), f);
+        }
+    }
+}
+
+impl<const N: usize, H: SmtHasher> Smt<N, H, TrieStore<N>> {
+    /// A new tree holding this one's entries plus `key` set to `value`, as
+    /// `insert` would leave it, with this tree left as it is. The two share every
+    /// node off `key`'s path, so this costs the same as an `insert`, and both trees
+    /// stay fully usable and provable.
+    pub fn inserted(&self, key: &[u8; N], value: &[u8]) -> Self {
+        let mut tree = self.fork();
+        tree.insert(key, value);
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Sha256Hasher, SparseMerkleTree};
+    use std::collections::HashSet;
+
+    type TrieTree = SparseMerkleTree<Sha256Hasher, TrieStore<16>>;
+
+    fn key(i: u32) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..4].copy_from_slice(&i.wrapping_mul(0x9e37_79b9).to_be_bytes());
+        key
+    }
+
+    /// Trie nodes reachable from any of `stores`, each counted once however many share it.
+    fn distinct_nodes(stores: &[&TrieStore<16>]) -> usize {
+        fn visit<T>(node: &Option<Arc<TrieNode<T>>>, seen: &mut HashSet<usize>) {
+            if let Some(node) = node {
+                if seen.insert(Arc::as_ptr(node) as usize) {
+                    node.children.iter().for_each(|child| visit(child, seen));
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        for store in stores {
+            visit(&store.nodes, &mut seen);
+            visit(&store.values, &mut seen);
+        }
+        seen.len()
+    }
+
+    #[test]
+    fn test_trie_store_matches_memory_store() {
+        let mut memory = SparseMerkleTree::new();
+        let mut trie = TrieTree::with_store(TrieStore::default());
+        for i in 0..200 {
+            memory.insert(&key(i), &i.to_be_bytes());
+            trie.insert(&key(i), &i.to_be_bytes());
+        }
+        for i in (0..200).step_by(3) {
+            memory.delete(&key(i));
+            trie.delete(&key(i));
+        }
+        assert!(trie.root_eq(&memory));
+        assert_eq!(trie.len(), memory.len());
+        assert_eq!(trie.entries(), memory.entries());
+        assert_eq!(trie.generate_proof(&key(4)), memory.generate_proof(&key(4)));
+        assert_eq!(trie.verify_integrity(), Ok(()));
+
+        // Deleting everything leaves an empty store behind.
+        for i in 0..200 {
+            trie.delete(&key(i));
+        }
+        assert_eq!(distinct_nodes(&[&trie.store]), 0);
+    }
+
+    #[test]
+    fn test_inserted_shares_nodes_between_versions() {
+        let mut versions = vec![TrieTree::with_store(TrieStore::default())];
+        let mut expected = SparseMerkleTree::new();
+        for i in 0..1000 {
+            let next = versions.last().unwrap().inserted(&key(i), &i.to_be_bytes());
+            expected.insert(&key(i), &i.to_be_bytes());
+            assert!(next.root_eq(&expected));
+            versions.push(next);
+        }
+
+        // Every version still holds and proves exactly its own keys.
+        for (i, version) in versions.iter().enumerate().step_by(97) {
+            assert_eq!(version.len(), i);
+            let proof = version.generate_proof(&key(i as u32));
+            assert_eq!(version.verify_proof(&key(i as u32), None, &proof), Ok(true));
+            if i > 0 {
+                let proof = version.generate_proof(&key(0));
+                assert_eq!(
+                    version.verify_proof(&key(0), Some(&0u32.to_be_bytes()), &proof),
+                    Ok(true)
+                );
+            }
+        }
+
+        // Each insert adds at most one path of trie nodes for its tree nodes and one for its
+        // value, while copying whole versions would take a number quadratic in the inserts.
+        let stores: Vec<_> = versions.iter().map(|version| &version.store).collect();
+        let distinct = distinct_nodes(&stores);
+        assert!(distinct <= 1000 * (2 * 128 + 2));
+        let copied: usize = versions
+            .iter()
+            .step_by(100)
+            .map(|version| distinct_nodes(&[&version.store]))
+            .sum::<usize>()
+            * 100;
+        assert!(distinct * 10 < copied);
+    }
+}
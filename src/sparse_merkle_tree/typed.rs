@@ -0,0 +1,202 @@
+use super::{Sha256Hasher, Smt, SmtError, SmtHasher, SmtProof};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// How a `TypedSmt` turns its values into the bytes its leaves hash. Roots and
+/// proofs only ever see those bytes, so trees holding different value types
+/// that encode to the same bytes have the same root as a plain `Smt` would.
+pub trait ValueEncoding<V> {
+    fn encode<'a>(&self, value: &'a V) -> Cow<'a, [u8]>;
+}
+
+/// Values that are bytes already, e.g. `Vec<u8>`, `String` or `[u8; 32]`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsBytes;
+
+impl<V: AsRef<[u8]>> ValueEncoding<V> for AsBytes {
+    fn encode<'a>(&self, value: &'a V) -> Cow<'a, [u8]> {
+        Cow::Borrowed(value.as_ref())
+    }
+}
+
+/// Any function from a value to its bytes, e.g. a hand-written codec.
+impl<V, F: Fn(&V) -> Vec<u8>> ValueEncoding<V> for F {
+    fn encode<'a>(&self, value: &'a V) -> Cow<'a, [u8]> {
+        Cow::Owned(self(value))
+    }
+}
+
+/// Values serialized with `serde_json::to_vec`. The root depends on those exact
+/// bytes, so values must serialize the same way every time: no `HashMap` fields,
+/// whose order varies.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "serde")]
+impl<V: serde::Serialize> ValueEncoding<V> for Json {
+    fn encode<'a>(&self, value: &'a V) -> Cow<'a, [u8]> {
+        Cow::Owned(serde_json::to_vec(value).expect("value can't be serialized as JSON"))
+    }
+}
+
+/// A sparse merkle tree holding values of type `V`, hashed through their
+/// encoding `E`. `get` hands out the stored values themselves, so callers don't
+/// decode anything; the underlying tree keeps only leaf hashes (see
+/// `Smt::with_store_values`), so the bytes aren't held twice.
+#[derive(Debug, Clone)]
+pub struct TypedSmt<const N: usize, V, E = AsBytes, H = Sha256Hasher> {
+    tree: Smt<N, H>,
+    values: HashMap<[u8; N], V>,
+    encoding: E,
+}
+
+pub type TypedSparseMerkleTree<V, E = AsBytes, H = Sha256Hasher> = TypedSmt<16, V, E, H>;
+
+impl<const N: usize, V: AsRef<[u8]>> TypedSmt<N, V> {
+    pub fn new() -> Self {
+        Self::with_encoding(Smt::new(), AsBytes)
+    }
+}
+
+impl<const N: usize, V: AsRef<[u8]>> Default for TypedSmt<N, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, V, E: ValueEncoding<V>, H: SmtHasher> TypedSmt<N, V, E, H> {
+    /// Wraps an empty `tree`, keeping its depth, flavor and bit order, with values
+    /// encoded by `encoding`. Panics if `tree` isn't empty.
+    pub fn with_encoding(tree: Smt<N, H>, encoding: E) -> Self {
+        Self {
+            tree: tree.with_store_values(false),
+            values: HashMap::new(),
+            encoding,
+        }
+    }
+
+    /// The underlying tree, for everything that only needs hashes, e.g. multiproofs.
+    pub fn tree(&self) -> &Smt<N, H> {
+        &self.tree
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Inserts or overwrites `key` and returns the new root.
+    pub fn insert(&mut self, key: &[u8; N], value: V) -> [u8; 32] {
+        self.tree.insert(key, &self.encoding.encode(&value));
+        self.values.insert(*key, value);
+        self.tree.root()
+    }
+
+    pub fn get(&self, key: &[u8; N]) -> Option<&V> {
+        self.values.get(key)
+    }
+
+    /// Removes `key`, returning the value it held.
+    pub fn remove(&mut self, key: &[u8; N]) -> Option<V> {
+        self.tree.delete(key);
+        self.values.remove(key)
+    }
+
+    pub fn generate_proof(&self, key: &[u8; N]) -> SmtProof {
+        self.tree.generate_proof(key)
+    }
+
+    /// `Smt::verify_proof` for `value` in its encoded form.
+    pub fn verify_proof(
+        &self,
+        key: &[u8; N],
+        value: Option<&V>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        let value = value.map(|value| self.encoding.encode(value));
+        self.tree.verify_proof(key, value.as_deref(), proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    #[test]
+    fn test_typed_roots_depend_only_on_bytes() {
+        let mut raw = SparseMerkleTree::new();
+        let mut strings = TypedSparseMerkleTree::<String>::new();
+        let mut vecs = TypedSparseMerkleTree::<Vec<u8>>::new();
+        for i in 0..20u8 {
+            let value = format!("value {i}");
+            raw.insert(&[i; 16], value.as_bytes());
+            strings.insert(&[i; 16], value.clone());
+            vecs.insert(&[i; 16], value.into_bytes());
+        }
+        strings.remove(&[3; 16]);
+        vecs.remove(&[3; 16]);
+        raw.delete(&[3; 16]);
+
+        assert_eq!(strings.root(), raw.root());
+        assert_eq!(vecs.root(), raw.root());
+        assert_eq!(strings.get(&[4; 16]).map(String::as_str), Some("value 4"));
+        assert_eq!(strings.get(&[3; 16]), None);
+        assert_eq!(strings.len(), 19);
+
+        let proof = strings.generate_proof(&[4; 16]);
+        assert_eq!(proof, raw.generate_proof(&[4; 16]));
+        let value = String::from("value 4");
+        assert_eq!(
+            strings.verify_proof(&[4; 16], Some(&value), &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            vecs.verify_proof(&[4; 16], Some(&b"value 4".to_vec()), &proof),
+            Ok(true)
+        );
+        assert_eq!(strings.verify_proof(&[4; 16], None, &proof), Ok(false));
+    }
+
+    #[test]
+    fn test_typed_values_with_a_codec() {
+        let encode = |balance: &u64| balance.to_be_bytes().to_vec();
+        let mut balances = TypedSmt::with_encoding(SparseMerkleTree::new(), encode);
+        let mut raw = SparseMerkleTree::new();
+        for i in 0..10u64 {
+            balances.insert(&[i as u8; 16], i * 1000);
+            raw.insert(&[i as u8; 16], &(i * 1000).to_be_bytes());
+        }
+        assert_eq!(balances.root(), raw.root());
+        assert_eq!(balances.get(&[7; 16]), Some(&7000));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_typed_values_as_json() {
+        #[derive(Debug, PartialEq, serde::Serialize)]
+        struct Account {
+            nonce: u64,
+            owner: String,
+        }
+
+        let account = Account {
+            nonce: 3,
+            owner: "alice".into(),
+        };
+        let mut accounts = TypedSmt::with_encoding(SparseMerkleTree::new(), Json);
+        accounts.insert(&[1; 16], account);
+        let mut raw = SparseMerkleTree::new();
+        raw.insert(&[1; 16], br#"{"nonce":3,"owner":"alice"}"#);
+        assert_eq!(accounts.root(), raw.root());
+        assert_eq!(accounts.get(&[1; 16]).map(|account| account.nonce), Some(3));
+    }
+}
@@ -0,0 +1,77 @@
+use super::{path_to_key, NodeStore, Smt, SmtError, SmtHasher, SmtProof};
+
+/// Integer keys for 16-byte trees. A `u128` key is stored under its big-endian
+/// bytes, `path_to_key(key)`, the inverse of `key_to_path`, so numerically
+/// adjacent keys are adjacent leaves in a full-depth tree. A tree of depth `d`
+/// reads the top `d` bits, so there keys must be multiples of `2^(128 - d)`.
+impl<H: SmtHasher, S: NodeStore<16>> Smt<16, H, S> {
+    pub fn insert_u128(&mut self, key: u128, value: &[u8]) -> [u8; 32] {
+        self.insert(&path_to_key(key), value)
+    }
+
+    pub fn get_u128(&self, key: u128) -> Option<Vec<u8>> {
+        self.get(&path_to_key(key))
+    }
+
+    pub fn delete_u128(&mut self, key: u128) -> [u8; 32] {
+        self.delete(&path_to_key(key))
+    }
+
+    pub fn generate_proof_u128(&self, key: u128) -> SmtProof {
+        self.generate_proof(&path_to_key(key))
+    }
+
+    pub fn verify_proof_u128(
+        &self,
+        key: u128,
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        self.verify_proof(&path_to_key(key), value, proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{key_to_path, SparseMerkleTree};
+
+    #[test]
+    fn test_u128_keys_match_big_endian_bytes() {
+        let mut by_int = SparseMerkleTree::new();
+        let mut by_bytes = SparseMerkleTree::new();
+        assert_eq!(
+            by_int.insert_u128(5, b"v"),
+            by_bytes.insert(&5u128.to_be_bytes(), b"v")
+        );
+        assert_eq!(
+            by_int.insert_u128(u128::MAX, b"max"),
+            by_bytes.insert(&[0xff; 16], b"max")
+        );
+        assert_eq!(by_int.get_u128(5), Some(b"v".to_vec()));
+        assert_eq!(by_int.get_u128(4), None);
+        assert_eq!(key_to_path(&5u128.to_be_bytes()), 5);
+
+        let proof = by_int.generate_proof_u128(5);
+        assert_eq!(proof, by_bytes.generate_proof(&5u128.to_be_bytes()));
+        assert_eq!(by_int.verify_proof_u128(5, Some(b"v"), &proof), Ok(true));
+        assert_eq!(by_int.verify_proof_u128(4, None, &proof), Ok(false));
+
+        assert_eq!(by_int.delete_u128(u128::MAX), by_bytes.delete(&[0xff; 16]));
+    }
+
+    #[test]
+    fn test_adjacent_u128_keys_are_sibling_leaves() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert_u128(6, b"six");
+        tree.insert_u128(7, b"seven");
+        // 6 and 7 differ only in the last bit, so each is the other's leaf sibling
+        // and every sibling above is an empty subtree.
+        let proof = tree.generate_proof_u128(6);
+        assert_eq!(proof.siblings[0], tree.node(128, 7u128.to_be_bytes()));
+        assert!(proof.siblings[1..]
+            .iter()
+            .enumerate()
+            .all(|(j, sibling)| *sibling == tree.default_nodes[127 - j]));
+        assert_eq!(tree.verify_proof_u128(6, Some(b"six"), &proof), Ok(true));
+    }
+}
@@ -0,0 +1,151 @@
+use super::{NodeStore, Smt, SmtHasher};
+
+/// A write recorded in the undo log: `key` went from `old_value` to `new_value`,
+/// `None` standing for an absent key, as in `SmtOperation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtOp<const N: usize> {
+    pub key: [u8; N],
+    pub old_value: Option<Vec<u8>>,
+    pub new_value: Option<Vec<u8>>,
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Keeps the last `depth` writes for `undo`, dropping the oldest ones beyond
+    /// that. 0, the default, turns the log off. Every key a batch writes counts as
+    /// one write, and deleting an absent key isn't one. Trees that don't retain
+    /// values (see `with_store_values`) have no old values to restore and log nothing.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        while self.undo_log.len() > depth {
+            self.undo_log.pop_front();
+        }
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.undo_depth
+    }
+
+    /// Reverts the most recent write still in the log, restoring the key's old
+    /// value and with it the root from before the write, and returns that write.
+    /// `None` once the log is empty.
+    pub fn undo(&mut self) -> Option<SmtOp<N>> {
+        let op = self.undo_log.pop_back()?;
+        // The revert itself isn't logged.
+        let depth = std::mem::take(&mut self.undo_depth);
+        match &op.old_value {
+            Some(value) => self.update(&op.key, value),
+            None => self.remove(&op.key),
+        };
+        self.undo_depth = depth;
+        Some(op)
+    }
+
+    pub(super) fn log_op(
+        &mut self,
+        key: &[u8; N],
+        old_value: Option<&[u8]>,
+        new_value: Option<&[u8]>,
+    ) {
+        if self.undo_depth == 0 {
+            return;
+        }
+        if self.undo_log.len() == self.undo_depth {
+            self.undo_log.pop_front();
+        }
+        self.undo_log.push_back(SmtOp {
+            key: *key,
+            old_value: old_value.map(<[u8]>::to_vec),
+            new_value: new_value.map(<[u8]>::to_vec),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    #[test]
+    fn test_undo_interleaved_writes() {
+        let mut tree = SparseMerkleTree::new();
+        tree.set_undo_depth(16);
+        let mut roots = vec![tree.root()];
+        tree.insert(&[1; 16], b"one");
+        roots.push(tree.root());
+        tree.insert(&[2; 16], b"two");
+        roots.push(tree.root());
+        tree.delete(&[1; 16]);
+        roots.push(tree.root());
+        // Deleting an absent key changes nothing and isn't logged.
+        tree.delete(&[9; 16]);
+
+        assert_eq!(
+            tree.undo(),
+            Some(SmtOp {
+                key: [1; 16],
+                old_value: Some(b"one".to_vec()),
+                new_value: None,
+            })
+        );
+        roots.pop();
+        assert_eq!(tree.root(), *roots.last().unwrap());
+        assert_eq!(tree.get(&[1; 16]), Some(b"one".to_vec()));
+
+        tree.insert(&[2; 16], b"updated");
+        roots.push(tree.root());
+        tree.insert(&[3; 16], b"three");
+        roots.push(tree.root());
+
+        let undone = tree.undo().unwrap();
+        assert_eq!(undone.key, [3; 16]);
+        assert_eq!(undone.old_value, None);
+        roots.pop();
+        assert_eq!(tree.root(), *roots.last().unwrap());
+        assert_eq!(tree.get(&[3; 16]), None);
+
+        let undone = tree.undo().unwrap();
+        assert_eq!(undone.old_value, Some(b"two".to_vec()));
+        assert_eq!(undone.new_value, Some(b"updated".to_vec()));
+        roots.pop();
+        assert_eq!(tree.root(), *roots.last().unwrap());
+        assert_eq!(tree.get(&[2; 16]), Some(b"two".to_vec()));
+
+        while tree.undo().is_some() {
+            roots.pop();
+            assert_eq!(tree.root(), *roots.last().unwrap());
+        }
+        assert_eq!(roots.len(), 1);
+        assert_eq!(tree.root(), SparseMerkleTree::new().root());
+    }
+
+    #[test]
+    fn test_undo_depth_bounds_log() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[0; 16], b"unlogged");
+        assert_eq!(tree.undo(), None);
+
+        tree.set_undo_depth(2);
+        let mut roots = vec![tree.root()];
+        for i in 1..=4u8 {
+            tree.insert(&[i; 16], &[i]);
+            roots.push(tree.root());
+        }
+        assert_eq!(tree.undo().unwrap().key, [4; 16]);
+        assert_eq!(tree.root(), roots[3]);
+        assert_eq!(tree.undo().unwrap().key, [3; 16]);
+        assert_eq!(tree.root(), roots[2]);
+        assert_eq!(tree.undo(), None);
+        assert_eq!(tree.root(), roots[2]);
+
+        // A batch logs each key it writes.
+        let entries = vec![([5; 16], vec![5]), ([6; 16], vec![6])];
+        tree.insert_batch(&entries);
+        tree.undo();
+        tree.undo();
+        assert_eq!(tree.root(), roots[2]);
+
+        tree.insert(&[7; 16], b"seven");
+        tree.set_undo_depth(0);
+        assert_eq!(tree.undo(), None);
+    }
+}
@@ -0,0 +1,351 @@
+use super::proof::{
+    check_depth, check_key, check_proof, ct_eq, decode_hex, fold_path, fold_siblings, leaf_hash,
+    BitOrder, ProofHexError, Sha256Hasher, SmtError, SmtFlavor, SmtHasher, SmtProof, DEFAULT_LEAF,
+};
+use core::marker::PhantomData;
+
+/// Everything a light client needs to check proofs against a tree it doesn't
+/// hold: the root, depth and flavor (and bit order, `MsbFirst` unless set with
+/// `with_bit_order`). Proofs made for another depth, flavor or bit order are
+/// rejected as by `Smt::verify_proof`, and the hashing is the same code the tree
+/// runs. Builds without `std`.
+///
+/// A proof records the shape of the tree it came from, but that record is as
+/// untrusted as the rest of the proof: relabelled with the other bit order, a
+/// proof that `key` holds a value would pass for the key with its bits reversed.
+/// So every check that takes a proof without a tree takes a verifier, and the
+/// shape comes from it.
+#[derive(Debug)]
+pub struct SmtVerifier<const N: usize, H = Sha256Hasher> {
+    root: [u8; 32],
+    depth: usize,
+    flavor: SmtFlavor,
+    bit_order: BitOrder,
+    hasher: PhantomData<H>,
+}
+
+impl<const N: usize, H: SmtHasher> SmtVerifier<N, H> {
+    pub fn new(root: [u8; 32], depth: usize, flavor: SmtFlavor) -> Self {
+        Self {
+            root,
+            depth,
+            flavor,
+            bit_order: BitOrder::MsbFirst,
+            hasher: PhantomData,
+        }
+    }
+
+    /// A verifier for the root in `Smt::root_hex` form. The `0x` prefix is optional
+    /// and either case is accepted.
+    pub fn from_hex_root(
+        root: &str,
+        depth: usize,
+        flavor: SmtFlavor,
+    ) -> Result<Self, ProofHexError> {
+        let bytes = decode_hex(root)?;
+        let root = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| ProofHexError::WrongLength {
+                expected: 32,
+                actual: bytes.len(),
+            })?;
+        Ok(Self::new(root, depth, flavor))
+    }
+
+    pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    /// This verifier's depth, flavor and bit order with another root, as for the
+    /// same tree after some writes.
+    pub fn with_root(mut self, root: [u8; 32]) -> Self {
+        self.root = root;
+        self
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn flavor(&self) -> SmtFlavor {
+        self.flavor
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    /// Whether `proof` shows `key` holding `value`, `None` for an absent key. An
+    /// empty value is an entry like any other: it is proven with `Some(b"")`, as
+    /// no value hashes to the empty leaf. Fails with `DepthMismatch`,
+    /// `FlavorMismatch` or `BitOrderMismatch` for a proof from another kind of
+    /// tree, `InvalidProofLength` if the proof's sibling count isn't its depth and
+    /// `KeyLength` if `key` has bits set past the depth. A proof for a hashed key
+    /// verifies as `false`.
+    pub fn verify(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        self.check_proof(proof)?;
+        check_key(key, self.depth)?;
+        let path = self.bit_order.path(key, self.depth);
+        Ok(!proof.hashed_key && ct_eq(&fold_path::<N, H>(&path, value, proof), &self.root))
+    }
+
+    /// `verify` for `Some(value)`.
+    pub fn verify_membership(
+        &self,
+        key: &[u8; N],
+        value: &[u8],
+        proof: &SmtProof,
+    ) -> Result<bool, SmtError> {
+        self.verify(key, Some(value), proof)
+    }
+
+    /// `verify` for `None`.
+    pub fn verify_non_membership(&self, key: &[u8; N], proof: &SmtProof) -> Result<bool, SmtError> {
+        self.verify(key, None, proof)
+    }
+
+    /// Whether `siblings`, those of a proof from the leaf up as in
+    /// `SmtProof::siblings`, show `key` holding `value` (`None` for an absent key).
+    /// The siblings are hashed in as they come, without collecting them, and with
+    /// the built-in hashers nothing is allocated, so this suits hot loops and
+    /// `no_std`. Exactly `depth` siblings must come: a shorter or longer iterator
+    /// gives `false`, as do a key with bits past the depth and an invalid depth.
+    pub fn verify_proof_streaming<'a>(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        siblings: impl IntoIterator<Item = &'a [u8; 32]>,
+    ) -> bool {
+        if check_depth::<N>(self.depth).is_err() || check_key(key, self.depth).is_err() {
+            return false;
+        }
+        let path = self.bit_order.path(key, self.depth);
+        let leaf = value.map_or(DEFAULT_LEAF, |value| {
+            leaf_hash::<N, H>(self.flavor, &path, value)
+        });
+        let mut siblings = siblings.into_iter();
+        match fold_siblings::<N, H, _>(&path, self.depth, leaf, &mut siblings) {
+            Some(root) => siblings.next().is_none() && ct_eq(&root, &self.root),
+            None => false,
+        }
+    }
+
+    /// Checks that `proof` has this verifier's depth, flavor and bit order, and as
+    /// many siblings as levels.
+    pub(super) fn check_proof(&self, proof: &SmtProof) -> Result<(), SmtError> {
+        self.check_shape(proof.depth, proof.flavor, proof.bit_order)?;
+        check_proof::<N>(proof)
+    }
+
+    /// Checks that a proof of the given depth, flavor and bit order was generated
+    /// for a tree like the one this verifier checks.
+    pub(super) fn check_shape(
+        &self,
+        depth: usize,
+        flavor: SmtFlavor,
+        bit_order: BitOrder,
+    ) -> Result<(), SmtError> {
+        if depth != self.depth {
+            return Err(SmtError::DepthMismatch {
+                expected: self.depth,
+                actual: depth,
+            });
+        }
+        if flavor != self.flavor {
+            return Err(SmtError::FlavorMismatch {
+                expected: self.flavor,
+                actual: flavor,
+            });
+        }
+        if bit_order != self.bit_order {
+            return Err(SmtError::BitOrderMismatch {
+                expected: self.bit_order,
+                actual: bit_order,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize, H> Clone for SmtVerifier<N, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<const N: usize, H> Copy for SmtVerifier<N, H> {}
+
+impl<const N: usize, H> PartialEq for SmtVerifier<N, H> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.root, self.depth, self.flavor, self.bit_order)
+            == (other.root, other.depth, other.flavor, other.bit_order)
+    }
+}
+
+impl<const N: usize, H> Eq for SmtVerifier<N, H> {}
+
+#[cfg(feature = "std")]
+impl<const N: usize, H: SmtHasher, S> super::Smt<N, H, S> {
+    /// A verifier for this tree's current root, which later writes don't change.
+    pub fn verifier(&self) -> SmtVerifier<N, H> {
+        SmtVerifier::new(self.root, self.depth, self.flavor).with_bit_order(self.bit_order)
+    }
+
+    /// `SmtVerifier::verify_proof_streaming` against this tree's root.
+    pub fn verify_proof_streaming<'a>(
+        &self,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        siblings: impl IntoIterator<Item = &'a [u8; 32]>,
+    ) -> bool {
+        self.verifier().verify_proof_streaming(key, value, siblings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The depth-4 tree holding 0101 = "a", 0110 = "c" and 1100 = "b", as in the
+    /// `proof` tests, with its proof for 0101.
+    const ROOT: &str = "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d";
+    const PROOF: &str = concat!(
+        "0x000400",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a",
+        "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+        "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+    );
+
+    fn key(path: u8) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[0] = path;
+        key
+    }
+
+    #[test]
+    fn test_verifier_from_hex_root() {
+        let verifier = SmtVerifier::<16>::from_hex_root(ROOT, 4, SmtFlavor::Plain).unwrap();
+        let proof = SmtProof::from_hex(PROOF).unwrap();
+        assert_eq!(
+            verifier.verify_membership(&key(0x50), b"a", &proof),
+            Ok(true)
+        );
+        assert_eq!(
+            verifier.verify_membership(&key(0x50), b"b", &proof),
+            Ok(false)
+        );
+        assert_eq!(
+            verifier.verify_non_membership(&key(0x50), &proof),
+            Ok(false)
+        );
+        // 0100 is empty, but its proof would have 0101's leaf as a sibling.
+        assert_eq!(
+            verifier.verify_non_membership(&key(0x40), &proof),
+            Ok(false)
+        );
+
+        let deeper = SmtVerifier::<16>::new(verifier.root(), 5, SmtFlavor::Plain);
+        assert_eq!(
+            deeper.verify_membership(&key(0x50), b"a", &proof),
+            Err(SmtError::DepthMismatch {
+                expected: 5,
+                actual: 4
+            })
+        );
+        let committing = SmtVerifier::<16>::new(verifier.root(), 4, SmtFlavor::PathCommitting);
+        assert!(matches!(
+            committing.verify_membership(&key(0x50), b"a", &proof),
+            Err(SmtError::FlavorMismatch { .. })
+        ));
+        assert!(verifier.verify_proof_streaming(&key(0x50), Some(b"a"), &proof.siblings));
+        assert!(!verifier.verify_proof_streaming(&key(0x50), Some(b"b"), &proof.siblings));
+        assert!(!verifier.verify_proof_streaming(&key(0x50), Some(b"a"), &proof.siblings[1..]));
+        let long = proof.siblings.iter().chain(&proof.siblings[..1]);
+        assert!(!verifier.verify_proof_streaming(&key(0x50), Some(b"a"), long));
+        assert!(!verifier.verify_proof_streaming(&key(0x58), Some(b"a"), &proof.siblings));
+
+        assert_eq!(
+            SmtVerifier::<16>::from_hex_root(&ROOT[2..], 4, SmtFlavor::Plain),
+            Err(ProofHexError::WrongLength {
+                expected: 32,
+                actual: 31
+            })
+        );
+        assert_eq!(
+            SmtVerifier::<16>::from_hex_root("0xzz", 4, SmtFlavor::Plain),
+            Err(ProofHexError::InvalidCharacter {
+                index: 0,
+                character: 'z'
+            })
+        );
+    }
+
+    /// The verifier and the tree accept and reject exactly the same proofs.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_verifier_agrees_with_tree() {
+        use crate::SparseMerkleTree;
+
+        // Keys with any 12-bit path.
+        let key_at = |i: u16| {
+            let mut key = [0; 16];
+            key[..2].copy_from_slice(&(i << 4).to_be_bytes());
+            key
+        };
+
+        for (flavor, bit_order) in [
+            (SmtFlavor::Plain, BitOrder::MsbFirst),
+            (SmtFlavor::PathCommitting, BitOrder::MsbFirst),
+            (SmtFlavor::LengthPrefixed, BitOrder::LsbFirst),
+        ] {
+            let mut tree = SparseMerkleTree::with_depth(12)
+                .with_flavor(flavor)
+                .with_bit_order(bit_order);
+            for i in 0..200u16 {
+                tree.insert(&key_at(i.wrapping_mul(37) & 0xfff), &i.to_be_bytes());
+            }
+            let verifier = SmtVerifier::from_hex_root(&tree.root_hex(), 12, flavor)
+                .unwrap()
+                .with_bit_order(bit_order);
+            assert_eq!(verifier, tree.verifier());
+
+            let mut accepted = 0;
+            for i in 0..300u16 {
+                let key = key_at(i.wrapping_mul(37) & 0xfff);
+                let own = tree.generate_proof(&key);
+                let other = tree.generate_proof(&key_at(0xfff));
+                let value = tree.get(&key);
+                for proof in [&own, &other] {
+                    for value in [value.as_deref(), None, Some(&i.to_be_bytes()[..])] {
+                        let expected = tree.verify_proof(&key, value, proof);
+                        let actual = match value {
+                            Some(value) => verifier.verify_membership(&key, value, proof),
+                            None => verifier.verify_non_membership(&key, proof),
+                        };
+                        assert_eq!(actual, expected);
+                        accepted += usize::from(actual == Ok(true));
+                    }
+                }
+            }
+            // At least two of the three values hold for each key's own proof.
+            assert!(accepted >= 600);
+            let short = SparseMerkleTree::with_depth(11).generate_proof(&[0; 16]);
+            assert_eq!(
+                verifier.verify_non_membership(&[0; 16], &short),
+                tree.verify_proof(&[0; 16], None, &short)
+            );
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use super::{truncate, NodePosition, Sha256Hasher, Smt, SmtHasher, SmtProof};
+use std::collections::HashMap;
+
+/// A sparse merkle tree that keeps the root of every version and can prove
+/// membership as of any of them. Each mutation creates a new version and only
+/// records the nodes along the changed path, so history grows by at most
+/// `depth + 1` nodes per version.
+#[derive(Debug, Clone)]
+pub struct VersionedSmt<const N: usize, H = Sha256Hasher> {
+    tree: Smt<N, H>,
+    roots: Vec<[u8; 32]>,
+    // Every hash a node has held as (version, hash) pairs in version order. A node
+    // with no entry at or before a version was the default node at that version.
+    history: HashMap<NodePosition<N>, Vec<(u64, [u8; 32])>>,
+    // Oldest version whose nodes are still in `history`, see `prune_before`.
+    oldest: u64,
+}
+
+pub type VersionedSparseMerkleTree<H = Sha256Hasher> = VersionedSmt<16, H>;
+
+impl<const N: usize> VersionedSmt<N> {
+    pub fn new() -> Self {
+        Self::from_tree(Smt::new())
+    }
+}
+
+impl<const N: usize> Default for VersionedSmt<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, H: SmtHasher> VersionedSmt<N, H> {
+    /// Starts the history with `tree`'s current state as version 0.
+    pub fn from_tree(tree: Smt<N, H>) -> Self {
+        let history = tree
+            .store
+            .nodes
+            .iter()
+            .map(|(&position, &hash)| (position, vec![(0, hash)]))
+            .collect();
+        Self {
+            roots: vec![tree.root],
+            tree,
+            history,
+            oldest: 0,
+        }
+    }
+
+    /// The latest state of the tree.
+    pub fn tree(&self) -> &Smt<N, H> {
+        &self.tree
+    }
+
+    pub fn version(&self) -> u64 {
+        self.roots.len() as u64 - 1
+    }
+
+    /// Inserts or overwrites `key` and returns the new version.
+    pub fn insert(&mut self, key: &[u8; N], value: &[u8]) -> u64 {
+        self.tree.insert(key, value);
+        self.commit(key)
+    }
+
+    /// Removes `key` and returns the new version, which is created even if the key was absent.
+    pub fn delete(&mut self, key: &[u8; N]) -> u64 {
+        self.tree.delete(key);
+        self.commit(key)
+    }
+
+    pub fn root_at(&self, version: u64) -> Option<&[u8; 32]> {
+        self.roots.get(version as usize)
+    }
+
+    /// Proof for `key` against `root_at(version)`, or `None` if the version doesn't
+    /// exist yet or was pruned.
+    pub fn generate_proof_at(&self, key: &[u8; N], version: u64) -> Option<SmtProof> {
+        if version > self.version() || version < self.oldest {
+            return None;
+        }
+        let depth = self.tree.depth;
+        let path = self.tree.path(key);
+        let siblings = (0..depth)
+            .rev()
+            .map(|i| {
+                let position = (i + 1, Smt::<N, H>::sibling(&path, i + 1));
+                self.node_at(position, version)
+            })
+            .collect();
+        Some(SmtProof {
+            depth,
+            flavor: self.tree.flavor,
+            hashed_key: false,
+            bit_order: self.tree.bit_order,
+            siblings,
+        })
+    }
+
+    /// Records the nodes on `key`'s path that changed in the latest mutation as a new version.
+    fn commit(&mut self, key: &[u8; N]) -> u64 {
+        let version = self.roots.len() as u64;
+        let path = self.tree.path(key);
+        for level in 0..=self.tree.depth {
+            let position = (level, truncate(&path, level));
+            let hash = self.tree.node(level, position.1);
+            let entries = self.history.entry(position).or_default();
+            let previous = entries
+                .last()
+                .map_or(&self.tree.default_nodes[level], |(_, hash)| hash);
+            if hash != *previous {
+                entries.push((version, hash));
+            } else if entries.is_empty() {
+                self.history.remove(&position);
+            }
+        }
+        self.roots.push(self.tree.root);
+        version
+    }
+
+    /// Drops the history only needed for versions before `version` (at most the
+    /// latest), returning how many recorded node hashes were dropped. Their roots
+    /// are kept, but `generate_proof_at` returns `None` for them from then on.
+    pub fn prune_before(&mut self, version: u64) -> usize {
+        let version = version.min(self.version());
+        let mut pruned = 0;
+        self.history.retain(|&(level, _), entries| {
+            // The last hash written at or before `version` is its hash at `version`.
+            let superseded = entries
+                .partition_point(|(at, _)| *at <= version)
+                .saturating_sub(1);
+            pruned += superseded;
+            entries.drain(..superseded);
+            // A node that is default from `version` on needs no entry at all.
+            if entries[..] == [(entries[0].0, self.tree.default_nodes[level])] {
+                pruned += 1;
+                return false;
+            }
+            true
+        });
+        self.oldest = self.oldest.max(version);
+        pruned
+    }
+
+    fn node_at(&self, position: NodePosition<N>, version: u64) -> [u8; 32] {
+        self.history
+            .get(&position)
+            .and_then(|entries| {
+                let written = entries.partition_point(|(at, _)| *at <= version);
+                written.checked_sub(1).map(|i| entries[i].1)
+            })
+            .unwrap_or(self.tree.default_nodes[position.0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitOrder, SparseMerkleTree};
+
+    #[test]
+    fn test_proofs_against_historical_roots() {
+        for bit_order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut tree = VersionedSparseMerkleTree::from_tree(
+                SparseMerkleTree::new().with_bit_order(bit_order),
+            );
+            let key1 = [1u8; 16];
+            let key2 = [2u8; 16];
+
+            assert_eq!(tree.insert(&key1, b"v1"), 1);
+            assert_eq!(tree.insert(&key1, b"v2"), 2);
+            assert_eq!(tree.insert(&key2, b"other"), 3);
+            assert_eq!(tree.delete(&key1), 4);
+            assert_eq!(tree.version(), 4);
+
+            let cases: [(u64, Option<&[u8]>); 5] = [
+                (0, None),
+                (1, Some(b"v1")),
+                (2, Some(b"v2")),
+                (3, Some(b"v2")),
+                (4, None),
+            ];
+            for (version, value) in cases {
+                let root = tree.root_at(version).unwrap();
+                let proof = tree.generate_proof_at(&key1, version).unwrap();
+                assert_eq!(
+                    tree.tree()
+                        .verifier()
+                        .with_root(*root)
+                        .verify(&key1, value, &proof),
+                    Ok(true)
+                );
+                assert_eq!(
+                    tree.tree()
+                        .verifier()
+                        .with_root(*root)
+                        .verify(&key1, Some(b"v3"), &proof),
+                    Ok(false)
+                );
+            }
+
+            let proof = tree.generate_proof_at(&key2, 2).unwrap();
+            assert_eq!(
+                tree.tree()
+                    .verifier()
+                    .with_root(*tree.root_at(2).unwrap())
+                    .verify(&key2, None, &proof),
+                Ok(true)
+            );
+            assert_eq!(tree.generate_proof_at(&key1, 5), None);
+            assert_eq!(tree.root_at(5), None);
+        }
+    }
+
+    #[test]
+    fn test_latest_version_matches_plain_tree() {
+        let mut versioned = VersionedSparseMerkleTree::new();
+        let mut plain = SparseMerkleTree::new();
+        for i in 0..20u8 {
+            versioned.insert(&[i; 16], &[i]);
+            plain.insert(&[i; 16], &[i]);
+        }
+        assert_eq!(versioned.tree().root(), plain.root());
+        assert_eq!(versioned.root_at(versioned.version()), Some(&plain.root()));
+
+        let key = [7u8; 16];
+        assert_eq!(
+            versioned.generate_proof_at(&key, versioned.version()),
+            Some(plain.generate_proof(&key))
+        );
+    }
+
+    #[test]
+    fn test_history_grows_by_changed_path_only() {
+        let mut tree = VersionedSparseMerkleTree::new();
+        for i in 0..10u8 {
+            tree.insert(&[i; 16], b"value");
+            let recorded: usize = tree.history.values().map(Vec::len).sum();
+            assert!(recorded <= (i as usize + 1) * 129);
+        }
+
+        // Overwriting a key only adds entries for its own path.
+        let before: usize = tree.history.values().map(Vec::len).sum();
+        tree.insert(&[3u8; 16], b"changed");
+        let after: usize = tree.history.values().map(Vec::len).sum();
+        assert_eq!(after - before, 129);
+    }
+
+    #[test]
+    fn test_from_existing_tree() {
+        let mut plain = SparseMerkleTree::new();
+        plain.insert(&[1u8; 16], b"value");
+        let mut tree = VersionedSmt::from_tree(plain.clone());
+        tree.insert(&[2u8; 16], b"value");
+
+        let proof = tree.generate_proof_at(&[1u8; 16], 0).unwrap();
+        assert_eq!(proof, plain.generate_proof(&[1u8; 16]));
+    }
+
+    #[test]
+    fn test_prune_before() {
+        let mut tree = VersionedSparseMerkleTree::new();
+        for round in 0..20u8 {
+            for i in 0..10u8 {
+                tree.insert(&[i; 16], &[round]);
+            }
+        }
+        tree.delete(&[9u8; 16]);
+        let recorded = |tree: &VersionedSparseMerkleTree| -> usize {
+            tree.history.values().map(Vec::len).sum()
+        };
+        let before = recorded(&tree);
+        let latest = tree.version();
+
+        let pruned = tree.prune_before(latest);
+        assert_eq!(recorded(&tree), before - pruned);
+        assert_eq!(recorded(&tree), tree.tree().store.nodes.len());
+        assert_eq!(tree.generate_proof_at(&[1u8; 16], latest - 1), None);
+        assert!(tree.root_at(latest - 1).is_some());
+        for key in [[1u8; 16], [9u8; 16]] {
+            assert_eq!(
+                tree.generate_proof_at(&key, latest),
+                Some(tree.tree().generate_proof(&key))
+            );
+        }
+
+        // Versions after the pruned ones keep their proofs.
+        tree.insert(&[1u8; 16], b"new");
+        let proof = tree.generate_proof_at(&[1u8; 16], latest).unwrap();
+        assert_eq!(
+            tree.tree()
+                .verifier()
+                .with_root(*tree.root_at(latest).unwrap())
+                .verify(&[1u8; 16], Some(&[19]), &proof),
+            Ok(true)
+        );
+        assert_eq!(tree.prune_before(0), 0);
+    }
+}
@@ -0,0 +1,214 @@
+use super::{bit, ct_eq, NodeStore, Smt, SmtError, SmtHasher, SmtProof, SmtVerifier, DEFAULT_LEAF};
+
+/// A proof laid out for an arithmetic circuit. Both arrays have one entry per
+/// level and run from the root down to the leaf: `path_bits[i]` is bit `i` of the
+/// path (the key read in the proof's bit order, MSB-first by default), `true` when the node at level `i + 1` is a
+/// right child, and `siblings[i]` is that node's sibling. This is the reverse of
+/// `SmtProof::siblings`, which runs from the leaf up.
+///
+/// `leaf` is the leaf hash, all zeros for an absent key. The root is recomputed by
+/// walking the arrays backwards, from the leaf up:
+///
+/// ```text
+/// node = leaf
+/// for i in (0..depth).rev():
+///     node = path_bits[i] ? hash_pair(siblings[i], node) : hash_pair(node, siblings[i])
+/// ```
+///
+/// with `hash_pair(l, r) = H(0x01 || l || r)` for the built-in hashers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtWitness {
+    pub siblings: Vec<[u8; 32]>,
+    pub path_bits: Vec<bool>,
+    pub leaf: [u8; 32],
+}
+
+impl<const N: usize, H: SmtHasher, S: NodeStore<N>> Smt<N, H, S> {
+    /// Lays out `proof` for `key` holding `value` (`None` for an absent key) as a
+    /// circuit witness, with the depth, flavor and bit order of `verifier`. For a
+    /// proof from `generate_proof_hashed`, pass the path from `hash_key` as `key`.
+    /// Fails like `SmtVerifier::verify` for malformed proofs or those from another
+    /// kind of tree, but doesn't check the proof against the verifier's root: use
+    /// `verify_witness` for that.
+    pub fn to_witness(
+        verifier: &SmtVerifier<N, H>,
+        key: &[u8; N],
+        value: Option<&[u8]>,
+        proof: &SmtProof,
+    ) -> Result<SmtWitness, SmtError> {
+        verifier.check_proof(proof)?;
+        let depth = verifier.depth();
+        Self::check_key(key, depth)?;
+        let path = verifier.bit_order().path(key, depth);
+        let leaf = value.map_or(DEFAULT_LEAF, |value| {
+            Self::leaf_hash(verifier.flavor(), &path, value)
+        });
+        Ok(SmtWitness {
+            siblings: proof.siblings.iter().rev().copied().collect(),
+            path_bits: (0..depth).map(|i| bit(&path, i)).collect(),
+            leaf,
+        })
+    }
+
+    /// Recomputes the root from `witness` the way a circuit would and compares it to
+    /// `root`. Fails with `InvalidProofLength` if the arrays differ in length.
+    pub fn verify_witness(root: &[u8; 32], witness: &SmtWitness) -> Result<bool, SmtError> {
+        if witness.siblings.len() != witness.path_bits.len() {
+            return Err(SmtError::InvalidProofLength {
+                expected: witness.path_bits.len(),
+                actual: witness.siblings.len(),
+            });
+        }
+        let mut node = witness.leaf;
+        for (sibling, &right) in witness.siblings.iter().zip(&witness.path_bits).rev() {
+            node = if right {
+                Self::hash_pair(sibling, &node)
+            } else {
+                Self::hash_pair(&node, sibling)
+            };
+        }
+        Ok(ct_eq(&node, root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SparseMerkleTree;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Depth-4 tree holding paths 0101, 0110 and 1100.
+    fn setup_tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::with_depth(4);
+        for (path, value) in [(0x50, b"a"), (0xc0, b"b"), (0x60, b"c")] {
+            tree.insert(&key(path), value);
+        }
+        tree
+    }
+
+    fn key(path: u8) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[0] = path;
+        key
+    }
+
+    #[test]
+    fn test_witness_vectors() {
+        let tree = setup_tree();
+        assert_eq!(
+            hex(&tree.root()),
+            "6b5e998d5554db417cbc5551deb0950c0425c81bbf1c1b2b77ef6d73c0f6875d"
+        );
+
+        let proof = tree.generate_proof(&key(0x50));
+        let witness =
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &key(0x50), Some(b"a"), &proof)
+                .unwrap();
+        assert_eq!(witness.path_bits, [false, true, false, true]);
+        assert_eq!(
+            witness.siblings.iter().map(|s| hex(s)).collect::<Vec<_>>(),
+            [
+                "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+                "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+                "df3fd2ae8d93e6b69ff2d93cb53960aa4be5ce914081811aa93fc7a36c314d5a",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            ]
+        );
+        assert_eq!(
+            hex(&witness.leaf),
+            "022a6979e6dab7aa5ae4c3e5e45f7e977112a7e63593820dbec1ec738a24f93c"
+        );
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &witness),
+            Ok(true)
+        );
+
+        // An absent key has the all-zero leaf.
+        let proof = tree.generate_proof(&key(0x70));
+        let witness =
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &key(0x70), None, &proof).unwrap();
+        assert_eq!(witness.path_bits, [false, true, true, true]);
+        assert_eq!(
+            witness.siblings.iter().map(|s| hex(s)).collect::<Vec<_>>(),
+            [
+                "87f9b62d69d0c9453664c5ec5d47d41854859f9a4626934de628370a67aeeb09",
+                "90534fe0aff6db9edb29eee74e78a386916a581c8e6465349493e1a6c87241e1",
+                "2c590bf6888fa760280c2fdb777540ba3059a1655934844ed5c79e7ec22eb449",
+                "597fcb31282d34654c200d3418fca5705c648ebf326ec73d8ddef11841f876d8",
+            ]
+        );
+        assert_eq!(witness.leaf, [0; 32]);
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &witness),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_full_depth_witness() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(&[0xa5; 16], b"value");
+        tree.insert(&[0x5a; 16], b"other");
+        let proof = tree.generate_proof(&[0xa5; 16]);
+        let witness =
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &[0xa5; 16], Some(b"value"), &proof)
+                .unwrap();
+
+        assert_eq!(witness.siblings.len(), 128);
+        assert_eq!(witness.path_bits.len(), 128);
+        assert_eq!(witness.siblings[0], tree.node(1, [0; 16]));
+        assert_eq!(witness.siblings[127], proof.siblings[0]);
+        assert_eq!(
+            &witness.path_bits[..8],
+            [true, false, true, false, false, true, false, true]
+        );
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &witness),
+            Ok(true)
+        );
+
+        let wrong_value =
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &[0xa5; 16], Some(b"other"), &proof)
+                .unwrap();
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &wrong_value),
+            Ok(false)
+        );
+        let mut flipped = witness.clone();
+        flipped.path_bits[100] ^= true;
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &flipped),
+            Ok(false)
+        );
+        let mut truncated = witness;
+        truncated.siblings.pop();
+        assert_eq!(
+            <SparseMerkleTree>::verify_witness(&tree.root(), &truncated),
+            Err(SmtError::InvalidProofLength {
+                expected: 128,
+                actual: 127
+            })
+        );
+    }
+
+    #[test]
+    fn test_witness_rejects_malformed_proofs() {
+        let tree = setup_tree();
+        let mut proof = tree.generate_proof(&key(0x50));
+        assert_eq!(
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &key(0x58), Some(b"a"), &proof),
+            Err(SmtError::KeyLength { depth: 4 })
+        );
+        proof.siblings.pop();
+        assert_eq!(
+            <SparseMerkleTree>::to_witness(&tree.verifier(), &key(0x50), Some(b"a"), &proof),
+            Err(SmtError::InvalidProofLength {
+                expected: 4,
+                actual: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "std")]
+
+use lil_merkle::merkle_tree::{bitcoin_merkle_root, MerkleKeccak256Hasher, Rfc6962Hasher};
+use lil_merkle::{MerkleError, MerkleProof, MerkleTree, PaddingPolicy};
+
+#[test]
+fn test_public_api_round_trip() {
+    let data = ["alice", "bob", "carol", "dave", "eve"];
+    let mut tree = MerkleTree::new(&data);
+    assert_eq!(tree.len(), 5);
+    assert_eq!(tree.root_hex().len(), 66);
+
+    for (index, item) in data.iter().enumerate() {
+        let leaf = <MerkleTree>::hash_leaf(item.as_bytes());
+        let proof = tree.generate_proof(index).unwrap();
+        assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &proof));
+        let decoded = MerkleProof::from_hex(&proof.to_hex()).unwrap();
+        assert!(<MerkleTree>::verify_proof(&tree.root(), &leaf, &decoded));
+    }
+
+    let before = tree.clone();
+    tree.update_leaf(1, b"mallory").unwrap();
+    assert_ne!(tree.root(), before.root());
+    assert_eq!(
+        tree.update_leaf(5, b"trent"),
+        Err(MerkleError::IndexOutOfRange { index: 5, len: 5 })
+    );
+    assert_eq!(
+        tree.generate_proof(5),
+        Err(MerkleError::IndexOutOfRange { index: 5, len: 5 })
+    );
+    let leaf = <MerkleTree>::hash_leaf(b"bob");
+    assert!(!<MerkleTree>::verify_proof(
+        &tree.root(),
+        &leaf,
+        &before.generate_proof(1).unwrap()
+    ));
+    assert!(format!("{before:?}").starts_with("MerkleTree"));
+}
+
+#[test]
+fn test_public_api_modes() {
+    let data = ["a", "b", "c"];
+    let keccak =
+        MerkleTree::<MerkleKeccak256Hasher>::with_padding(&data, PaddingPolicy::DuplicateLast);
+    let proof = keccak.generate_proof(2).unwrap();
+    let leaf = MerkleTree::<MerkleKeccak256Hasher>::hash_leaf(b"c");
+    assert!(MerkleTree::<MerkleKeccak256Hasher>::verify_proof(
+        &keccak.root(),
+        &leaf,
+        &proof
+    ));
+
+    let mut log = MerkleTree::rfc6962(&data[..2]);
+    let (old_root, old_size) = (log.root(), log.len());
+    log.push(b"c");
+    let consistency = log.generate_consistency_proof(old_size, log.len());
+    assert!(MerkleTree::<Rfc6962Hasher>::verify_consistency(
+        &old_root,
+        old_size,
+        &log.root(),
+        log.len(),
+        &consistency
+    ));
+
+    let txids = [[1; 32], [2; 32]];
+    assert_eq!(
+        bitcoin_merkle_root(&txids),
+        MerkleTree::bitcoin(&txids).root()
+    );
+}
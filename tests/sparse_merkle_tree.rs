@@ -0,0 +1,44 @@
+#![cfg(feature = "std")]
+
+use lil_merkle::{SparseMerkleTree, SparseMerkleTree256};
+
+#[test]
+fn test_public_api_round_trip() {
+    let mut tree = SparseMerkleTree::new();
+    let empty_root = tree.root();
+
+    let key = [7u8; 16];
+    tree.insert(&key, b"value");
+    assert_eq!(tree.get(&key), Some(b"value".to_vec()));
+    assert_ne!(tree.root(), empty_root);
+
+    let proof = tree.generate_proof(&key);
+    assert_eq!(tree.verify_proof(&key, Some(b"value"), &proof), Ok(true));
+
+    let absent = [8u8; 16];
+    let proof = tree.generate_proof(&absent);
+    assert_eq!(tree.verify_proof(&absent, None, &proof), Ok(true));
+
+    let compressed = tree.compress_proof(&proof);
+    assert_eq!(tree.verify_compressed(&absent, None, &compressed), Ok(true));
+
+    let snapshot = tree.clone();
+    tree.delete(&key);
+    assert_eq!(tree.root(), empty_root);
+    assert_eq!(
+        snapshot.verify_proof(&key, Some(b"value"), &snapshot.generate_proof(&key)),
+        Ok(true)
+    );
+}
+
+#[test]
+fn test_public_api_256_bit_keys() {
+    let mut tree = SparseMerkleTree256::new();
+    let key = [0xffu8; 32];
+    tree.insert(&key, b"value");
+    assert_eq!(tree.depth(), 256);
+    assert_eq!(
+        tree.verify_proof(&key, Some(b"value"), &tree.generate_proof(&key)),
+        Ok(true)
+    );
+}
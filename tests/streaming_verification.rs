@@ -0,0 +1,92 @@
+#![cfg(feature = "std")]
+
+use lil_merkle::{BitOrder, SmtFlavor, SparseMerkleTree};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// The system allocator, counting the allocations made on each thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Minimal xorshift so the keys are reproducible without a dependency.
+fn next(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[test]
+fn test_streaming_verification_matches_verify_proof_without_allocating() {
+    for (flavor, bit_order) in [
+        (SmtFlavor::Plain, BitOrder::MsbFirst),
+        (SmtFlavor::PathCommitting, BitOrder::MsbFirst),
+        (SmtFlavor::LengthPrefixed, BitOrder::LsbFirst),
+    ] {
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+        let mut tree = SparseMerkleTree::new()
+            .with_flavor(flavor)
+            .with_bit_order(bit_order);
+        let mut key = || {
+            let mut key = [0u8; 16];
+            key[..8].copy_from_slice(&next(&mut state).to_be_bytes());
+            key[8..].copy_from_slice(&next(&mut state).to_be_bytes());
+            key
+        };
+        let keys: Vec<_> = (0..200).map(|_| key()).collect();
+        for key in &keys[..150] {
+            tree.insert(key, &key[..3]);
+        }
+        // Each key's proof, tampered with for every fifth key.
+        let cases: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let mut proof = tree.generate_proof(key);
+                if i % 5 == 0 {
+                    proof.siblings[i % 128][0] ^= 1;
+                }
+                (key, tree.get(key), proof)
+            })
+            .collect();
+        let verifier = tree.verifier();
+
+        let before = allocations();
+        let streamed: Vec<bool> = cases
+            .iter()
+            .map(|(key, value, proof)| {
+                verifier.verify_proof_streaming(key, value.as_deref(), &proof.siblings)
+            })
+            .collect();
+        // One allocation for `streamed` itself.
+        assert_eq!(allocations() - before, 1);
+
+        let expected: Vec<bool> = cases
+            .iter()
+            .map(|(key, value, proof)| tree.verify_proof(key, value.as_deref(), proof) == Ok(true))
+            .collect();
+        assert_eq!(streamed, expected);
+        assert_eq!(expected.iter().filter(|&&valid| valid).count(), 160);
+    }
+}